@@ -0,0 +1,123 @@
+//! Assembler for hand-written assembunny programs. Besides plain instructions,
+//! it accepts line numbers, named labels as jump targets, comments (`;`) and
+//! loop art (`|`), like in the annotated listing of day 23.
+
+use std::collections::HashMap;
+use super::{Dialect, Instruction};
+
+
+/// Error while assembling a program. Line numbers start at 1.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Line contains an invalid instruction or label
+    InvalidInstruction(usize),
+    /// Reference to a label that isn't defined
+    UndefinedLabel(usize, String),
+    /// Definition of a label that has been defined before
+    DuplicateLabel(usize, String),
+}
+
+
+/// Check if the given name is a valid label name. Labels must not
+/// collide with register names of the dialect.
+fn is_label(name: &str, dialect: &Dialect) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(ch) if ch.is_alphabetic() || ch == '_' => (),
+        _ => return false,
+    }
+    chars.all(|ch| ch.is_alphanumeric() || ch == '_') &&
+        !(name.chars().count() == 1 && dialect.registers.contains(name))
+}
+
+/// Strip comments, loop art and a leading line number from a line
+fn strip_line(line: &str) -> &str {
+    let line = line.split(|ch| ch == ';' || ch == '|').next().unwrap().trim();
+    let number_len = line.find(char::is_whitespace).unwrap_or(line.len());
+    if line[..number_len].chars().all(|ch| ch.is_digit(10)) {
+        line[number_len..].trim()
+    } else {
+        line
+    }
+}
+
+/// Assemble a multiline-text to a vector of instructions of the given dialect.
+/// Jump targets of `jnz` can be given as label name, which is resolved to the
+/// relative offset the CPU expects.
+pub fn assemble(s: &str, dialect: &Dialect) -> Result<Vec<Instruction>, Error> {
+    // First pass: collect label addresses and instruction lines
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+    for (i, line) in s.lines().enumerate() {
+        let lineno = i + 1;
+        let mut line = strip_line(line);
+        while let Some(pos) = line.find(':') {
+            let label = line[..pos].trim();
+            if !is_label(label, dialect) {
+                return Err(Error::InvalidInstruction(lineno));
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(Error::DuplicateLabel(lineno, label.to_owned()));
+            }
+            line = line[pos+1..].trim();
+        }
+        if !line.is_empty() {
+            lines.push((lineno, line));
+        }
+    }
+    // Second pass: resolve label references and parse instructions
+    lines.iter().enumerate().map(|(ip, &(lineno, line))| {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let line = if words.len() == 3 && words[0] == "jnz" && is_label(words[2], dialect) {
+            match labels.get(words[2]) {
+                Some(&addr) => format!("jnz {} {}", words[1], addr as isize - ip as isize),
+                None => return Err(Error::UndefinedLabel(lineno, words[2].to_owned())),
+            }
+        } else {
+            line.to_owned()
+        };
+        Instruction::parse_line(&line, dialect).map_err(|_| Error::InvalidInstruction(lineno))
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Value;
+
+    #[test]
+    fn assembling() {
+        assert_eq!(assemble("01  cpy 2 a   ; a=2\n\nloop:\n02  dec a       | a--\n03  jnz a loop  |\n    jnz 1 end\n    inc b\nend:", &Dialect::default()), Ok(vec![
+            Instruction::Cpy(Value::Immediate(2), Value::Register(0)),
+            Instruction::Dec(0),
+            Instruction::Jnz(Value::Register(0), Value::Immediate(-1)),
+            Instruction::Jnz(Value::Immediate(1), Value::Immediate(2)),
+            Instruction::Inc(1),
+        ]));
+        assert_eq!(assemble("start: jnz 1 start", &Dialect::default()), Ok(vec![
+            Instruction::Jnz(Value::Immediate(1), Value::Immediate(0)),
+        ]));
+    }
+
+    #[test]
+    fn assembling_errors() {
+        assert_eq!(assemble("inc a\nfoo b", &Dialect::default()), Err(Error::InvalidInstruction(2)));
+        assert_eq!(assemble("inc a\njnz a loop", &Dialect::default()), Err(Error::UndefinedLabel(2, "loop".to_owned())));
+        assert_eq!(assemble("loop:\ninc a\nloop:\njnz a loop", &Dialect::default()), Err(Error::DuplicateLabel(3, "loop".to_owned())));
+        assert_eq!(assemble("a:\ninc a", &Dialect::default()), Err(Error::InvalidInstruction(1)));
+        assert_eq!(assemble("tgl a", &Dialect::day12()), Err(Error::InvalidInstruction(1)));
+    }
+
+    #[test]
+    fn assembling_registers() {
+        let dialect = Dialect::default().with_registers("xyzw");
+        assert_eq!(assemble("a:\ndec y\njnz x y\njnz y a", &dialect), Ok(vec![
+            Instruction::Dec(1),
+            Instruction::Jnz(Value::Register(0), Value::Register(1)),
+            Instruction::Jnz(Value::Register(1), Value::Immediate(-2)),
+        ]));
+        assert_eq!(assemble("x:\ninc x", &dialect), Err(Error::InvalidInstruction(1)));
+        assert_eq!(assemble("jnz x b", &dialect), Err(Error::UndefinedLabel(1, "b".to_owned())));
+    }
+}
@@ -0,0 +1,106 @@
+//! Clock signal detection. Runs a program and checks that its output is
+//! 0, 1, 0, 1, ... The complete machine state is recorded after every
+//! output. Once a state repeats, the program (being deterministic) repeats
+//! the outputs in between forever, which proves the signal without relying
+//! on the shape of the program.
+
+use std::collections::HashMap;
+use super::Cpu;
+use super::outcome::Fault;
+use super::word::Word;
+
+
+/// Result of checking a program for a clock signal
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Signal<W = i32> {
+    /// Output is 0, 1, 0, 1, ... forever. The machine state after the output
+    /// at index `start` repeats every `period` outputs.
+    Clock { start: usize, period: usize },
+    /// Output deviates from the pattern at the given index (which may be an
+    /// output that is proven to come, but wasn't produced yet)
+    Mismatch { index: usize, value: W },
+    /// Program halts, so the signal ends
+    Halted,
+    /// Program was stopped by a fault, so the signal ends
+    Fault(Fault),
+    /// Program waits for input, so the signal stalls
+    Waiting,
+    /// Not decided within the given number of steps
+    Undecided,
+}
+
+impl<W: Word> Cpu<W> {
+    /// Run the program until it is proven whether it produces a clock
+    /// signal, or the given number of steps is exceeded
+    pub fn clock_signal(&mut self, max_steps: usize) -> Signal<W> {
+        let mut states = HashMap::new();
+        let mut checked = self.output.len();
+        for _ in 0..max_steps {
+            if self.step() {
+                return match self.fault {
+                    Some(fault) => Signal::Fault(fault),
+                    None if self.is_waiting() => Signal::Waiting,
+                    None => Signal::Halted,
+                };
+            }
+            if self.output.len() == checked {
+                continue;
+            }
+            let index = checked;
+            let value = self.output[index];
+            checked += 1;
+            if value != W::from((index % 2) as i32) {
+                return Signal::Mismatch { index: index, value: value };
+            }
+            let state = (self.ip, self.regs.clone(), self.input.clone(), self.instructions.clone());
+            if let Some(&start) = states.get(&state) {
+                let period = index - start;
+                if period % 2 != 0 {
+                    // Output after the repeated state is the same, but the
+                    // expected value isn't
+                    return Signal::Mismatch { index: index + 1, value: self.output[start + 1] };
+                }
+                return Signal::Clock { start: start, period: period };
+            }
+            states.insert(state, index);
+        }
+        Signal::Undecided
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dialect;
+    use super::super::word::Overflow;
+
+    #[test]
+    fn detecting_signals() {
+        let mut cpu = Cpu::new("cpy 0 a\nout a\ninc a\nout a\ndec a\njnz 1 -4", &Dialect::day25()).unwrap();
+        assert_eq!(cpu.clock_signal(1000), Signal::Clock { start: 0, period: 2 });
+        let mut cpu = Cpu::new("cpy 5 b\nout 0\nout 1\ndec b\njnz b -3\ncpy 1 c\nout c\njnz 1 -2", &Dialect::day25()).unwrap();
+        assert_eq!(cpu.clock_signal(1000), Signal::Mismatch { index: 10, value: 1 });
+        let mut cpu = Cpu::new("out 0\nout 1\nout 0", &Dialect::day25()).unwrap();
+        assert_eq!(cpu.clock_signal(1000), Signal::Halted);
+        let mut cpu = Cpu::new("out 0\ninc a\njnz 1 -1", &Dialect::day25()).unwrap();
+        assert_eq!(cpu.clock_signal(1000), Signal::Undecided);
+    }
+
+    #[test]
+    fn detecting_stopped_signals() {
+        let mut cpu = Cpu::new("out 0\nout 1\njnz 1 -5", &Dialect::day25()).unwrap();
+        assert_eq!(cpu.clock_signal(1000), Signal::Fault(Fault::Jump { addr: 2, target: -3 }));
+        // Same registers after every input, but less input left
+        let mut cpu = Cpu::new("out 0\nout 1\ninp a\ncpy 0 a\njnz 1 -4", &Dialect::default()).unwrap();
+        cpu.input.extend(vec![7, 7]);
+        assert_eq!(cpu.clock_signal(1000), Signal::Waiting);
+        assert_eq!(cpu.output, vec![0, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn detecting_wide_signals() {
+        let mut cpu: Cpu<i64> = Cpu::with_overflow("cpy 4294967296 c\nout 0\nout 1\njnz c -2", &Dialect::day25(), Overflow::Wrap).unwrap();
+        assert_eq!(cpu.clock_signal(1000), Signal::Clock { start: 0, period: 2 });
+    }
+}
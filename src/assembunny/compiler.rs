@@ -0,0 +1,256 @@
+//! Compiled execution of assembunny programs. Instead of decoding every
+//! instruction on each step, a program is translated into basic blocks
+//! once, which are then run directly. Blocks are compiled on demand when
+//! first entered, and are recompiled if a `tgl` instruction modifies any
+//! instruction they were compiled from. Optimizable loops always start a
+//! new block, so they can be run in a single step when entered.
+
+use std::collections::HashMap;
+use super::{offset, Cpu, Instruction, Value};
+use super::outcome::Fault;
+use super::word::{Overflow, OverflowError, Word};
+
+
+/// Straight-line operation within a basic block
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Op {
+    /// Set register to an immediate value
+    Set(u8, i64),
+    /// Copy register to another register
+    Copy(u8, u8),
+    /// Add an immediate value to a register (`inc`/`dec` instructions at
+    /// the given address, folded if overflow wraps around)
+    Add(u8, i32, usize),
+    /// Output a value
+    Out(Value),
+}
+
+
+/// Exit of a basic block
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Exit {
+    /// Continue at the given address
+    Goto(usize),
+    /// Conditional jump of the `jnz` instruction at the given address
+    Jnz(usize, Value, Value),
+    /// Toggle instruction of the `tgl` instruction at the given address
+    Tgl(usize, u8),
+    /// Read input of the `inp` instruction at the given address
+    Inp(usize, u8),
+}
+
+
+/// A basic block: a sequence of straight-line operations followed by an exit
+#[derive(Debug, PartialEq, Eq)]
+struct Block {
+    /// Address of first instruction
+    start: usize,
+    /// Address of last instruction
+    end: usize,
+    /// Operations
+    ops: Vec<Op>,
+    /// Exit
+    exit: Exit,
+}
+
+impl Block {
+    /// Compile the basic block starting at the given address
+    fn compile<W: Word>(cpu: &Cpu<W>, start: usize) -> Block {
+        let mut ops: Vec<Op> = Vec::new();
+        let mut ip = start;
+        let exit = loop {
+            if ip >= cpu.instructions.len() || (ip > start && cpu.loops[ip].is_some()) {
+                break Exit::Goto(ip);
+            }
+            let op = match cpu.instructions[ip] {
+                Instruction::Cpy(_, Value::Immediate(_)) => None,
+                Instruction::Cpy(Value::Immediate(x), Value::Register(y)) => Some(Op::Set(y, x)),
+                Instruction::Cpy(Value::Register(x), Value::Register(y)) => Some(Op::Copy(y, x)),
+                Instruction::Inc(x) => Some(Op::Add(x, 1, ip)),
+                Instruction::Dec(x) => Some(Op::Add(x, -1, ip)),
+                Instruction::Out(v) => Some(Op::Out(v)),
+                Instruction::Jnz(v1, v2) => break Exit::Jnz(ip, v1, v2),
+                Instruction::Tgl(x) => break Exit::Tgl(ip, x),
+                Instruction::Inp(x) => break Exit::Inp(ip, x),
+            };
+            match (ops.last_mut(), op) {
+                (Some(&mut Op::Add(x, ref mut n, _)), Some(Op::Add(y, m, _))) if x == y && cpu.overflow == Overflow::Wrap => *n += m,
+                (_, Some(op)) => ops.push(op),
+                (_, None) => (),
+            }
+            ip += 1;
+        };
+        let end = match exit {
+            Exit::Goto(_) => ip - 1,
+            _ => ip,
+        };
+        Block { start: start, end: end, ops: ops, exit: exit }
+    }
+
+    /// Check if the block needs to be recompiled after the instruction at the
+    /// given address has been modified (either because the block contains
+    /// it, or because the block now contains the start of an optimizable loop)
+    fn is_stale<W: Word>(&self, cpu: &Cpu<W>, addr: usize) -> bool {
+        (self.start <= addr && addr <= self.end) ||
+            (self.start + 1..self.end + 1).any(|ip| cpu.loops[ip].is_some())
+    }
+}
+
+
+/// Compiled basic blocks of a program, indexed by start address. Blocks
+/// must only be used with the CPU they were compiled for, and the program
+/// must not be modified by other means (e.g. stepping the CPU) in between.
+#[derive(Debug, Default)]
+pub struct Blocks {
+    blocks: HashMap<usize, Block>,
+}
+
+impl Blocks {
+    /// Discard all blocks that are stale after the instruction at the given
+    /// address has been modified
+    fn invalidate<W: Word>(&mut self, cpu: &Cpu<W>, addr: usize) {
+        let stale: Vec<usize> = self.blocks.values().filter(|block| block.is_stale(cpu, addr)).map(|block| block.start).collect();
+        for start in stale {
+            self.blocks.remove(&start);
+        }
+    }
+}
+
+
+impl<W: Word> Cpu<W> {
+    /// Run program using compiled basic blocks
+    pub fn run_compiled(&mut self) {
+        self.run_blocks(&mut Blocks::default());
+    }
+
+    /// Run program using the given compiled basic blocks, compiling
+    /// missing blocks as needed. Blocks can't be traced or profiled, so the
+    /// program is interpreted while a trace or profile is being recorded.
+    pub fn run_blocks(&mut self, blocks: &mut Blocks) {
+        if self.trace.is_some() || self.profile.is_some() {
+            return self.run();
+        }
+        while !self.is_done() {
+            if self.run_loop() {
+                continue;
+            }
+            let ip = self.ip;
+            if !blocks.blocks.contains_key(&ip) {
+                let block = Block::compile(self, ip);
+                blocks.blocks.insert(ip, block);
+            }
+            let exit = {
+                let block = &blocks.blocks[&ip];
+                for op in &block.ops {
+                    match *op {
+                        Op::Set(x, v) => self.regs[x as usize] = W::truncate(v as i128),
+                        Op::Copy(x, y) => self.regs[x as usize] = self.regs[y as usize],
+                        Op::Add(x, n, addr) => match self.overflow.add(self.regs[x as usize], n as i128) {
+                            Some(value) => self.regs[x as usize] = value,
+                            None => {
+                                self.fault = Some(Fault::Overflow(OverflowError { addr: addr, register: x, name: self.register_name(x) }));
+                                self.ip = addr;
+                                return;
+                            },
+                        },
+                        Op::Out(v) => self.output.push(v.get(&self.regs)),
+                    }
+                }
+                block.exit
+            };
+            match exit {
+                Exit::Goto(addr) => self.ip = addr,
+                Exit::Jnz(ip, v1, v2) => {
+                    if v1.get(&self.regs) != W::default() {
+                        match offset(ip, v2.get(&self.regs).into()) {
+                            Ok(addr) => self.ip = addr,
+                            Err(target) => {
+                                self.fault = Some(Fault::Jump { addr: ip, target: target });
+                                self.ip = ip;
+                            },
+                        }
+                    } else {
+                        self.ip = ip + 1;
+                    }
+                },
+                Exit::Tgl(ip, x) => match offset(ip, self.regs[x as usize].into()) {
+                    Ok(addr) => {
+                        if self.toggle(addr) {
+                            blocks.invalidate(self, addr);
+                        }
+                        self.ip = ip + 1;
+                    },
+                    Err(target) => {
+                        self.fault = Some(Fault::Toggle { addr: ip, target: target });
+                        self.ip = ip;
+                    },
+                },
+                Exit::Inp(ip, x) => match self.input.pop_front() {
+                    Some(value) => {
+                        self.regs[x as usize] = value;
+                        self.ip = ip + 1;
+                    },
+                    // Wait for input
+                    None => self.ip = ip,
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Cpu, Dialect};
+    use super::super::word::Overflow;
+
+    /// Run a program interpreted and compiled and check that both result in the same state
+    fn check(program: &str, a: i32) -> Cpu {
+        let mut cpu1 = Cpu::new(program, &Dialect::default()).unwrap();
+        let mut cpu2 = Cpu::new(program, &Dialect::default()).unwrap();
+        cpu1.regs[0] = a;
+        cpu2.regs[0] = a;
+        cpu1.run();
+        cpu2.run_compiled();
+        assert_eq!(cpu1, cpu2);
+        cpu2
+    }
+
+    #[test]
+    fn running() {
+        assert_eq!(check("cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a", 0).regs[0], 42);
+        assert_eq!(check("cpy 2 a\nout a\ndec a\njnz a -2\nout 7", 0).output, vec![2, 1, 7]);
+        assert_eq!(check("cpy 5 b\ncpy 6 c\ncpy c d\ninc a\ndec d\njnz d -2\ndec b\njnz b -5", 0).regs, [30, 0, 6, 0]);
+        assert_eq!(check("cpy 7 b\ninc a\ndec b\njnz b -2\ncpy 3 b\njnz 1 b", 0).regs, [7, 3, 0, 0]);
+    }
+
+    #[test]
+    fn running_toggled() {
+        assert_eq!(check("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a", 0).regs[0], 3);
+        // loop body is toggled on every iteration and needs to be recompiled
+        assert_eq!(check("cpy 2 d\ncpy 1 c\ntgl c\ninc b\ninc a\ndec d\njnz d -4", 0).regs, [2, 0, 1, 0]);
+    }
+
+    #[test]
+    fn running_overflow() {
+        for &overflow in &[Overflow::Wrap, Overflow::Saturate, Overflow::Trap] {
+            let program = "cpy 2147483646 a\ninc a\ninc a\ndec a\ninc b";
+            let mut cpu1: Cpu<i32> = Cpu::with_overflow(program, &Dialect::default(), overflow).unwrap();
+            let mut cpu2: Cpu<i32> = Cpu::with_overflow(program, &Dialect::default(), overflow).unwrap();
+            cpu1.run();
+            cpu2.run_compiled();
+            assert_eq!(cpu1, cpu2);
+        }
+    }
+
+    #[test]
+    fn running_input() {
+        let mut cpu = Cpu::new("inp a\ninp b\ninc a\nout a\ndec b\njnz b -3", &Dialect::default()).unwrap();
+        cpu.input.push_back(10);
+        cpu.run_compiled();
+        assert_eq!((cpu.ip, cpu.is_waiting()), (1, true));
+        cpu.input.push_back(3);
+        cpu.run_compiled();
+        assert_eq!(cpu.output, vec![11, 12, 13]);
+    }
+}
@@ -0,0 +1,531 @@
+//! Interactive step debugger for assembunny programs. Execution can be
+//! paused at breakpoints (by address or by a condition on a register) and
+//! when a watched register changes. While paused, registers can be
+//! inspected and modified, and the program can be stepped one instruction
+//! or one loop iteration at a time.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use super::{Cpu, Instruction, Value, named_register};
+
+
+/// Parse the name of one of the registers of a CPU
+fn parse_register(s: &str, cpu: &Cpu) -> Result<u8, &'static str> {
+    (0..cpu.regs.len() as u8).find(|&x| s.chars().eq(Some(cpu.register_name(x)))).ok_or("Invalid register")
+}
+
+/// Parse an immediate value or register of a CPU
+fn parse_value(s: &str, cpu: &Cpu) -> Result<Value, &'static str> {
+    match s.parse() {
+        Ok(x) => Ok(Value::Immediate(x)),
+        Err(_) => parse_register(s, cpu).map(Value::Register).map_err(|_| "Invalid value"),
+    }
+}
+
+
+/// Comparison operator of a conditional breakpoint
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }.fmt(f)
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Comparison, &'static str> {
+        match s {
+            "==" => Ok(Comparison::Eq),
+            "!=" => Ok(Comparison::Ne),
+            "<" => Ok(Comparison::Lt),
+            "<=" => Ok(Comparison::Le),
+            ">" => Ok(Comparison::Gt),
+            ">=" => Ok(Comparison::Ge),
+            _ => Err("Invalid comparison"),
+        }
+    }
+}
+
+impl Comparison {
+    /// Compare two values
+    fn test(&self, x: i32, y: i32) -> bool {
+        match *self {
+            Comparison::Eq => x == y,
+            Comparison::Ne => x != y,
+            Comparison::Lt => x < y,
+            Comparison::Le => x <= y,
+            Comparison::Gt => x > y,
+            Comparison::Ge => x >= y,
+        }
+    }
+}
+
+
+/// A breakpoint
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Breakpoint {
+    /// Break before the instruction at the given address is run. Addresses
+    /// are parsed and shown as line numbers, which start at 1.
+    Address(usize),
+    /// Break as soon as the condition on a register becomes true
+    Condition(u8, Comparison, Value),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Standard register names
+        self.show("").fmt(f)
+    }
+}
+
+impl Breakpoint {
+    /// Parse a breakpoint on the registers of a CPU
+    pub fn parse(s: &str, cpu: &Cpu) -> Result<Breakpoint, &'static str> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.len() {
+            1 => match words[0].parse::<usize>() {
+                Ok(line) if line > 0 => Ok(Breakpoint::Address(line - 1)),
+                _ => Err("Invalid breakpoint line"),
+            },
+            3 => Ok(Breakpoint::Condition(parse_register(words[0], cpu)?, words[1].parse()?, parse_value(words[2], cpu)?)),
+            _ => Err("Invalid breakpoint format"),
+        }
+    }
+
+    /// Show breakpoint, using the given register names
+    pub fn show(&self, names: &str) -> String {
+        match *self {
+            Breakpoint::Address(addr) => format!("at line {}", addr + 1),
+            Breakpoint::Condition(x, cmp, v) => format!("when {} {} {}", named_register(names, x), cmp, v.show(names)),
+        }
+    }
+
+    /// Check if the breakpoint condition is true
+    fn test(&self, cpu: &Cpu) -> bool {
+        match *self {
+            Breakpoint::Address(addr) => cpu.ip == addr,
+            Breakpoint::Condition(x, cmp, v) => cmp.test(cpu.regs[x as usize], v.get(&cpu.regs)),
+        }
+    }
+}
+
+
+/// Reason why the debugger paused execution
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Event {
+    /// Stepping finished
+    Paused,
+    /// Program finished
+    Done,
+    /// Breakpoint with the given number was hit
+    Breakpoint(usize),
+    /// Watched register changed from the first to the second value
+    Watch(u8, i32, i32),
+}
+
+
+/// A debugger command
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Command {
+    /// Step the given number of instructions
+    Step(usize),
+    /// Step one iteration of the innermost loop
+    Next,
+    /// Continue until a breakpoint or watch triggers
+    Continue,
+    /// Add a breakpoint
+    Break(Breakpoint),
+    /// Remove the breakpoint with the given number
+    Delete(usize),
+    /// Watch a register for changes
+    Watch(u8),
+    /// Set a register to a value
+    Set(u8, i32),
+    /// Show registers
+    Registers,
+    /// Show instructions around the current address
+    List,
+    /// Show breakpoints and watches
+    Info,
+    /// Quit the debugger
+    Quit,
+}
+
+impl Command {
+    /// Parse a command for debugging a CPU
+    pub fn parse(s: &str, cpu: &Cpu) -> Result<Command, &'static str> {
+        let s = s.trim();
+        let (cmd, args) = match s.find(' ') {
+            Some(i) => (&s[..i], s[i + 1..].trim()),
+            None => (s, ""),
+        };
+        match (cmd, args) {
+            ("s", "") | ("step", "") => Ok(Command::Step(1)),
+            ("s", n) | ("step", n) => n.parse().map(Command::Step).map_err(|_| "Invalid step count"),
+            ("n", "") | ("next", "") => Ok(Command::Next),
+            ("c", "") | ("continue", "") => Ok(Command::Continue),
+            ("b", bp) | ("break", bp) => Breakpoint::parse(bp, cpu).map(Command::Break),
+            ("d", n) | ("delete", n) => n.parse().map(Command::Delete).map_err(|_| "Invalid breakpoint number"),
+            ("w", x) | ("watch", x) => parse_register(x, cpu).map(Command::Watch),
+            ("set", args) => {
+                let words: Vec<&str> = args.split_whitespace().collect();
+                if words.len() != 2 { return Err("Invalid set format"); }
+                Ok(Command::Set(parse_register(words[0], cpu)?, words[1].parse().map_err(|_| "Invalid register value")?))
+            },
+            ("r", "") | ("regs", "") => Ok(Command::Registers),
+            ("l", "") | ("list", "") => Ok(Command::List),
+            ("i", "") | ("info", "") => Ok(Command::Info),
+            ("q", "") | ("quit", "") => Ok(Command::Quit),
+            _ => Err("Unknown command"),
+        }
+    }
+}
+
+
+/// Debugger for an assembunny CPU
+#[derive(Debug)]
+pub struct Debugger {
+    pub cpu: Cpu,
+    /// Breakpoints (removed breakpoints are kept as `None` to keep numbering stable)
+    breakpoints: Vec<Option<Breakpoint>>,
+    /// Watched registers with their last seen value
+    watches: Vec<(u8, i32)>,
+}
+
+impl From<Cpu> for Debugger {
+    fn from(cpu: Cpu) -> Debugger {
+        Debugger { cpu: cpu, breakpoints: vec![], watches: vec![] }
+    }
+}
+
+impl Debugger {
+    /// Add a breakpoint. Returns the number of the breakpoint
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) -> usize {
+        self.breakpoints.push(Some(bp));
+        self.breakpoints.len() - 1
+    }
+
+    /// Remove the breakpoint with the given number. Returns true if it existed
+    pub fn remove_breakpoint(&mut self, n: usize) -> bool {
+        match self.breakpoints.get_mut(n) {
+            Some(bp) => bp.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Watch a register for changes
+    pub fn watch(&mut self, x: u8) {
+        if !self.watches.iter().any(|&(y, _)| x == y) {
+            self.watches.push((x, self.cpu.regs[x as usize]));
+        }
+    }
+
+    /// Set a register to a value
+    pub fn set(&mut self, x: u8, value: i32) {
+        self.cpu.regs[x as usize] = value;
+        for watch in self.watches.iter_mut().filter(|&&mut (y, _)| x == y) {
+            watch.1 = value;
+        }
+    }
+
+    /// Returns true if the optimized loop at the current address can be run
+    /// without missing a breakpoint or watch. Conditional breakpoints could be
+    /// missed by any shortcut, address breakpoints only if they are inside the
+    /// loop and watches only if the loop modifies the watched register.
+    fn can_run_loop(&self) -> bool {
+        match self.cpu.loops.get(self.cpu.ip) {
+            Some(&Some(ref l)) => self.breakpoints.iter().all(|bp| match *bp {
+                Some(Breakpoint::Address(addr)) => addr < l.start || addr > l.end,
+                Some(Breakpoint::Condition(..)) => false,
+                None => true,
+            }) && match l.apply(&self.cpu.regs, self.cpu.overflow) {
+                Some(regs) => self.watches.iter().all(|&(x, value)| regs[x as usize] == value),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Run a single step and check breakpoints and watches afterwards
+    fn single_step(&mut self, shortcut: bool) -> Option<Event> {
+        let before: Vec<bool> = self.breakpoints.iter().map(|bp| bp.map(|bp| bp.test(&self.cpu)).unwrap_or(false)).collect();
+        if shortcut && self.can_run_loop() {
+            self.cpu.step();
+        } else {
+            self.cpu.step_instruction();
+        }
+        for watch in self.watches.iter_mut() {
+            let value = self.cpu.regs[watch.0 as usize];
+            if value != watch.1 {
+                let event = Event::Watch(watch.0, watch.1, value);
+                watch.1 = value;
+                return Some(event);
+            }
+        }
+        for (n, bp) in self.breakpoints.iter().enumerate() {
+            match *bp {
+                Some(bp @ Breakpoint::Address(_)) if bp.test(&self.cpu) => return Some(Event::Breakpoint(n)),
+                // Conditional breakpoints only trigger when the condition becomes true
+                Some(bp @ Breakpoint::Condition(..)) if !before[n] && bp.test(&self.cpu) => return Some(Event::Breakpoint(n)),
+                _ => (),
+            }
+        }
+        if self.cpu.is_done() { Some(Event::Done) } else { None }
+    }
+
+    /// Step the given number of instructions
+    pub fn step(&mut self, count: usize) -> Event {
+        if self.cpu.is_done() { return Event::Done; }
+        for _ in 0..count {
+            if let Some(event) = self.single_step(false) {
+                return event;
+            }
+        }
+        Event::Paused
+    }
+
+    /// Address range of the innermost loop (backward jump) around the current address
+    fn current_loop(&self) -> Option<(usize, usize)> {
+        let ip = self.cpu.ip;
+        self.cpu.instructions.iter().enumerate().skip(ip).filter_map(|(addr, ins)| match *ins {
+            Instruction::Jnz(_, Value::Immediate(offset)) if offset <= 0 && addr as i64 + offset <= ip as i64 =>
+                Some(((addr as i64 + offset) as usize, addr)),
+            _ => None,
+        }).min_by_key(|&(start, end)| end - start)
+    }
+
+    /// Step until the innermost loop around the current address gets back
+    /// to its first instruction or is left. Steps a single instruction if
+    /// not inside a loop.
+    pub fn next(&mut self) -> Event {
+        if self.cpu.is_done() { return Event::Done; }
+        match self.current_loop() {
+            Some((start, end)) => loop {
+                if let Some(event) = self.single_step(false) {
+                    return event;
+                }
+                if self.cpu.ip == start || self.cpu.ip < start || self.cpu.ip > end {
+                    return Event::Paused;
+                }
+            },
+            None => self.step(1),
+        }
+    }
+
+    /// Continue running until a breakpoint or watch triggers or the program finishes
+    pub fn cont(&mut self) -> Event {
+        if self.cpu.is_done() { return Event::Done; }
+        loop {
+            if let Some(event) = self.single_step(true) {
+                return event;
+            }
+        }
+    }
+
+    /// Registers as text
+    pub fn registers(&self) -> String {
+        let regs: Vec<String> = self.cpu.regs.iter().enumerate().map(|(x, value)| format!("{}={}", self.cpu.register_name(x as u8), value)).collect();
+        format!("ip={} {}", self.cpu.ip, regs.join(" "))
+    }
+
+    /// Instructions around the current address as text, with line numbers. The
+    /// current address is marked with an arrow, addresses with a breakpoint are
+    /// marked with an asterisk.
+    pub fn listing(&self, context: usize) -> String {
+        let ip = self.cpu.ip;
+        let start = if ip > context { ip - context } else { 0 };
+        let mut s = String::new();
+        for (addr, ins) in self.cpu.instructions.iter().enumerate().skip(start).take(ip + context + 1 - start) {
+            let bp = self.breakpoints.iter().any(|bp| *bp == Some(Breakpoint::Address(addr)));
+            s.push_str(&format!("{}{} {:3}  {}\n", if addr == ip { "=>" } else { "  " }, if bp { "*" } else { " " }, addr + 1, ins.show(&self.cpu.names)));
+        }
+        if ip >= self.cpu.instructions.len() {
+            s.push_str(&format!("=>  {:3}  (end)\n", ip + 1));
+        }
+        s
+    }
+
+    /// Describe an event as text
+    fn describe(&self, event: Event) -> String {
+        match event {
+            Event::Paused => format!("{}\n", self.registers()),
+            Event::Done => format!("Program finished\n{}\n", self.registers()),
+            Event::Breakpoint(n) => format!("Breakpoint {} {}\n{}\n", n, self.breakpoints[n].unwrap().show(&self.cpu.names), self.registers()),
+            Event::Watch(x, old, new) => format!("Register {} changed from {} to {}\n{}\n", self.cpu.register_name(x), old, new, self.registers()),
+        }
+    }
+
+    /// Execute a debugger command. Returns the text to show, or None to quit
+    pub fn execute(&mut self, command: Command) -> Option<String> {
+        Some(match command {
+            Command::Step(n) => { let event = self.step(n); self.describe(event) },
+            Command::Next => { let event = self.next(); self.describe(event) },
+            Command::Continue => { let event = self.cont(); self.describe(event) },
+            Command::Break(bp) => format!("Breakpoint {} {}\n", self.add_breakpoint(bp), bp.show(&self.cpu.names)),
+            Command::Delete(n) if self.remove_breakpoint(n) => format!("Deleted breakpoint {}\n", n),
+            Command::Delete(n) => format!("No breakpoint {}\n", n),
+            Command::Watch(x) => { self.watch(x); format!("Watching register {}\n", self.cpu.register_name(x)) },
+            Command::Set(x, value) => { self.set(x, value); format!("{}\n", self.registers()) },
+            Command::Registers => format!("{}\n", self.registers()),
+            Command::List => self.listing(5),
+            Command::Info => {
+                let mut s = String::new();
+                for (n, bp) in self.breakpoints.iter().enumerate() {
+                    if let Some(bp) = *bp {
+                        s.push_str(&format!("Breakpoint {} {}\n", n, bp.show(&self.cpu.names)));
+                    }
+                }
+                for &(x, _) in &self.watches {
+                    s.push_str(&format!("Watching register {}\n", self.cpu.register_name(x)));
+                }
+                s
+            },
+            Command::Quit => return None,
+        })
+    }
+
+    /// Run an interactive debugger session, reading commands from the given
+    /// input and writing to the given output. An empty line repeats the last
+    /// command.
+    pub fn interact<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let mut last = None;
+        write!(output, "{}(dbg) ", self.listing(2))?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let command = if line.trim().is_empty() { last.ok_or("No previous command") } else { Command::parse(&line, &self.cpu) };
+            match command {
+                Ok(command) => match self.execute(command) {
+                    Some(s) => { last = Some(command); write!(output, "{}", s)? },
+                    None => return Ok(()),
+                },
+                Err(err) => writeln!(output, "{}", err)?,
+            }
+            write!(output, "(dbg) ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dialect;
+
+    fn debugger() -> Debugger {
+        Debugger::from(Cpu::new("cpy 3 a\ncpy 4 b\ninc c\ndec b\njnz b -2\ndec a\njnz a -5\nout c", &Dialect::default()).unwrap())
+    }
+
+    #[test]
+    fn parsing_commands() {
+        let cpu = debugger().cpu;
+        assert_eq!(Command::parse("s", &cpu), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 10", &cpu), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("b 4", &cpu), Ok(Command::Break(Breakpoint::Address(3))));
+        assert_eq!(Command::parse("b 0", &cpu), Err("Invalid breakpoint line"));
+        assert_eq!(Command::parse("break c >= 5", &cpu), Ok(Command::Break(Breakpoint::Condition(2, Comparison::Ge, Value::Immediate(5)))));
+        assert_eq!(Command::parse("break a == b", &cpu), Ok(Command::Break(Breakpoint::Condition(0, Comparison::Eq, Value::Register(1)))));
+        assert_eq!(Command::parse("set d -3", &cpu), Ok(Command::Set(3, -3)));
+        assert_eq!(Command::parse("watch e", &cpu), Err("Invalid register"));
+        assert_eq!(Command::parse("jump", &cpu), Err("Unknown command"));
+    }
+
+    #[test]
+    fn parsing_named_registers() {
+        let cpu = Cpu::new("inc e\ninc f", &Dialect::default().with_registers("abcdef")).unwrap();
+        assert_eq!(Command::parse("set e 5", &cpu), Ok(Command::Set(4, 5)));
+        assert_eq!(Command::parse("watch e", &cpu), Ok(Command::Watch(4)));
+        assert_eq!(Command::parse("watch g", &cpu), Err("Invalid register"));
+        let mut dbg = Debugger::from(Cpu::new("cpy 3 y\ninc x\ndec y\njnz y -2", &Dialect::default().with_registers("xyzw")).unwrap());
+        let bp = Command::parse("break y < x", &dbg.cpu).unwrap();
+        assert_eq!(bp, Command::Break(Breakpoint::Condition(1, Comparison::Lt, Value::Register(0))));
+        assert_eq!(Command::parse("watch a", &dbg.cpu), Err("Invalid register"));
+        assert_eq!(dbg.execute(bp), Some("Breakpoint 0 when y < x\n".to_owned()));
+        assert_eq!(dbg.execute(Command::Continue), Some("Breakpoint 0 when y < x\nip=3 x=2 y=1 z=0 w=0\n".to_owned()));
+        assert_eq!(dbg.execute(Command::List), Some("      1  cpy 3 y\n      2  inc x\n      3  dec y\n=>    4  jnz y -2\n".to_owned()));
+    }
+
+    #[test]
+    fn stepping() {
+        let mut dbg = debugger();
+        assert_eq!(dbg.step(3), Event::Paused);
+        assert_eq!(dbg.cpu.ip, 3);
+        assert_eq!(dbg.next(), Event::Paused);
+        assert_eq!((dbg.cpu.ip, dbg.cpu.regs.clone()), (2, vec![3, 3, 1, 0]));
+        assert_eq!(dbg.next(), Event::Paused);
+        assert_eq!((dbg.cpu.ip, dbg.cpu.regs.clone()), (2, vec![3, 2, 2, 0]));
+        assert_eq!(dbg.cont(), Event::Done);
+        assert_eq!(dbg.cpu.output, vec![12]);
+    }
+
+    #[test]
+    fn breaking() {
+        let mut dbg = debugger();
+        assert_eq!(dbg.add_breakpoint(Breakpoint::Address(5)), 0);
+        assert_eq!(dbg.add_breakpoint(Breakpoint::parse("c == 6", &dbg.cpu).unwrap()), 1);
+        assert_eq!(dbg.cont(), Event::Breakpoint(0));
+        assert_eq!(dbg.cpu.regs, [3, 0, 4, 0]);
+        assert_eq!(dbg.cont(), Event::Breakpoint(1));
+        assert_eq!((dbg.cpu.ip, dbg.cpu.regs.clone()), (3, vec![2, 3, 6, 0]));
+        assert!(dbg.remove_breakpoint(1));
+        dbg.set(0, 1);
+        assert_eq!(dbg.cont(), Event::Breakpoint(0));
+        assert_eq!(dbg.cpu.regs, [1, 0, 8, 0]);
+        assert_eq!(dbg.cont(), Event::Done);
+        assert_eq!(dbg.cpu.output, vec![8]);
+    }
+
+    #[test]
+    fn watching() {
+        let mut dbg = debugger();
+        dbg.watch(0);
+        assert_eq!(dbg.cont(), Event::Watch(0, 0, 3));
+        assert_eq!(dbg.cont(), Event::Watch(0, 3, 2));
+        assert_eq!(dbg.cpu.regs, [2, 0, 4, 0]);
+    }
+
+    #[test]
+    fn interacting() {
+        let mut dbg = debugger();
+        let mut output = Vec::new();
+        dbg.interact("b 7\nc\n\nl\ni\nq\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+=>    1  cpy 3 a
+      2  cpy 4 b
+      3  inc c
+(dbg) Breakpoint 0 at line 7
+(dbg) Breakpoint 0 at line 7
+ip=6 a=2 b=0 c=4 d=0
+(dbg) Breakpoint 0 at line 7
+ip=6 a=1 b=0 c=8 d=0
+(dbg)       2  cpy 4 b
+      3  inc c
+      4  dec b
+      5  jnz b -2
+      6  dec a
+=>*   7  jnz a -5
+      8  out c
+(dbg) Breakpoint 0 at line 7
+(dbg) ");
+    }
+}
@@ -0,0 +1,348 @@
+//! Differential checking of the loop optimizer. A program is run by a plain
+//! reference interpreter (without shortcuts) and by the optimized
+//! interpreter side by side. Their states are compared whenever both have
+//! reached the same point: after every single instruction, and after every
+//! loop that the optimized interpreter ran in a single step. Programs can
+//! be generated randomly to check the optimizer with code nobody wrote.
+
+use std::fmt;
+use super::{Cpu, Instruction, Value};
+use super::outcome::Fault;
+use super::word::Word;
+
+
+/// Difference between the state of the plain (first) and the optimized
+/// (second) interpreter
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Difference<W> {
+    /// Different faults (or a fault in only one of them)
+    Fault(Option<Fault>, Option<Fault>),
+    /// Different instruction pointers
+    Address(usize, usize),
+    /// Different register values
+    Registers(Vec<W>, Vec<W>),
+    /// Different output
+    Output(Vec<W>, Vec<W>),
+    /// Different instructions at the given address (toggled differently)
+    Program(usize, Instruction, Instruction),
+}
+
+impl<W: Word> fmt::Display for Difference<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn fault(fault: &Option<Fault>) -> String {
+            fault.map(|fault| fault.to_string()).unwrap_or_else(|| "none".to_owned())
+        }
+        match *self {
+            Difference::Fault(ref x, ref y) => write!(f, "fault {} vs. {}", fault(x), fault(y)),
+            Difference::Address(x, y) => write!(f, "at line {} vs. {}", x + 1, y + 1),
+            Difference::Registers(ref x, ref y) => write!(f, "registers {:?} vs. {:?}", x, y),
+            Difference::Output(ref x, ref y) => write!(f, "output {:?} vs. {:?}", x, y),
+            Difference::Program(addr, x, y) => write!(f, "line {} is `{}` vs. `{}`", addr + 1, x, y),
+        }
+    }
+}
+
+impl<W: Word> Difference<W> {
+    /// First difference between the states of two CPUs
+    fn between(plain: &Cpu<W>, optimized: &Cpu<W>) -> Option<Difference<W>> {
+        if plain.fault != optimized.fault {
+            Some(Difference::Fault(plain.fault, optimized.fault))
+        } else if plain.ip != optimized.ip {
+            Some(Difference::Address(plain.ip, optimized.ip))
+        } else if plain.regs != optimized.regs {
+            Some(Difference::Registers(plain.regs.clone(), optimized.regs.clone()))
+        } else if plain.output != optimized.output {
+            Some(Difference::Output(plain.output.clone(), optimized.output.clone()))
+        } else {
+            plain.instructions.iter().zip(optimized.instructions.iter()).enumerate()
+                .find(|&(_, (x, y))| x != y)
+                .map(|(addr, (&x, &y))| Difference::Program(addr, x, y))
+        }
+    }
+}
+
+
+/// First point where the optimized interpreter diverged from the plain one
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Divergence<W> {
+    /// Number of instructions the plain interpreter ran
+    pub steps: u64,
+    /// Address of the instruction (or first address of the loop) that the
+    /// optimized interpreter ran last
+    pub addr: usize,
+    /// Last address of the loop, if the optimized interpreter ran a loop
+    pub end: Option<usize>,
+    /// Difference of the states (plain vs. optimized)
+    pub difference: Difference<W>,
+}
+
+impl<W: Word> fmt::Display for Divergence<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "after {} steps, loop at lines {}-{}: ", self.steps, self.addr + 1, end + 1)?,
+            None => write!(f, "after {} steps, line {}: ", self.steps, self.addr + 1)?,
+        }
+        write!(f, "{}", self.difference)
+    }
+}
+
+
+/// Result of running both interpreters without a divergence
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Agreement {
+    /// Both agreed until the program stopped or the limit of steps was
+    /// reached. Gives the number of instructions the plain interpreter ran.
+    Complete(u64),
+    /// The limit of steps was reached while the plain interpreter caught
+    /// up with the loop at the given address, which the optimized
+    /// interpreter ran in a single step. The result of the loop is unchecked.
+    Inconclusive { steps: u64, addr: usize },
+}
+
+impl fmt::Display for Agreement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Agreement::Complete(steps) => write!(f, "agreed for {} steps", steps),
+            Agreement::Inconclusive { steps, addr } => write!(f, "inconclusive after {} steps, loop at line {} unchecked", steps, addr + 1),
+        }
+    }
+}
+
+
+/// Run a program by the plain and the optimized interpreter. The CPUs are
+/// prepared by the given function (e.g. to set registers or input).
+pub fn compare<W: Word, F: Fn(&mut Cpu<W>)>(instructions: &[Instruction], setup: F, max_steps: u64) -> Result<Agreement, Divergence<W>> {
+    let mut plain = Cpu::from(instructions.to_vec());
+    setup(&mut plain);
+    let mut optimized = Cpu::from(instructions.to_vec());
+    setup(&mut optimized);
+    compare_cpus(plain, optimized, max_steps)
+}
+
+/// Run two CPUs side by side, the first one without and the second one
+/// with shortcuts
+pub fn compare_cpus<W: Word>(mut plain: Cpu<W>, mut optimized: Cpu<W>, max_steps: u64) -> Result<Agreement, Divergence<W>> {
+    plain.shortcuts = false;
+    optimized.shortcuts = true;
+    let mut steps = 0;
+    while steps < max_steps && !optimized.is_done() {
+        let addr = optimized.ip;
+        let end = if optimized.run_loop() {
+            while plain.ip != optimized.ip && steps < max_steps && !plain.step_instruction() {
+                steps += 1;
+            }
+            if plain.ip != optimized.ip && !plain.is_done() {
+                return Ok(Agreement::Inconclusive { steps: steps, addr: addr });
+            }
+            Some(optimized.ip - 1)
+        } else {
+            optimized.step_instruction();
+            plain.step_instruction();
+            steps += 1;
+            None
+        };
+        if let Some(difference) = Difference::between(&plain, &optimized) {
+            return Err(Divergence { steps: steps, addr: addr, end: end, difference: difference });
+        }
+    }
+    Ok(Agreement::Complete(steps))
+}
+
+
+/// Generator of random programs. Uses a xorshift generator, so that the
+/// programs of a seed are always the same.
+#[derive(Debug)]
+pub struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    /// Create new generator with the given seed
+    pub fn new(seed: u64) -> Generator {
+        Generator { state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    /// Random number below n
+    fn below(&mut self, n: u64) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state % n
+    }
+
+    /// Random number in the given range (inclusive)
+    fn between(&mut self, min: i32, max: i32) -> i32 {
+        min + self.below((max - min + 1) as u64) as i32
+    }
+
+    /// Random register
+    fn register(&mut self) -> u8 {
+        self.below(4) as u8
+    }
+
+    /// Random immediate or register value
+    fn value(&mut self) -> Value {
+        if self.below(3) == 0 {
+            Value::Immediate(self.between(-3, 3) as i64)
+        } else {
+            Value::Register(self.register())
+        }
+    }
+
+    /// Random instruction
+    fn instruction(&mut self) -> Instruction {
+        match self.below(8) {
+            0 | 1 => Instruction::Cpy(self.value(), Value::Register(self.register())),
+            2 => Instruction::Inc(self.register()),
+            3 => Instruction::Dec(self.register()),
+            4 | 5 => Instruction::Jnz(self.value(), Value::Immediate(self.between(-4, 3) as i64)),
+            6 => Instruction::Tgl(self.register()),
+            _ => Instruction::Out(self.value()),
+        }
+    }
+
+    /// Random program of about the given length. Besides random
+    /// instructions, it contains add and multiply loops (with random
+    /// direction of counting), so that the optimizer has something to do.
+    pub fn program(&mut self, len: usize) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        while instructions.len() < len {
+            let (x, y, z) = (self.register(), self.register(), self.register());
+            let count = |up: bool, r| if up { Instruction::Inc(r) } else { Instruction::Dec(r) };
+            match self.below(6) {
+                0 => {
+                    let (up1, up2) = (self.below(4) == 0, self.below(2) == 0);
+                    instructions.extend_from_slice(&[count(up2, x), count(up1, y), Instruction::Jnz(Value::Register(y), Value::Immediate(-2))]);
+                },
+                1 => {
+                    let (v, up) = (self.value(), self.below(4) == 0);
+                    instructions.extend_from_slice(&[
+                        Instruction::Cpy(v, Value::Register(y)),
+                        Instruction::Inc(x),
+                        count(up, y),
+                        Instruction::Jnz(Value::Register(y), Value::Immediate(-2)),
+                        Instruction::Dec(z),
+                        Instruction::Jnz(Value::Register(z), Value::Immediate(-5)),
+                    ]);
+                },
+                _ => instructions.push(self.instruction()),
+            }
+        }
+        instructions
+    }
+
+    /// Random register values
+    pub fn registers(&mut self) -> Vec<i32> {
+        (0..4).map(|_| self.between(-3, 10)).collect()
+    }
+}
+
+
+/// Program and register values for which the interpreters diverged
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Counterexample<W> {
+    pub instructions: Vec<Instruction>,
+    pub regs: Vec<i32>,
+    pub divergence: Divergence<W>,
+}
+
+impl<W: Word> fmt::Display for Counterexample<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.divergence)?;
+        writeln!(f, "registers at start: {:?}", self.regs)?;
+        for ins in &self.instructions {
+            writeln!(f, "{}", ins)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the interpreters on the given number of random programs of
+/// about the given length, each started with random register values.
+/// Returns the number of programs with an inconclusive result.
+pub fn check_random<W: Word>(seed: u64, count: usize, len: usize, max_steps: u64) -> Result<usize, Counterexample<W>> {
+    let mut generator = Generator::new(seed);
+    let mut inconclusive = 0;
+    for _ in 0..count {
+        let instructions = generator.program(len);
+        let regs = generator.registers();
+        let setup = |cpu: &mut Cpu<W>| for (reg, &value) in cpu.regs.iter_mut().zip(regs.iter()) { *reg = W::from(value); };
+        match compare(&instructions, setup, max_steps) {
+            Ok(Agreement::Complete(_)) => (),
+            Ok(Agreement::Inconclusive { .. }) => inconclusive += 1,
+            Err(divergence) => return Err(Counterexample { instructions: instructions, regs: regs, divergence: divergence }),
+        }
+    }
+    Ok(inconclusive)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{optimizer, Dialect};
+    use super::super::word::Overflow;
+
+    #[test]
+    fn running_without_shortcuts() {
+        let mut cpu = Cpu::new("cpy 5 b\ninc a\ndec b\njnz b -2", &Dialect::default()).unwrap();
+        cpu.shortcuts = false;
+        assert_eq!(cpu.run_with_limit(100).to_string(), "halted after 16 steps");
+        assert_eq!(cpu.regs, [5, 0, 0, 0]);
+        cpu.reset();
+        cpu.shortcuts = true;
+        assert_eq!(cpu.run_with_limit(100).to_string(), "halted after 2 steps");
+        assert_eq!(cpu.regs, [5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn comparing() {
+        let instructions = Instruction::parse("cpy 5 b\ninc a\ndec b\njnz b -2\nout a", &Dialect::default()).unwrap();
+        assert_eq!(compare::<i32, _>(&instructions, |_| (), 100), Ok(Agreement::Complete(17)));
+        assert_eq!(compare::<i32, _>(&instructions, |_| (), 10), Ok(Agreement::Inconclusive { steps: 10, addr: 1 }));
+        // Negative counter: 2^32 iterations, which the optimizer leaves to stepping
+        let instructions = Instruction::parse("inc a\ndec b\njnz b -2", &Dialect::default()).unwrap();
+        assert_eq!(compare::<i32, _>(&instructions, |cpu| cpu.regs[1] = -1, 1000), Ok(Agreement::Complete(1000)));
+        // Loop run in a single step, but the plain interpreter can't catch up
+        let instructions = Instruction::parse("cpy 5000 b\ninc a\ndec b\njnz b -2", &Dialect::default()).unwrap();
+        let agreement = compare::<i32, _>(&instructions, |_| (), 1000).unwrap();
+        assert_eq!(agreement, Agreement::Inconclusive { steps: 1000, addr: 1 });
+        assert_eq!(agreement.to_string(), "inconclusive after 1000 steps, loop at line 2 unchecked");
+        let instructions = Instruction::parse("cpy 2 c\ntgl c\ncpy 3 b\ninc a\ndec b\njnz b -2", &Dialect::default()).unwrap();
+        assert_eq!(compare::<i64, _>(&instructions, |cpu| cpu.overflow = Overflow::Trap, 100), Ok(Agreement::Complete(12)));
+    }
+
+    #[test]
+    fn finding_divergence() {
+        let instructions = Instruction::parse("cpy 3 b\ninc a\ndec b\njnz b -2\nout a", &Dialect::default()).unwrap();
+        let plain = Cpu::from(instructions.clone());
+        let mut optimized = Cpu::from(instructions);
+        // Pretend the optimizer got the loop wrong
+        optimized.loops = optimizer::find_loops(&Instruction::parse("cpy 3 b\ndec a\ndec b\njnz b -2", &Dialect::default()).unwrap());
+        let divergence = compare_cpus::<i32>(plain, optimized, 100).unwrap_err();
+        assert_eq!(divergence, Divergence { steps: 10, addr: 1, end: Some(3), difference: Difference::Registers(vec![3, 0, 0, 0], vec![-3, 0, 0, 0]) });
+        assert_eq!(divergence.to_string(), "after 10 steps, loop at lines 2-4: registers [3, 0, 0, 0] vs. [-3, 0, 0, 0]");
+    }
+
+    #[test]
+    fn generating() {
+        let program = Generator::new(42).program(20);
+        assert!(program.len() >= 20);
+        assert_eq!(Generator::new(42).program(20), program);
+        assert!(Generator::new(43).program(20) != program);
+    }
+
+    #[test]
+    fn checking_random_programs() {
+        // Some programs run long loops, which can't be checked within the
+        // limit of steps, but most of them should be checked
+        match check_random::<i32>(1, 200, 12, 2000) {
+            Ok(inconclusive) => assert!(inconclusive <= 100, "{} of 200 runs inconclusive", inconclusive),
+            Err(counterexample) => panic!("{}", counterexample),
+        }
+        match check_random::<i64>(2, 200, 20, 2000) {
+            Ok(inconclusive) => assert!(inconclusive <= 100, "{} of 200 runs inconclusive", inconclusive),
+            Err(counterexample) => panic!("{}", counterexample),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Disassembler that turns instructions back into a readable listing in the
+//! style of the annotated listing of day 23: jump targets get labels, loops
+//! are marked with loop art (`|`) and toggled instructions are annotated.
+
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use super::{Dialect, Instruction, Value};
+
+
+/// Column where loop art starts (relative to the instruction text)
+const ART_COLUMN: usize = 16;
+
+
+/// Returns the address a `jnz` instruction with constant offset jumps to (if it
+/// jumps into the program or right behind its end)
+fn jump_target(instructions: &[Instruction], ip: usize) -> Option<usize> {
+    match instructions[ip] {
+        Instruction::Jnz(_, Value::Immediate(ofs)) => {
+            let target = ip as isize + ofs as isize;
+            if target >= 0 && target as usize <= instructions.len() { Some(target as usize) } else { None }
+        },
+        _ => None,
+    }
+}
+
+/// Append a line with the given text, loop art and comment to a listing
+fn push_line(listing: &mut String, text: &str, column: usize, depth: usize, comment: Option<String>) {
+    let mut line = format!("{:<2$}{}", text, "| ".repeat(depth), column);
+    if let Some(comment) = comment {
+        line = format!("{:<2$}; {}", line, comment, column + 12);
+    }
+    listing.push_str(line.trim_end());
+    listing.push('\n');
+}
+
+/// Disassemble a program of the given dialect to a listing
+pub fn disassemble(instructions: &[Instruction], dialect: &Dialect) -> String {
+    disassemble_toggled(instructions, instructions, dialect)
+}
+
+/// Disassemble a program of the given dialect to a listing, marking
+/// instructions that differ from the original program (i.e. that have been
+/// modified by `tgl`)
+pub fn disassemble_toggled(instructions: &[Instruction], original: &[Instruction], dialect: &Dialect) -> String {
+    let names = dialect.registers;
+    // Find jump targets and loops (address ranges of backward jumps)
+    let mut targets = BTreeSet::new();
+    let mut loops = Vec::new();
+    for ip in 0..instructions.len() {
+        if let Some(target) = jump_target(instructions, ip) {
+            targets.insert(target);
+            if target <= ip {
+                loops.push((target, ip));
+            }
+        }
+    }
+    // Synthesize label names
+    let mut labels = HashMap::new();
+    let (mut num_loops, mut num_labels) = (0, 0);
+    for &target in &targets {
+        let label = if target == instructions.len() {
+            "end".to_owned()
+        } else if loops.iter().any(|&(start, _)| start == target) {
+            num_loops += 1;
+            format!("loop{}", num_loops)
+        } else {
+            num_labels += 1;
+            format!("label{}", num_labels)
+        };
+        labels.insert(target, label);
+    }
+    // Write labels and instructions
+    let width = cmp::max(2, instructions.len().to_string().len());
+    let mut listing = String::new();
+    for ip in 0..instructions.len() + 1 {
+        if let Some(label) = labels.get(&ip) {
+            let depth = loops.iter().filter(|&&(start, end)| start < ip && ip <= end).count();
+            if depth == 0 && ip > 0 {
+                listing.push('\n');
+            }
+            let text = format!("{:2$}{}:", "", label, width + 2);
+            push_line(&mut listing, &text, width + 6 + ART_COLUMN, depth, None);
+        }
+        if ip < instructions.len() {
+            let depth = loops.iter().filter(|&&(start, end)| start <= ip && ip <= end).count();
+            let text = match (instructions[ip], jump_target(instructions, ip)) {
+                (Instruction::Jnz(x, _), Some(target)) => format!("{:03$}      jnz {} {}", ip + 1, x.show(names), labels[&target], width),
+                (ins, _) => format!("{:02$}      {}", ip + 1, ins.show(names), width),
+            };
+            let comment = match original.get(ip) {
+                Some(ins) if *ins != instructions[ip] => Some(format!("toggled from: {}", ins.show(names))),
+                _ => None,
+            };
+            push_line(&mut listing, &text, width + 6 + ART_COLUMN, depth, comment);
+        }
+    }
+    listing
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dialect;
+    use super::super::assembler::assemble;
+
+    #[test]
+    fn disassembling() {
+        let program = Instruction::parse("cpy 2 a\ncpy a b\ninc c\ndec b\njnz b -2\ndec a\njnz a -5\njnz c 2\ninc d", &Dialect::default()).unwrap();
+        assert_eq!(disassemble(&program, &Dialect::default()), concat!(
+            "01      cpy 2 a\n",
+            "\n",
+            "    loop1:\n",
+            "02      cpy a b         |\n",
+            "    loop2:              |\n",
+            "03      inc c           | |\n",
+            "04      dec b           | |\n",
+            "05      jnz b loop2     | |\n",
+            "06      dec a           |\n",
+            "07      jnz a loop1     |\n",
+            "08      jnz c end\n",
+            "09      inc d\n",
+            "\n",
+            "    end:\n",
+        ));
+        assert_eq!(assemble(&disassemble(&program, &Dialect::default()), &Dialect::default()), Ok(program));
+    }
+
+    #[test]
+    fn disassembling_toggled() {
+        let original = Instruction::parse("tgl a\ninc b\njnz b 7", &Dialect::default()).unwrap();
+        let mut program = original.clone();
+        program[1] = program[1].toggle();
+        program[2] = program[2].toggle();
+        assert_eq!(disassemble_toggled(&program, &original, &Dialect::default()), concat!(
+            "01      tgl a\n",
+            "02      dec b                       ; toggled from: inc b\n",
+            "03      cpy b 7                     ; toggled from: jnz b 7\n",
+        ));
+    }
+
+    #[test]
+    fn disassembling_named_registers() {
+        let dialect = Dialect::default().with_registers("xyzw");
+        let program = Instruction::parse("cpy 3 y\ninc x\ndec y\njnz y -2\nout x", &dialect).unwrap();
+        assert_eq!(disassemble(&program, &dialect), concat!(
+            "01      cpy 3 y\n",
+            "\n",
+            "    loop1:\n",
+            "02      inc x           |\n",
+            "03      dec y           |\n",
+            "04      jnz y loop1     |\n",
+            "05      out x\n",
+        ));
+        assert_eq!(assemble(&disassemble(&program, &dialect), &dialect), Ok(program));
+    }
+}
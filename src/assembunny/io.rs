@@ -0,0 +1,176 @@
+//! Input and output channels. Values output by `out` are collected in the
+//! CPU's output, values read by `inp` are taken from its input queue. When
+//! running a program with channels, every output value is passed on to a
+//! sink as soon as it is produced, and the input queue is refilled from a
+//! source whenever the program waits for input.
+
+use std::char;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use super::Cpu;
+use super::word::Word;
+
+
+/// Destination of output values
+pub trait Sink<W> {
+    /// Take an output value
+    fn write(&mut self, value: W);
+}
+
+/// Collect output values
+impl<W> Sink<W> for Vec<W> {
+    fn write(&mut self, value: W) {
+        self.push(value);
+    }
+}
+
+/// Decode output values as ASCII text. Values that are no ASCII codes are
+/// decoded as replacement characters.
+impl<W: Word> Sink<W> for String {
+    fn write(&mut self, value: W) {
+        let code: i128 = value.into();
+        self.push(if code >= 0 && code < 0x80 { code as u8 as char } else { char::REPLACEMENT_CHARACTER });
+    }
+}
+
+/// Pass output values to a function
+pub struct Callback<F>(pub F);
+
+impl<W, F: FnMut(W)> Sink<W> for Callback<F> {
+    fn write(&mut self, value: W) {
+        (self.0)(value)
+    }
+}
+
+
+/// Origin of input values
+pub trait Source<W> {
+    /// Get the next input value. Returns None at the end of input.
+    fn read(&mut self) -> io::Result<Option<W>>;
+}
+
+/// Take input values from a queue
+impl<W> Source<W> for VecDeque<W> {
+    fn read(&mut self) -> io::Result<Option<W>> {
+        Ok(self.pop_front())
+    }
+}
+
+/// Queue of input values with the ASCII codes of the given text
+pub fn text<W: Word>(s: &str) -> VecDeque<W> {
+    s.bytes().map(|b| W::from(b as i32)).collect()
+}
+
+/// Read input values from a reader, one number per line. Empty lines are
+/// skipped.
+pub struct Reader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Create new source reading from the given reader
+    pub fn new(reader: R) -> Reader<R> {
+        Reader { reader: reader }
+    }
+}
+
+impl<W: Word, R: BufRead> Source<W> for Reader<R> {
+    fn read(&mut self) -> io::Result<Option<W>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if !line.is_empty() {
+                return line.parse().map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid input value: {}", line)));
+            }
+        }
+    }
+}
+
+/// Read input values from standard input, one number per line
+pub fn stdin() -> Reader<io::BufReader<io::Stdin>> {
+    Reader::new(io::BufReader::new(io::stdin()))
+}
+
+
+impl<W: Word> Cpu<W> {
+    /// Run program, passing every output value to the given sink as soon as
+    /// it is produced. Whenever the program waits for input, the next value
+    /// is read from the given source. Stops when the program has finished or
+    /// the source has no more input.
+    pub fn run_io<I: Source<W>, O: Sink<W>>(&mut self, source: &mut I, sink: &mut O) -> io::Result<()> {
+        let mut written = self.output.len();
+        loop {
+            let done = self.step();
+            for &value in &self.output[written..] {
+                sink.write(value);
+            }
+            written = self.output.len();
+            if done {
+                if !self.is_waiting() {
+                    return Ok(());
+                }
+                match source.read()? {
+                    Some(value) => self.input.push_back(value),
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dialect;
+
+    #[test]
+    fn writing() {
+        let mut values = vec![];
+        Sink::<i32>::write(&mut values, 7);
+        assert_eq!(values, vec![7]);
+        let mut s = String::new();
+        for &value in &[72, 105, 10, 300, -1] {
+            Sink::<i32>::write(&mut s, value);
+        }
+        assert_eq!(s, "Hi\n\u{fffd}\u{fffd}");
+        let mut sum = 0;
+        Callback(|value: i32| sum += value).write(5);
+        assert_eq!(sum, 5);
+    }
+
+    #[test]
+    fn reading() {
+        let mut queue = text::<i32>("Hi");
+        assert_eq!(queue.read().unwrap(), Some(72));
+        assert_eq!(queue.read().unwrap(), Some(105));
+        assert_eq!(queue.read().unwrap(), None);
+        let mut reader = Reader::new("12\n\n-3\n".as_bytes());
+        assert_eq!(Source::<i64>::read(&mut reader).unwrap(), Some(12));
+        assert_eq!(Source::<i64>::read(&mut reader).unwrap(), Some(-3));
+        assert_eq!(Source::<i64>::read(&mut reader).unwrap(), None);
+        assert!(Source::<i32>::read(&mut Reader::new("x".as_bytes())).is_err());
+    }
+
+    #[test]
+    fn running_io() {
+        // Echo input, converted to upper case
+        let program = "inp a\njnz a 2\njnz 1 8\ncpy 32 b\ndec a\ndec b\njnz b -2\nout a\njnz 1 -8";
+        let mut cpu = Cpu::new(program, &Dialect::default()).unwrap();
+        let mut output = String::new();
+        cpu.run_io(&mut text("hello"), &mut output).unwrap();
+        assert_eq!(output, "HELLO");
+        assert!(cpu.is_waiting());
+        cpu.input.push_back(0);
+        cpu.run();
+        assert!(cpu.is_done() && !cpu.is_waiting());
+        let mut cpu = Cpu::new("inp a\ninp b\ninc a\nout a\ndec b\njnz b -3", &Dialect::default()).unwrap();
+        let mut values = vec![];
+        cpu.run_io(&mut Reader::new("10\n3\n".as_bytes()), &mut Callback(|value| values.push(value))).unwrap();
+        assert_eq!(values, vec![11, 12, 13]);
+    }
+}
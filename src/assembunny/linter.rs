@@ -0,0 +1,334 @@
+//! Static checks of programs. The program is analyzed without running it,
+//! tracking for every reachable instruction which registers have a known
+//! constant value and which registers have definitely been written on every
+//! path that leads to it. Instructions that may be toggled are analyzed with
+//! the effects of both variants. Suspicious things are reported as warnings.
+
+use std::{cmp, fmt};
+use super::{named_register, registers_used, Instruction, Value};
+
+
+/// A suspicious thing found in a program. Addresses start at 0, but are
+/// shown as line numbers starting at 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Warning {
+    /// Jump with a constant offset to a negative address or beyond the end
+    /// of the program (jumping right behind the last instruction halts)
+    JumpOutside { addr: usize, target: i64 },
+    /// `cpy` into an immediate value, which is ignored
+    CpyImmediate { addr: usize },
+    /// Instructions in the given range (inclusive) are never run
+    Unreachable { start: usize, end: usize },
+    /// `tgl` with a constant target outside of the program, which is ignored
+    ToggleOutside { addr: usize, target: i64 },
+    /// Register is read before it is written (its initial value is used)
+    ReadBeforeWrite { addr: usize, register: u8 },
+    /// `out` of a constant value that is no ASCII code
+    NonAscii { addr: usize, value: i64 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Standard register names
+        self.show("").fmt(f)
+    }
+}
+
+impl Warning {
+    /// Show warning, using the given register names
+    pub fn show(&self, names: &str) -> String {
+        match *self {
+            Warning::JumpOutside { addr, target } => format!("line {}: jump to address {} outside of the program", addr + 1, target),
+            Warning::CpyImmediate { addr } => format!("line {}: copy into an immediate value is ignored", addr + 1),
+            Warning::Unreachable { start, end } if start == end => format!("line {}: unreachable instruction", start + 1),
+            Warning::Unreachable { start, end } => format!("lines {}-{}: unreachable instructions", start + 1, end + 1),
+            Warning::ToggleOutside { addr, target } => format!("line {}: toggle of address {} outside of the program is ignored", addr + 1, target),
+            Warning::ReadBeforeWrite { addr, register } => format!("line {}: register {} is read before it is written", addr + 1, named_register(names, register)),
+            Warning::NonAscii { addr, value } => format!("line {}: output value {} is no ASCII code", addr + 1, value),
+        }
+    }
+}
+
+
+/// Abstract machine state at an instruction
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct State {
+    /// Known constant value of each register
+    values: Vec<Option<i64>>,
+    /// Whether each register has been written on every path
+    written: Vec<bool>,
+}
+
+impl State {
+    /// Known constant value
+    fn get(&self, v: Value) -> Option<i64> {
+        match v {
+            Value::Immediate(x) => Some(x),
+            Value::Register(x) => self.values[x as usize],
+        }
+    }
+
+    /// Write a register
+    fn set(&mut self, x: u8, value: Option<i64>) {
+        self.values[x as usize] = value;
+        self.written[x as usize] = true;
+    }
+
+    /// Merge with the state of another path. Returns true if the state changed.
+    fn join(&mut self, other: &State) -> bool {
+        let old = self.clone();
+        for (value, &other) in self.values.iter_mut().zip(&other.values) {
+            if *value != other { *value = None; }
+        }
+        for (written, &other) in self.written.iter_mut().zip(&other.written) {
+            *written = *written && other;
+        }
+        *self != old
+    }
+}
+
+
+/// Successors of an instruction
+struct Successors {
+    /// State after running the instruction
+    state: State,
+    /// Addresses that may be run next
+    targets: Vec<i64>,
+    /// Whether any address may be run next (jump by an unknown offset)
+    anywhere: bool,
+}
+
+/// Run an instruction at the given address on an abstract state
+fn execute(ins: Instruction, addr: usize, state: &State) -> Successors {
+    let mut state = state.clone();
+    let next = addr as i64 + 1;
+    let (targets, anywhere) = match ins {
+        Instruction::Cpy(v, Value::Register(y)) => {
+            let value = state.get(v);
+            state.set(y, value);
+            (vec![next], false)
+        },
+        Instruction::Inc(x) => {
+            let value = state.values[x as usize].map(|v| v.wrapping_add(1));
+            state.set(x, value);
+            (vec![next], false)
+        },
+        Instruction::Dec(x) => {
+            let value = state.values[x as usize].map(|v| v.wrapping_sub(1));
+            state.set(x, value);
+            (vec![next], false)
+        },
+        Instruction::Inp(x) => {
+            state.set(x, None);
+            (vec![next], false)
+        },
+        Instruction::Jnz(v1, v2) => match (state.get(v1), state.get(v2)) {
+            (Some(0), _) => (vec![next], false),
+            (Some(_), Some(ofs)) => (vec![addr as i64 + ofs as i64], false),
+            (None, Some(ofs)) => (vec![next, addr as i64 + ofs as i64], false),
+            (_, None) => (vec![next], true),
+        },
+        Instruction::Cpy(_, Value::Immediate(_)) | Instruction::Tgl(_) | Instruction::Out(_) => (vec![next], false),
+    };
+    Successors { state: state, targets: targets, anywhere: anywhere }
+}
+
+
+/// Registers read by an instruction
+fn reads(ins: Instruction) -> Vec<u8> {
+    match ins {
+        Instruction::Cpy(v, _) | Instruction::Out(v) => Instruction::Out(v).registers(),
+        Instruction::Inp(_) => vec![],
+        ins => ins.registers(),
+    }
+}
+
+
+/// Analyzer that determines the abstract state at every instruction
+struct Analyzer<'a> {
+    instructions: &'a [Instruction],
+    /// Whether each instruction may be toggled
+    toggled: Vec<bool>,
+    /// State at each instruction (None if unreachable)
+    states: Vec<Option<State>>,
+}
+
+impl<'a> Analyzer<'a> {
+    /// Successors of the instruction at the given address, including the
+    /// ones of its toggled variant if it may be toggled
+    fn successors(&self, addr: usize, state: &State) -> Vec<Successors> {
+        let ins = self.instructions[addr];
+        let mut successors = vec![execute(ins, addr, state)];
+        if self.toggled[addr] {
+            successors.push(execute(ins.toggle(), addr, state));
+        }
+        successors
+    }
+
+    /// Merge a state into the state at the given address. Returns true if it changed.
+    fn merge(&mut self, addr: usize, state: &State) -> bool {
+        match self.states[addr] {
+            Some(ref mut s) => s.join(state),
+            None => { self.states[addr] = Some(state.clone()); true },
+        }
+    }
+
+    /// Determine the states at all instructions
+    fn analyze(&mut self, registers: usize) {
+        let len = self.instructions.len();
+        self.states = vec![None; len];
+        if len == 0 {
+            return;
+        }
+        self.states[0] = Some(State { values: vec![None; registers], written: vec![false; registers] });
+        let mut pending = vec![0];
+        while let Some(addr) = pending.pop() {
+            let state = self.states[addr].clone().unwrap();
+            for successors in self.successors(addr, &state) {
+                let targets: Vec<usize> = if successors.anywhere {
+                    (0..len).collect()
+                } else {
+                    successors.targets.iter().filter(|&&t| t >= 0 && t < len as i64).map(|&t| t as usize).collect()
+                };
+                for target in targets {
+                    if self.merge(target, &successors.state) {
+                        pending.push(target);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Determine which instructions may be toggled by reachable `tgl`
+    /// instructions. Returns true if any more instructions may be toggled.
+    fn update_toggled(&mut self) -> bool {
+        let mut changed = false;
+        for (addr, &ins) in self.instructions.iter().enumerate() {
+            if let (Instruction::Tgl(x), Some(ref state)) = (ins, self.states[addr].clone()) {
+                let targets: Vec<usize> = match state.values[x as usize] {
+                    Some(ofs) => {
+                        let target = addr as i64 + ofs as i64;
+                        if target >= 0 && target < self.instructions.len() as i64 { vec![target as usize] } else { vec![] }
+                    },
+                    None => (0..self.instructions.len()).collect(),
+                };
+                for target in targets {
+                    if !self.toggled[target] {
+                        self.toggled[target] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+
+/// Check a program for suspicious things. If `text` is set, the output is
+/// expected to be ASCII text.
+pub fn lint(instructions: &[Instruction], text: bool) -> Vec<Warning> {
+    let len = instructions.len();
+    let registers = cmp::max(4, registers_used(instructions));
+    let mut analyzer = Analyzer { instructions: instructions, toggled: vec![false; len], states: Vec::new() };
+    analyzer.analyze(registers);
+    while analyzer.update_toggled() {
+        analyzer.analyze(registers);
+    }
+    let mut warnings = Vec::new();
+    let mut read = vec![false; registers];
+    let mut addr = 0;
+    while addr < len {
+        let state = match analyzer.states[addr] {
+            Some(ref state) => state,
+            None => {
+                let start = addr;
+                while addr + 1 < len && analyzer.states[addr + 1].is_none() { addr += 1; }
+                warnings.push(Warning::Unreachable { start: start, end: addr });
+                addr += 1;
+                continue;
+            },
+        };
+        let ins = instructions[addr];
+        if let Instruction::Cpy(_, Value::Immediate(_)) = ins {
+            warnings.push(Warning::CpyImmediate { addr: addr });
+        }
+        for x in reads(ins) {
+            if !state.written[x as usize] && !read[x as usize] {
+                read[x as usize] = true;
+                warnings.push(Warning::ReadBeforeWrite { addr: addr, register: x });
+            }
+        }
+        match ins {
+            Instruction::Jnz(v1, v2) if state.get(v1) != Some(0) => if let Some(ofs) = state.get(v2) {
+                let target = addr as i64 + ofs as i64;
+                if target < 0 || target > len as i64 {
+                    warnings.push(Warning::JumpOutside { addr: addr, target: target });
+                }
+            },
+            Instruction::Tgl(x) => if let Some(ofs) = state.values[x as usize] {
+                let target = addr as i64 + ofs as i64;
+                if target < 0 || target >= len as i64 {
+                    warnings.push(Warning::ToggleOutside { addr: addr, target: target });
+                }
+            },
+            Instruction::Out(v) if text => if let Some(value) = state.get(v) {
+                if value < 0 || value >= 0x80 {
+                    warnings.push(Warning::NonAscii { addr: addr, value: value });
+                }
+            },
+            _ => (),
+        }
+        addr += 1;
+    }
+    warnings
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dialect;
+
+    fn linted(program: &str, text: bool) -> Vec<String> {
+        lint(&Instruction::parse(program, &Dialect::default()).unwrap(), text).iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn linting() {
+        assert_eq!(linted("inp a\njnz a 2\ncpy 3 b\nout a", false), Vec::<String>::new());
+        assert_eq!(linted("cpy 1 a\njnz a 3\ninc a\ninc b\ncpy a 2\njnz 1 -7", false), vec![
+            "lines 3-4: unreachable instructions",
+            "line 5: copy into an immediate value is ignored",
+            "line 6: jump to address -2 outside of the program",
+        ]);
+        assert_eq!(linted("cpy b a\ninc c\njnz d 2\ninp d\nout d", false), vec![
+            "line 1: register b is read before it is written",
+            "line 2: register c is read before it is written",
+            "line 3: register d is read before it is written",
+        ]);
+        assert_eq!(linted("cpy 200 a\nout a\ncpy 65 a\nout a\ninp b\nout b", true), vec![
+            "line 2: output value 200 is no ASCII code",
+        ]);
+        assert!(linted("cpy 200 a\nout a", false).is_empty());
+    }
+
+    #[test]
+    fn linting_registers() {
+        let dialect = Dialect::default().with_registers("xy");
+        let warnings = lint(&Instruction::parse("inc x\nout y", &dialect).unwrap(), false);
+        let shown: Vec<String> = warnings.iter().map(|w| w.show(dialect.registers)).collect();
+        assert_eq!(shown, vec!["line 1: register x is read before it is written", "line 2: register y is read before it is written"]);
+    }
+
+    #[test]
+    fn linting_toggles() {
+        // The toggle turns the jump into a copy, so the end is reachable
+        assert_eq!(linted("cpy 2 a\ntgl a\ncpy 5 a\njnz 1 0\ninc a", false), Vec::<String>::new());
+        assert_eq!(linted("cpy 3 a\ntgl a\ncpy 5 a", false), vec![
+            "line 2: toggle of address 4 outside of the program is ignored",
+        ]);
+        // A toggle by an unknown offset may affect any instruction
+        assert_eq!(linted("inp b\ntgl b\njnz 1 0\ncpy 1 a", false), Vec::<String>::new());
+    }
+}
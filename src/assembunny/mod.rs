@@ -0,0 +1,702 @@
+//! Assembunny virtual machine, shared by all puzzles that run assembunny code
+//! (day 12, day 23, day 25 and the bonus challenge)
+
+// Not every puzzle uses every feature of the virtual machine
+#![allow(dead_code)]
+
+pub mod assembler;
+pub mod clock;
+pub mod compiler;
+pub mod debugger;
+pub mod differential;
+pub mod disassembler;
+pub mod io;
+pub mod linter;
+pub mod optimizer;
+pub mod outcome;
+pub mod profiler;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+pub mod transpiler;
+pub mod word;
+
+use std::{cmp, fmt};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use nom::{self, space, digit};
+use parse_error::{self, ParseError};
+use self::optimizer::Loop;
+use self::profiler::Profile;
+use self::trace::Trace;
+use self::outcome::Fault;
+use self::word::{Overflow, OverflowError, Word};
+
+
+/// Assembunny dialect. Each puzzle extends the instruction set a little,
+/// so a dialect specifies which instructions are allowed and how quirks
+/// are treated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Dialect {
+    /// Allow the `tgl` instruction (introduced on day 23)
+    pub tgl: bool,
+    /// Allow the `out` instruction (introduced on day 25)
+    pub out: bool,
+    /// Allow the `inp` instruction (not used by any puzzle)
+    pub inp: bool,
+    /// Treatment of `cpy` into an immediate value
+    pub invalid_cpy: InvalidCpy,
+    /// Names of the registers, in order of their numbers
+    pub registers: &'static str,
+}
+
+/// Treatment of `cpy` instructions that have an immediate value as target
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InvalidCpy {
+    /// Fail to parse such an instruction
+    Reject,
+    /// Accept such an instruction, but skip it when executed
+    Ignore,
+}
+
+impl Default for Dialect {
+    /// The default dialect is the superset of all dialects
+    fn default() -> Dialect {
+        Dialect { tgl: true, out: true, inp: true, invalid_cpy: InvalidCpy::Ignore, registers: "abcd" }
+    }
+}
+
+impl Dialect {
+    /// Dialect of day 12 (`cpy`, `inc`, `dec` and `jnz` only)
+    pub fn day12() -> Dialect {
+        Dialect { tgl: false, out: false, inp: false, invalid_cpy: InvalidCpy::Reject, registers: "abcd" }
+    }
+
+    /// Dialect of day 23 (adds `tgl`, which may create invalid `cpy` instructions)
+    pub fn day23() -> Dialect {
+        Dialect { tgl: true, out: false, inp: false, invalid_cpy: InvalidCpy::Ignore, registers: "abcd" }
+    }
+
+    /// Dialect of day 25 and the bonus challenge (adds `out`)
+    pub fn day25() -> Dialect {
+        Dialect { tgl: false, out: true, inp: false, invalid_cpy: InvalidCpy::Ignore, registers: "abcd" }
+    }
+
+    /// Same dialect with a different register file. Registers are named by
+    /// single characters, e.g. `"abcdef"` for six registers.
+    pub fn with_registers(self, registers: &'static str) -> Dialect {
+        Dialect { registers: registers, ..self }
+    }
+
+    /// Name of a register in this dialect
+    pub fn register_name(&self, x: u8) -> char {
+        named_register(self.registers, x)
+    }
+}
+
+
+/// Standard name of a register (used when showing instructions)
+pub fn register_name(x: u8) -> char {
+    (b'a' + x) as char
+}
+
+/// Name of a register, given the names of the register file (falls back to
+/// the standard name if the register has no name)
+pub fn named_register(names: &str, x: u8) -> char {
+    names.chars().nth(x as usize).unwrap_or_else(|| register_name(x))
+}
+
+/// Number of registers used by a program
+pub fn registers_used(instructions: &[Instruction]) -> usize {
+    instructions.iter().flat_map(|ins| ins.registers()).map(|x| x as usize + 1).max().unwrap_or(0)
+}
+
+
+/// An immediate or register value
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Value {
+    Immediate(i64),
+    Register(u8),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.show("").fmt(f)
+    }
+}
+
+impl Value {
+    /// Show value, using the given register names
+    pub fn show(&self, names: &str) -> String {
+        match *self {
+            Value::Immediate(x) => x.to_string(),
+            Value::Register(x) => named_register(names, x).to_string(),
+        }
+    }
+
+    /// Get value
+    pub fn get<W: Word>(&self, regs: &[W]) -> W {
+        match *self {
+            // Immediate values are checked to fit when the program is loaded
+            Value::Immediate(x) => W::truncate(x as i128),
+            Value::Register(x) => regs[x as usize],
+        }
+    }
+}
+
+
+/// An assembunny instruction
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Instruction {
+    Cpy(Value, Value),
+    Inc(u8),
+    Dec(u8),
+    Jnz(Value, Value),
+    Tgl(u8),
+    Out(Value),
+    /// Read input into register
+    Inp(u8),
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Standard register names
+        self.show("").fmt(f)
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Instruction, ParseError> {
+        Instruction::parse_line(s, &Dialect::default())
+    }
+}
+
+/// Parse a register with one of the given names
+fn register<'a>(input: &'a str, names: &str) -> nom::IResult<&'a str, u8> {
+    if input.is_empty() {
+        return nom::IResult::Incomplete(nom::Needed::Size(1));
+    }
+    match input.chars().next().and_then(|c| names.chars().position(|name| name == c).map(|x| (c, x))) {
+        Some((c, x)) => nom::IResult::Done(&input[c.len_utf8()..], x as u8),
+        None => nom::IResult::Error(error_position!(nom::ErrorKind::OneOf, input)),
+    }
+}
+
+/// Parse an immediate value or a register with one of the given names
+fn value<'a>(input: &'a str, names: &str) -> nom::IResult<&'a str, Value> {
+    alt!(input,
+        do_parse!(
+            x: map_res!(recognize!(pair!(opt!(tag!("-")), digit)), i64::from_str) >>
+            (Value::Immediate(x))
+        ) |
+        do_parse!(
+            r: call!(register, names) >>
+            (Value::Register(r))
+        )
+    )
+}
+
+impl Instruction {
+    /// Show instruction, using the given register names
+    pub fn show(&self, names: &str) -> String {
+        let reg = |x| named_register(names, x);
+        match *self {
+            Instruction::Cpy(x, y) => format!("cpy {} {}", x.show(names), y.show(names)),
+            Instruction::Inc(x) => format!("inc {}", reg(x)),
+            Instruction::Dec(x) => format!("dec {}", reg(x)),
+            Instruction::Jnz(x, y) => format!("jnz {} {}", x.show(names), y.show(names)),
+            Instruction::Tgl(x) => format!("tgl {}", reg(x)),
+            Instruction::Out(x) => format!("out {}", x.show(names)),
+            Instruction::Inp(x) => format!("inp {}", reg(x)),
+        }
+    }
+
+    /// Parse a single line to an instruction of the given dialect
+    pub fn parse_line(s: &str, dialect: &Dialect) -> Result<Instruction, ParseError> {
+        Instruction::parse_named(s, dialect, dialect.registers)
+    }
+
+    /// Parse a single line to an instruction of the given dialect, with
+    /// registers named differently than in the dialect
+    fn parse_named(s: &str, dialect: &Dialect, names: &str) -> Result<Instruction, ParseError> {
+        parse_line!(s, alt!(
+            do_parse!(
+                tag!("cpy") >> space >>
+                x: call!(value, names) >> space >>
+                y: alt!(
+                    map!(call!(register, names), Value::Register) |
+                    cond_reduce!(dialect.invalid_cpy == InvalidCpy::Ignore, call!(value, names))
+                ) >>
+                (Instruction::Cpy(x, y))
+            ) |
+            do_parse!(
+                tag!("inc") >> space >>
+                x: call!(register, names) >>
+                (Instruction::Inc(x))
+            ) |
+            do_parse!(
+                tag!("dec") >> space >>
+                x: call!(register, names) >>
+                (Instruction::Dec(x))
+            ) |
+            do_parse!(
+                tag!("jnz") >> space >>
+                x: call!(value, names) >> space >>
+                y: call!(value, names) >>
+                (Instruction::Jnz(x, y))
+            ) |
+            do_parse!(
+                cond_reduce!(dialect.tgl, tag!("tgl")) >> space >>
+                x: call!(register, names) >>
+                (Instruction::Tgl(x))
+            ) |
+            do_parse!(
+                cond_reduce!(dialect.out, tag!("out")) >> space >>
+                x: call!(value, names) >>
+                (Instruction::Out(x))
+            ) |
+            do_parse!(
+                cond_reduce!(dialect.inp, tag!("inp")) >> space >>
+                x: call!(register, names) >>
+                (Instruction::Inp(x))
+            )
+        ))
+    }
+
+    /// Parse a multiline-text to a vector of instructions of the given dialect
+    pub fn parse(s: &str, dialect: &Dialect) -> Result<Vec<Instruction>, ParseError> {
+        parse_error::parse_lines(s, |line| Instruction::parse_line(line, dialect))
+    }
+
+    /// Registers used by the instruction
+    pub fn registers(&self) -> Vec<u8> {
+        let value = |v: Value| match v {
+            Value::Register(x) => Some(x),
+            Value::Immediate(_) => None,
+        };
+        match *self {
+            Instruction::Cpy(v1, v2) | Instruction::Jnz(v1, v2) => value(v1).into_iter().chain(value(v2)).collect(),
+            Instruction::Inc(x) | Instruction::Dec(x) | Instruction::Tgl(x) | Instruction::Inp(x) => vec![x],
+            Instruction::Out(v) => value(v).into_iter().collect(),
+        }
+    }
+
+    /// Returns the toggled instruction (result of being affected by a tgl instruction)
+    pub fn toggle(&self) -> Instruction {
+        match *self {
+            Instruction::Cpy(v1, v2) => Instruction::Jnz(v1, v2),
+            Instruction::Inc(x) => Instruction::Dec(x),
+            Instruction::Dec(x) => Instruction::Inc(x),
+            Instruction::Jnz(v1, v2) => Instruction::Cpy(v1, v2),
+            Instruction::Tgl(x) | Instruction::Inp(x) => Instruction::Inc(x),
+            Instruction::Out(Value::Register(x)) => Instruction::Inc(x),
+            // inc of an immediate value can't be represented, keep as is
+            Instruction::Out(v) => Instruction::Out(v),
+        }
+    }
+}
+
+
+/// Address at the given offset from an address (or an address past any
+/// program if it is too large). A negative address is returned as error.
+fn offset(addr: usize, offset: i128) -> Result<usize, i128> {
+    let addr = addr as i128 + offset;
+    if addr < 0 {
+        Err(addr)
+    } else if addr > usize::MAX as i128 {
+        Ok(usize::MAX)
+    } else {
+        Ok(addr as usize)
+    }
+}
+
+
+/// The assembunny CPU, with registers of type `W`
+#[derive(Debug)]
+pub struct Cpu<W: Word = i32> {
+    pub instructions: Vec<Instruction>,
+    pub ip: usize,
+    pub regs: Vec<W>,
+    pub output: Vec<W>,
+    /// Values to be read by `inp` instructions
+    pub input: VecDeque<W>,
+    /// Treatment of arithmetic overflow
+    pub overflow: Overflow,
+    /// Fault that stopped the program
+    pub fault: Option<Fault>,
+    /// Run optimizable loops in a single step. Without these shortcuts,
+    /// the CPU is a plain reference interpreter.
+    pub shortcuts: bool,
+    /// Names of the registers (of the dialect the program was written in)
+    pub names: String,
+    /// Program as it was loaded, before it was modified by toggles
+    original: Vec<Instruction>,
+    /// Number of times the program was modified (counted by `optimize` and toggles)
+    generation: u64,
+    /// Optimizable loop starting at each address
+    loops: Vec<Option<Loop>>,
+    /// Trace being recorded
+    trace: Option<Box<Trace<W>>>,
+    /// Profile being collected
+    profile: Option<Box<Profile>>,
+}
+
+// Equal CPUs may have modified their programs a different number of times
+impl<W: Word> PartialEq for Cpu<W> {
+    fn eq(&self, other: &Cpu<W>) -> bool {
+        self.instructions == other.instructions && self.ip == other.ip && self.regs == other.regs &&
+            self.output == other.output && self.input == other.input && self.overflow == other.overflow &&
+            self.fault == other.fault && self.shortcuts == other.shortcuts && self.names == other.names && self.original == other.original &&
+            self.loops == other.loops && self.trace == other.trace && self.profile == other.profile
+    }
+}
+
+impl<W: Word> Eq for Cpu<W> {}
+
+impl<W: Word> From<Vec<Instruction>> for Cpu<W> {
+    fn from(instructions: Vec<Instruction>) -> Cpu<W> {
+        let loops = optimizer::find_loops(&instructions);
+        let regs = vec![W::default(); cmp::max(4, registers_used(&instructions))];
+        Cpu { original: instructions.clone(), instructions: instructions, ip: 0, regs: regs, output: vec![], input: VecDeque::new(), overflow: Overflow::Wrap, fault: None, shortcuts: true, names: Dialect::default().registers.to_owned(), generation: 0, loops: loops, trace: None, profile: None }
+    }
+}
+
+impl Cpu {
+    /// Create new CPU with a program of the given dialect
+    pub fn new(input: &str, dialect: &Dialect) -> Result<Cpu, ParseError> {
+        Cpu::with_overflow(input, dialect, Overflow::Wrap)
+    }
+}
+
+impl<W: Word> Cpu<W> {
+    /// Create new CPU with a program of the given dialect, that treats
+    /// arithmetic overflow as given. The CPU has a register for every
+    /// register name of the dialect.
+    pub fn with_overflow(input: &str, dialect: &Dialect, overflow: Overflow) -> Result<Cpu<W>, ParseError> {
+        let instructions = parse_error::parse_lines(input, |line| {
+            let ins = Instruction::parse_line(line, dialect)?;
+            // Immediate values must fit into a register
+            let fits = |x: i64| x as i128 >= W::MIN && x as i128 <= W::MAX;
+            match line.split_whitespace().skip(1).find(|word| word.parse().map(|x| !fits(x)).unwrap_or(false)) {
+                Some(word) => Err(ParseError::of(line, word)),
+                None => Ok(ins),
+            }
+        })?;
+        let mut cpu = Cpu::from(instructions);
+        cpu.regs = vec![W::default(); cmp::max(registers_used(&cpu.instructions), dialect.registers.chars().count())];
+        cpu.overflow = overflow;
+        cpu.names = dialect.registers.to_owned();
+        Ok(cpu)
+    }
+
+    /// Reset CPU. The program is restored to how it was loaded, undoing
+    /// any toggles.
+    pub fn reset(&mut self) {
+        if self.instructions != self.original {
+            self.instructions = self.original.clone();
+            self.optimize();
+        }
+        self.ip = 0;
+        for reg in self.regs.iter_mut() { *reg = W::default(); }
+        self.output = vec![];
+        self.input.clear();
+        self.fault = None;
+        if self.trace.is_some() {
+            self.start_trace();
+        }
+    }
+
+    /// Name of a register
+    pub fn register_name(&self, x: u8) -> char {
+        named_register(&self.names, x)
+    }
+
+    /// Analyze the program for optimizable loops. Needs to be called after
+    /// instructions have been modified directly.
+    pub fn optimize(&mut self) {
+        self.loops = optimizer::find_loops(&self.instructions);
+        self.generation += 1;
+    }
+
+    /// Loops that are optimized when running the program
+    pub fn optimized_loops(&self) -> Vec<&Loop> {
+        self.loops.iter().filter_map(|l| l.as_ref()).collect()
+    }
+
+    /// Toggle the instruction at the given address (if it is inside the program)
+    fn toggle(&mut self, addr: usize) -> bool {
+        if addr < self.instructions.len() {
+            self.instructions[addr] = self.instructions[addr].toggle();
+            optimizer::update_loops(&mut self.loops, &self.instructions, addr);
+            self.generation += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Add a value to a register, as done by the instruction at the given
+    /// address. Returns false if the overflow was trapped, which stops the
+    /// program.
+    fn add(&mut self, addr: usize, x: u8, n: i128) -> bool {
+        match self.overflow.add(self.regs[x as usize], n) {
+            Some(value) => {
+                self.regs[x as usize] = value;
+                true
+            },
+            None => {
+                self.fault = Some(Fault::Overflow(OverflowError { addr: addr, register: x, name: self.register_name(x) }));
+                false
+            },
+        }
+    }
+
+    /// Run the loop starting at the current address in a single step, if it
+    /// is optimizable. Returns true if it was run.
+    fn run_loop(&mut self) -> bool {
+        if !self.shortcuts {
+            return false;
+        }
+        let regs = match self.loops[self.ip] {
+            Some(ref l) => l.apply(&self.regs, self.overflow).map(|regs| (regs, l.end)),
+            None => None,
+        };
+        match regs {
+            Some((regs, end)) => {
+                if let Some(ref mut profile) = self.profile {
+                    let l = self.loops[self.ip].as_ref().unwrap();
+                    profile.shortcut(end, l.cost(&self.regs));
+                }
+                self.regs = regs;
+                self.ip = end + 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns true if the program has finished (or was stopped by a
+    /// fault, or waits for input)
+    pub fn is_done(&self) -> bool {
+        self.ip >= self.instructions.len() || self.fault.is_some() || self.is_waiting()
+    }
+
+    /// Returns true if the program waits for input. It continues when a
+    /// value is added to the input queue.
+    pub fn is_waiting(&self) -> bool {
+        match self.instructions.get(self.ip) {
+            Some(&Instruction::Inp(_)) => self.input.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Step program. Returns true if done
+    pub fn step(&mut self) -> bool {
+        if self.is_done() {
+            true
+        } else {
+            self.traced(|cpu| cpu.run_loop() || { cpu.execute(); false });
+            false
+        }
+    }
+
+    /// Step a single instruction, without running optimized loops. Returns true if done
+    pub fn step_instruction(&mut self) -> bool {
+        if self.is_done() {
+            true
+        } else {
+            self.traced(|cpu| { cpu.execute(); false });
+            false
+        }
+    }
+
+    /// Execute the instruction at the current address. A fault leaves the
+    /// instruction pointer at the instruction.
+    fn execute(&mut self) {
+        let ip = self.ip;
+        let ins = self.instructions[ip];
+        match ins {
+            Instruction::Cpy(_, Value::Immediate(_)) => { /* ignore invalid cpy */ },
+            Instruction::Cpy(ref v, Value::Register(y)) => self.regs[y as usize] = v.get(&self.regs),
+            Instruction::Inc(x) => if !self.add(ip, x, 1) { return; },
+            Instruction::Dec(x) => if !self.add(ip, x, -1) { return; },
+            Instruction::Jnz(ref v1, ref v2) => {
+                if v1.get(&self.regs) != W::default() {
+                    match offset(ip, v2.get(&self.regs).into()) {
+                        Ok(addr) => self.ip = addr,
+                        Err(target) => {
+                            self.fault = Some(Fault::Jump { addr: ip, target: target });
+                            return;
+                        },
+                    }
+                } else {
+                    self.ip += 1
+                }
+            },
+            Instruction::Tgl(x) => match offset(ip, self.regs[x as usize].into()) {
+                Ok(addr) => { self.toggle(addr); },
+                Err(target) => {
+                    self.fault = Some(Fault::Toggle { addr: ip, target: target });
+                    return;
+                },
+            },
+            Instruction::Out(ref v) => self.output.push(v.get(&self.regs)),
+            Instruction::Inp(x) => self.regs[x as usize] = self.input.pop_front().unwrap(),
+        }
+        match ins {
+            Instruction::Jnz(_, _) => (),
+            _ => self.ip += 1,
+        }
+        if let Some(ref mut profile) = self.profile {
+            profile.count(ip, self.ip != ip + 1);
+        }
+    }
+
+    /// Run program
+    pub fn run(&mut self) {
+        while !self.step() { }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        assert_eq!("inc a".parse(), Ok(Instruction::Inc(0)));
+        assert_eq!(Instruction::parse("cpy 41 a\ninc b\ndec c\njnz d 2\njnz 1 -2", &Dialect::default()), Ok(vec![
+            Instruction::Cpy(Value::Immediate(41), Value::Register(0)),
+            Instruction::Inc(1),
+            Instruction::Dec(2),
+            Instruction::Jnz(Value::Register(3), Value::Immediate(2)),
+            Instruction::Jnz(Value::Immediate(1), Value::Immediate(-2)),
+        ]));
+    }
+
+    #[test]
+    fn parsing_invalid() {
+        let err = Instruction::parse("inc a\ncpy 1 x\ndec b", &Dialect::default()).unwrap_err();
+        assert_eq!(err.to_string(), "invalid input at line 2, column 7:\ncpy 1 x\n      ^");
+        assert_eq!(Instruction::parse_line("jnz a", &Dialect::default()).unwrap_err().column, 6);
+        assert_eq!(Instruction::parse_line("tgl a", &Dialect::day12()).unwrap_err().column, 1);
+    }
+
+    #[test]
+    fn parsing_dialects() {
+        assert_eq!(Instruction::parse_line("tgl a", &Dialect::day23()), Ok(Instruction::Tgl(0)));
+        assert!(Instruction::parse_line("tgl a", &Dialect::day12()).is_err());
+        assert!(Instruction::parse_line("tgl a", &Dialect::day25()).is_err());
+        assert_eq!(Instruction::parse_line("out b", &Dialect::day25()), Ok(Instruction::Out(Value::Register(1))));
+        assert!(Instruction::parse_line("out b", &Dialect::day12()).is_err());
+        assert!(Instruction::parse_line("out b", &Dialect::day23()).is_err());
+        assert_eq!(Instruction::parse_line("cpy a 1", &Dialect::day23()), Ok(Instruction::Cpy(Value::Register(0), Value::Immediate(1))));
+        assert!(Instruction::parse_line("cpy a 1", &Dialect::day12()).is_err());
+        assert_eq!(Instruction::parse_line("inp c", &Dialect::default()), Ok(Instruction::Inp(2)));
+        assert!(Instruction::parse_line("inp c", &Dialect::day25()).is_err());
+    }
+
+    #[test]
+    fn parsing_registers() {
+        let dialect = Dialect::default().with_registers("abcdef");
+        assert_eq!(Instruction::parse_line("cpy f e", &dialect), Ok(Instruction::Cpy(Value::Register(5), Value::Register(4))));
+        assert!(Instruction::parse_line("inc f", &Dialect::default()).is_err());
+        let dialect = Dialect::default().with_registers("xy");
+        assert_eq!(Instruction::parse_line("jnz y x", &dialect), Ok(Instruction::Jnz(Value::Register(1), Value::Register(0))));
+        assert!(Instruction::parse_line("inc a", &dialect).is_err());
+        assert_eq!(registers_used(&Instruction::parse("inc a\ncpy 1 f", &Dialect::default().with_registers("abcdef")).unwrap()), 6);
+    }
+
+    #[test]
+    fn parsing_large_immediates() {
+        assert_eq!(Instruction::parse_line("cpy -2147483648 a", &Dialect::default()), Ok(Instruction::Cpy(Value::Immediate(-2147483648), Value::Register(0))));
+        let cpu: Cpu<i64> = Cpu::with_overflow("cpy 4294967296 a\nout -9223372036854775808", &Dialect::default(), Overflow::Wrap).unwrap();
+        assert_eq!(cpu.instructions[0], Instruction::Cpy(Value::Immediate(1 << 32), Value::Register(0)));
+        assert_eq!(Cpu::new("cpy -2147483648 a", &Dialect::default()).map(|cpu| cpu.instructions.len()), Ok(1));
+        let err = Cpu::new("inc a\ncpy 2147483648 a", &Dialect::default()).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 5));
+        assert_eq!(Instruction::parse_line("cpy 9223372036854775808 a", &Dialect::default()).unwrap_err().column, 23);
+        let mut cpu: Cpu<i128> = Cpu::with_overflow("cpy 9223372036854775807 a\ninc a", &Dialect::default(), Overflow::Trap).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], 1 << 63);
+    }
+
+    #[test]
+    fn showing_registers() {
+        let dialect = Dialect::default().with_registers("xyzw");
+        let program = "cpy 2 y\ninc x\ndec w\njnz z x\ntgl y\nout w\ninp z";
+        let instructions = Instruction::parse(program, &dialect).unwrap();
+        let lines: Vec<String> = instructions.iter().map(|ins| ins.show(dialect.registers)).collect();
+        assert_eq!(lines.join("\n"), program);
+        assert_eq!(instructions[1].to_string(), "inc a");
+    }
+
+    #[test]
+    fn running() {
+        let mut cpu = Cpu::new("cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a", &Dialect::default()).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], 42);
+    }
+
+    #[test]
+    fn running_toggled() {
+        let mut cpu = Cpu::new("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a", &Dialect::day23()).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], 3);
+        assert_eq!(cpu.instructions[4], Instruction::Jnz(Value::Immediate(1), Value::Register(0)));
+        cpu.reset();
+        assert_eq!(cpu.instructions[4], Instruction::Cpy(Value::Immediate(1), Value::Register(0)));
+        cpu.run();
+        assert_eq!(cpu.regs[0], 3);
+    }
+
+    #[test]
+    fn running_output() {
+        let mut cpu = Cpu::new("cpy 2 a\nout a\ndec a\njnz a -2\nout 7", &Dialect::day25()).unwrap();
+        cpu.run();
+        assert_eq!(cpu.output, vec![2, 1, 7]);
+    }
+
+    #[test]
+    fn running_registers() {
+        let mut cpu = Cpu::new("cpy 3 f\ninc e\ndec f\njnz f -2\ncpy e a", &Dialect::default().with_registers("abcdef")).unwrap();
+        assert_eq!(cpu.regs.len(), 6);
+        cpu.run();
+        assert_eq!(cpu.regs, [3, 0, 0, 0, 3, 0]);
+        let mut cpu = Cpu::new("inc x\njnz x 2\ninc y", &Dialect::default().with_registers("xy")).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs, [1, 0]);
+    }
+
+    #[test]
+    fn running_overflow() {
+        let program = "cpy 65536 b\ncpy b d\ncpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5\ndec a";
+        let mut cpu = Cpu::new(program, &Dialect::default()).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], -1);
+        let mut cpu: Cpu<i64> = Cpu::with_overflow(program, &Dialect::default(), Overflow::Trap).unwrap();
+        assert_eq!(cpu.try_run(), Ok(()));
+        assert_eq!(cpu.regs[0], (1 << 32) - 1);
+        let mut cpu: Cpu<i128> = Cpu::with_overflow(program, &Dialect::default(), Overflow::Wrap).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], (1 << 32) - 1);
+        let mut cpu: Cpu<i32> = Cpu::with_overflow(program, &Dialect::default(), Overflow::Trap).unwrap();
+        assert_eq!(cpu.try_run(), Err(Fault::Overflow(OverflowError { addr: 3, register: 0, name: 'a' })));
+        assert_eq!((cpu.ip, cpu.regs[0]), (3, i32::max_value()));
+        assert_eq!(cpu.fault.unwrap().to_string(), "overflow of register a at line 4");
+        let mut cpu: Cpu<i32> = Cpu::with_overflow("cpy 2147483646 a\ninc a\ninc a\ninc a\ndec b", &Dialect::default(), Overflow::Saturate).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs, [i32::max_value(), -1, 0, 0]);
+        let mut cpu: Cpu<i32> = Cpu::with_overflow("cpy -2147483647 a\ndec a\ndec a\ninc a", &Dialect::default(), Overflow::Saturate).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], i32::min_value() + 1);
+        let mut cpu: Cpu<i32> = Cpu::with_overflow("cpy 2147483647 y\ninc y", &Dialect::default().with_registers("xy"), Overflow::Trap).unwrap();
+        assert_eq!(cpu.try_run().unwrap_err().to_string(), "overflow of register y at line 2");
+        cpu.reset();
+        cpu.run_compiled();
+        assert_eq!(cpu.fault.unwrap().to_string(), "overflow of register y at line 2");
+    }
+}
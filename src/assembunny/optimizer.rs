@@ -0,0 +1,594 @@
+//! Loop optimizer. Analyzes a program for loops whose effect on registers
+//! can be calculated in closed form (counted loops with affine register
+//! updates, possibly nested, and a division/modulo idiom). When the CPU
+//! enters such a loop, the whole loop is replaced by a single update.
+
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::{fmt, iter};
+use super::{register_name, registers_used, Instruction, Value};
+use super::word::{Overflow, Word};
+
+
+/// Polynomial over register values at loop entry. Maps monomials (sorted
+/// list of register numbers, empty for the constant term) to coefficients.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Poly(BTreeMap<Vec<u8>, i64>);
+
+impl fmt::Display for Poly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("0");
+        }
+        for (i, (monomial, &coef)) in self.0.iter().enumerate() {
+            match (i, coef < 0) {
+                (0, true) => f.write_str("-")?,
+                (_, true) => f.write_str(" - ")?,
+                (0, false) => (),
+                (_, false) => f.write_str(" + ")?,
+            }
+            if monomial.is_empty() || coef.abs() != 1 {
+                write!(f, "{}", coef.abs())?;
+                if !monomial.is_empty() { f.write_str("*")?; }
+            }
+            for (j, &r) in monomial.iter().enumerate() {
+                if j > 0 { f.write_str("*")?; }
+                write!(f, "{}", register_name(r))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Poly {
+    /// Constant polynomial
+    fn constant(c: i64) -> Poly {
+        let mut poly = Poly::default();
+        if c != 0 { poly.0.insert(vec![], c); }
+        poly
+    }
+
+    /// Polynomial of a single register
+    fn register(r: u8) -> Poly {
+        let mut poly = Poly::default();
+        poly.0.insert(vec![r], 1);
+        poly
+    }
+
+    /// Polynomial of an immediate or register value, given the current
+    /// polynomials of all registers
+    fn value(v: Value, state: &[Poly]) -> Poly {
+        match v {
+            Value::Immediate(x) => Poly::constant(x as i64),
+            Value::Register(r) => state[r as usize].clone(),
+        }
+    }
+
+    /// Sum of two polynomials. Returns None if a coefficient overflows.
+    fn plus(&self, other: &Poly) -> Option<Poly> {
+        let mut poly = self.clone();
+        for (monomial, &coef) in &other.0 {
+            let sum = poly.0.get(monomial).cloned().unwrap_or(0).checked_add(coef)?;
+            if sum == 0 { poly.0.remove(monomial); } else { poly.0.insert(monomial.clone(), sum); }
+        }
+        Some(poly)
+    }
+
+    /// Difference of two polynomials. Returns None if a coefficient overflows.
+    fn minus(&self, other: &Poly) -> Option<Poly> {
+        self.plus(&other.times(&Poly::constant(-1))?)
+    }
+
+    /// Product of two polynomials. Returns None if a coefficient overflows.
+    fn times(&self, other: &Poly) -> Option<Poly> {
+        let mut poly = Poly::default();
+        for (m1, &c1) in &self.0 {
+            for (m2, &c2) in &other.0 {
+                let mut monomial: Vec<u8> = m1.iter().chain(m2.iter()).cloned().collect();
+                monomial.sort();
+                let mut term = Poly::default();
+                term.0.insert(monomial, c1.checked_mul(c2)?);
+                poly = poly.plus(&term)?;
+            }
+        }
+        Some(poly)
+    }
+
+    /// Substitute registers by the given polynomials. Returns None if a
+    /// coefficient overflows.
+    fn substitute(&self, state: &[Poly]) -> Option<Poly> {
+        let mut poly = Poly::default();
+        for (monomial, &coef) in &self.0 {
+            let mut term = Poly::constant(coef);
+            for &r in monomial {
+                term = term.times(&state[r as usize])?;
+            }
+            poly = poly.plus(&term)?;
+        }
+        Some(poly)
+    }
+
+    /// Check if the polynomial only uses registers of the given set
+    fn uses_only(&self, regs: &[bool]) -> bool {
+        self.0.keys().all(|monomial| monomial.iter().all(|&r| regs[r as usize]))
+    }
+
+    /// Returns the value if the polynomial is constant
+    fn as_constant(&self) -> Option<i64> {
+        match self.0.len() {
+            0 => Some(0),
+            1 => self.0.get(&vec![]).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Monomials and their coefficients
+    pub fn terms<'a>(&'a self) -> btree_map::Iter<'a, Vec<u8>, i64> {
+        self.0.iter()
+    }
+
+    /// Evaluate with the given register values. Returns None if the result
+    /// doesn't fit into `i128`.
+    pub fn eval(&self, regs: &[i128]) -> Option<i128> {
+        self.0.iter().fold(Some(0), |sum, (monomial, &coef)| {
+            let term = monomial.iter().fold(Some(coef as i128), |prod, &r| prod.and_then(|prod| prod.checked_mul(regs[r as usize])));
+            sum.and_then(|sum| term.and_then(|term| sum.checked_add(term)))
+        })
+    }
+
+    /// Evaluate with the given register values. Wraps around on overflow like
+    /// the CPU would have done by stepping.
+    pub fn eval_wrapping(&self, regs: &[i128]) -> i128 {
+        self.0.iter().fold(0, |sum, (monomial, &coef)| {
+            sum.wrapping_add(monomial.iter().fold(coef as i128, |prod, &r| prod.wrapping_mul(regs[r as usize])))
+        })
+    }
+}
+
+
+/// Update of a register by a loop iteration
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Update {
+    /// Register is left unchanged
+    Keep,
+    /// Register is increased by the given (loop invariant) amount
+    Add(Poly),
+    /// Register is set to the given (loop invariant) value
+    Set(Poly),
+}
+
+
+/// Closed-form effect of a loop
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Effect {
+    /// Loop counting the counter register to zero in steps of the given size.
+    /// The counter is checked before (while) or after (do-while) each
+    /// iteration. Guards must be non-negative for the loop to be optimizable.
+    /// The cost is the number of instructions run per iteration (if it is
+    /// the same for every iteration).
+    Counted { counter: u8, step: i64, check_first: bool, updates: Vec<Update>, guards: Vec<Poly>, cost: Option<Poly> },
+    /// Loop dividing the dividend register by a constant divisor
+    DivMod { quotient: u8, dividend: u8, remainder: u8, divisor: i64 },
+}
+
+
+/// An optimizable loop
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Loop {
+    /// Address of first instruction (loop entry)
+    pub start: usize,
+    /// Address of last instruction
+    pub end: usize,
+    /// Effect of the loop
+    pub effect: Effect,
+}
+
+impl fmt::Display for Loop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "lines {}-{}: ", self.start + 1, self.end + 1)?;
+        match self.effect {
+            Effect::Counted { counter, step, ref updates, ref guards, .. } => {
+                // Number of iterations (negated if the counter counts up)
+                let n = match step.abs() {
+                    1 => format!("{}", register_name(counter)),
+                    s => format!("{}/{}", register_name(counter), s),
+                };
+                // Updates are shown in the order they apply. All of them use
+                // the values before the loop, so the counter is reset last.
+                let order = (0..updates.len()).filter(|&r| r != counter as usize).chain(iter::once(counter as usize));
+                let mut first = true;
+                for r in order {
+                    if updates[r] == Update::Keep { continue; }
+                    if !first { f.write_str(", ")?; }
+                    let name = register_name(r as u8);
+                    match updates[r] {
+                        Update::Keep => (),
+                        _ if r == counter as usize => write!(f, "{} = 0", name)?,
+                        Update::Add(ref k) => {
+                            // Show negative amounts as subtraction
+                            let (negative, k) = match k.times(&Poly::constant(-1)) {
+                                Some(negated) if k.terms().all(|(_, &coef)| coef < 0) => (step < 0, negated),
+                                _ => (step > 0, k.clone()),
+                            };
+                            let op = if negative { "-=" } else { "+=" };
+                            match (k.as_constant(), k.0.len()) {
+                                (Some(1), _) => write!(f, "{} {} {}", name, op, n)?,
+                                (_, 1) => write!(f, "{} {} {}*{}", name, op, n, k)?,
+                                _ => write!(f, "{} {} {}*({})", name, op, n, k)?,
+                            }
+                        },
+                        Update::Set(ref e) => write!(f, "{} = {}", name, e)?,
+                    }
+                    first = false;
+                }
+                for guard in guards {
+                    // Show guard as `expr >= constant`
+                    let mut expr = guard.clone();
+                    let constant = expr.0.remove(&vec![]).unwrap_or(0);
+                    write!(f, " if {} >= {}", expr, -(constant as i128))?;
+                }
+                Ok(())
+            },
+            Effect::DivMod { quotient, dividend, remainder, divisor } => {
+                let (q, d, r) = (register_name(quotient), register_name(dividend), register_name(remainder));
+                write!(f, "{} += {}/{}, {} = {} - {}%{}, {} = 0", q, d, divisor, r, divisor, d, divisor, d)
+            },
+        }
+    }
+}
+
+impl Loop {
+    /// Apply the loop to the given register values. Returns the register
+    /// values after the loop, or None if the loop can't be optimized for
+    /// these values (e.g. because it wouldn't terminate). Unless overflow
+    /// wraps around, the loop is only optimized if all results fit, so that
+    /// stepping it saturates or traps at the right instruction instead.
+    pub fn apply<W: Word>(&self, regs: &[W], overflow: Overflow) -> Option<Vec<W>> {
+        let values: Vec<i128> = regs.iter().map(|&x| x.into()).collect();
+        let fit = |wrapped: i128, exact: Option<i128>| match overflow {
+            Overflow::Wrap => Some(W::truncate(wrapped)),
+            _ => exact.filter(|&x| x >= W::MIN && x <= W::MAX).map(W::truncate),
+        };
+        let mut result = regs.to_vec();
+        match self.effect {
+            Effect::Counted { counter, step, check_first, ref updates, ref guards, .. } => {
+                if guards.iter().any(|guard| guard.eval(&values).map(|g| g < 0).unwrap_or(true)) {
+                    return None;
+                }
+                let (r, step) = (values[counter as usize], step as i128);
+                let n = -r / step;
+                if r % step != 0 || n < if check_first { 0 } else { 1 } {
+                    return None;
+                }
+                if n > 0 {
+                    for (x, update) in updates.iter().enumerate() {
+                        result[x] = match *update {
+                            Update::Keep => continue,
+                            Update::Add(ref k) => fit(
+                                values[x].wrapping_add(n.wrapping_mul(k.eval_wrapping(&values))),
+                                k.eval(&values).and_then(|k| n.checked_mul(k)).and_then(|d| values[x].checked_add(d)),
+                            )?,
+                            Update::Set(ref e) => fit(e.eval_wrapping(&values), e.eval(&values))?,
+                        };
+                    }
+                }
+            },
+            Effect::DivMod { quotient, dividend, remainder, divisor } => {
+                let (q, d, k) = (values[quotient as usize], values[dividend as usize], divisor as i128);
+                if d < 0 {
+                    return None;
+                }
+                result[quotient as usize] = fit(q.wrapping_add(d / k), q.checked_add(d / k))?;
+                result[remainder as usize] = W::truncate(k - d % k);
+                result[dividend as usize] = W::default();
+            },
+        }
+        Some(result)
+    }
+
+    /// Number of instructions that running the loop with the given register
+    /// values takes, or None if unknown
+    pub fn cost<W: Word>(&self, regs: &[W]) -> Option<u64> {
+        let values: Vec<i128> = regs.iter().map(|&x| x.into()).collect();
+        match self.effect {
+            Effect::Counted { counter, step, check_first, cost: Some(ref cost), .. } => {
+                let n = -values[counter as usize] / step as i128;
+                Some(n.wrapping_mul(cost.eval_wrapping(&values)).wrapping_add(if check_first { 2 } else { 0 }) as u64)
+            },
+            Effect::Counted { .. } => None,
+            Effect::DivMod { dividend, divisor, .. } => {
+                // Every decrement takes 4 instructions, every division round
+                // 3 more, and the final (partial) round 3 more
+                let d = values[dividend as usize] as u64;
+                Some(4 * d + 3 * (d / divisor as u64) + 3)
+            },
+        }
+    }
+}
+
+
+/// Detect the division idiom starting at the given address:
+///
+/// ```text
+///     cpy K c
+///     jnz b 2
+///     jnz 1 6
+///     dec b
+///     dec c
+///     jnz c -4
+///     inc a
+///     jnz 1 -7
+/// ```
+fn detect_divmod(ins: &[Instruction], start: usize, limit: usize) -> Option<Loop> {
+    use self::Instruction::*;
+    use self::Value::*;
+    if start + 8 > limit {
+        return None;
+    }
+    match (ins[start], ins[start+1], ins[start+2], ins[start+3], ins[start+4], ins[start+5], ins[start+6], ins[start+7]) {
+        (Cpy(Immediate(k), Register(c1)), Jnz(Register(b1), Immediate(2)), Jnz(Immediate(x), Immediate(6)),
+         Dec(b2), Dec(c2), Jnz(Register(c3), Immediate(-4)), Inc(a), Jnz(Immediate(y), Immediate(-7)))
+            if k > 0 && x != 0 && y != 0 && b1 == b2 && c1 == c2 && c1 == c3 && a != b1 && a != c1 && b1 != c1 =>
+            Some(Loop { start: start, end: start + 7, effect: Effect::DivMod { quotient: a, dividend: b1, remainder: c1, divisor: k } }),
+        _ => None,
+    }
+}
+
+/// Symbolically execute the instructions in the given address range.
+/// Returns polynomials of all registers after execution (in terms of the
+/// register values before execution), guards that must be non-negative
+/// and the number of instructions run (if known).
+fn execute(ins: &[Instruction], from: usize, to: usize) -> Option<(Vec<Poly>, Vec<Poly>, Option<Poly>)> {
+    let mut state: Vec<Poly> = (0..registers_used(ins) as u8).map(Poly::register).collect();
+    let mut guards = Vec::new();
+    let mut cost = Some(Poly::constant(0));
+    let mut ip = from;
+    while ip < to {
+        if let Some(inner) = summarize(ins, ip, to) {
+            match inner.effect {
+                Effect::Counted { counter, step, check_first, ref updates, guards: ref inner_guards, cost: ref inner_cost } if step.abs() == 1 => {
+                    // Number of iterations (as polynomial, since step is +/-1)
+                    let n = state[counter as usize].times(&Poly::constant(-step))?;
+                    cost = match (cost, inner_cost.as_ref()) {
+                        (Some(cost), Some(inner_cost)) => inner_cost.substitute(&state)
+                            .and_then(|inner_cost| n.times(&inner_cost))
+                            .and_then(|inner_cost| cost.plus(&inner_cost))
+                            .and_then(|cost| cost.plus(&Poly::constant(if check_first { 2 } else { 0 }))),
+                        _ => None,
+                    };
+                    let sets = updates.iter().any(|update| match *update { Update::Set(_) => true, _ => false });
+                    for guard in inner_guards {
+                        guards.push(guard.substitute(&state)?);
+                    }
+                    guards.push(if check_first && !sets { n.clone() } else { n.minus(&Poly::constant(1))? });
+                    state = updates.iter().enumerate().map(|(x, update)| match *update {
+                        Update::Keep => Some(state[x].clone()),
+                        Update::Add(ref k) => k.substitute(&state).and_then(|k| n.times(&k)).and_then(|delta| state[x].plus(&delta)),
+                        Update::Set(ref e) => e.substitute(&state),
+                    }).collect::<Option<_>>()?;
+                },
+                _ => return None,
+            }
+            ip = inner.end + 1;
+            continue;
+        }
+        match ins[ip] {
+            Instruction::Cpy(_, Value::Immediate(_)) => (),
+            Instruction::Cpy(v, Value::Register(y)) => state[y as usize] = Poly::value(v, &state),
+            Instruction::Inc(x) => state[x as usize] = state[x as usize].plus(&Poly::constant(1))?,
+            Instruction::Dec(x) => state[x as usize] = state[x as usize].minus(&Poly::constant(1))?,
+            Instruction::Jnz(_, _) | Instruction::Tgl(_) | Instruction::Out(_) | Instruction::Inp(_) => return None,
+        }
+        cost = cost.and_then(|cost| cost.plus(&Poly::constant(1)));
+        ip += 1;
+    }
+    Some((state, guards, cost))
+}
+
+/// Summarize a loop with the given body and counter register
+fn summarize_counted(ins: &[Instruction], start: usize, end: usize, body: (usize, usize), counter: u8, check_first: bool) -> Option<Loop> {
+    let (state, guards, cost) = match execute(ins, body.0, body.1) {
+        Some(result) => result,
+        None => return None,
+    };
+    let invariant: Vec<bool> = state.iter().enumerate().map(|(x, poly)| *poly == Poly::register(x as u8)).collect();
+    let step = match state[counter as usize].minus(&Poly::register(counter))?.as_constant() {
+        Some(0) | None => return None,
+        Some(step) => step,
+    };
+    if !guards.iter().all(|guard| guard.uses_only(&invariant)) {
+        return None;
+    }
+    // Constant guards either always or never hold
+    if guards.iter().any(|guard| guard.as_constant().map(|c| c < 0).unwrap_or(false)) {
+        return None;
+    }
+    let guards: Vec<Poly> = guards.into_iter().filter(|guard| guard.as_constant().is_none()).collect();
+    let mut updates = Vec::new();
+    for x in 0..state.len() {
+        let delta = state[x].minus(&Poly::register(x as u8))?;
+        updates.push(if invariant[x] {
+            Update::Keep
+        } else if delta.uses_only(&invariant) {
+            Update::Add(delta)
+        } else if state[x].uses_only(&invariant) {
+            Update::Set(state[x].clone())
+        } else {
+            return None;
+        });
+    }
+    // Iterations of while loops run two extra jumps, do-while loops one
+    let cost = cost.and_then(|cost| cost.plus(&Poly::constant(if check_first { 2 } else { 1 }))).filter(|cost| cost.uses_only(&invariant));
+    Some(Loop { start: start, end: end, effect: Effect::Counted { counter: counter, step: step, check_first: check_first, updates: updates, guards: guards, cost: cost } })
+}
+
+/// Find the largest optimizable loop that starts at the given address and
+/// ends before the given limit
+fn summarize(ins: &[Instruction], start: usize, limit: usize) -> Option<Loop> {
+    if let Some(divmod) = detect_divmod(ins, start, limit) {
+        return Some(divmod);
+    }
+    for end in (start..limit).rev() {
+        match ins[end] {
+            // do-while loop: body; jnz r <start>
+            Instruction::Jnz(Value::Register(r), Value::Immediate(ofs)) if end as isize + ofs as isize == start as isize => {
+                if let Some(l) = summarize_counted(ins, start, end, (start, end), r, false) {
+                    return Some(l);
+                }
+            },
+            // while loop: jnz r 2; jnz 1 <exit>; body; jnz 1 <start>
+            Instruction::Jnz(Value::Immediate(x), Value::Immediate(ofs)) if x != 0 && end as isize + ofs as isize == start as isize && end >= start + 2 => {
+                match (ins[start], ins[start+1]) {
+                    (Instruction::Jnz(Value::Register(r), Value::Immediate(2)), Instruction::Jnz(Value::Immediate(y), Value::Immediate(exit)))
+                        if y != 0 && start as isize + 1 + exit as isize == end as isize + 1 => {
+                        if let Some(l) = summarize_counted(ins, start, end, (start + 2, end), r, true) {
+                            return Some(l);
+                        }
+                    },
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Find optimizable loops in a program. Returns a vector with the loop
+/// starting at each address (if any).
+pub fn find_loops(instructions: &[Instruction]) -> Vec<Option<Loop>> {
+    (0..instructions.len()).map(|start| summarize(instructions, start, instructions.len())).collect()
+}
+
+/// Update the loops found by `find_loops` after the instruction at the given
+/// address has been modified. Only loops that may contain the address are
+/// analyzed again: loops found before that contain it, loops closed by a
+/// backward jump across it and division idioms around it.
+pub fn update_loops(loops: &mut [Option<Loop>], instructions: &[Instruction], addr: usize) {
+    let mut starts: BTreeSet<usize> = loops.iter().filter_map(|l| l.as_ref())
+        .filter(|l| l.start <= addr && addr <= l.end).map(|l| l.start).collect();
+    for end in addr..instructions.len() {
+        if let Instruction::Jnz(_, Value::Immediate(ofs)) = instructions[end] {
+            let start = end as i64 + ofs;
+            if start >= 0 && start as usize <= addr {
+                starts.insert(start as usize);
+            }
+        }
+    }
+    starts.extend(addr.saturating_sub(7)..addr + 1);
+    for start in starts {
+        loops[start] = summarize(instructions, start, instructions.len());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Cpu, Dialect};
+
+    fn loops(program: &str) -> Vec<String> {
+        let instructions = Instruction::parse(program, &Dialect::default()).unwrap();
+        find_loops(&instructions).iter().filter_map(|l| l.as_ref()).map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn finding_loops() {
+        assert_eq!(loops("inc a\ndec c\njnz c -2"), vec!["lines 1-3: a += c, c = 0"]);
+        assert_eq!(loops("dec c\ninc a\njnz c -2"), vec!["lines 1-3: a += c, c = 0"]);
+        assert_eq!(loops("dec d\ninc c\njnz d -2"), vec!["lines 1-3: c += d, d = 0"]);
+        assert_eq!(loops("inc a\ninc c\njnz c -2"), vec!["lines 1-3: a -= c, c = 0"]);
+        assert_eq!(loops("dec a\ndec a\ninc c\njnz c -3"), vec!["lines 1-4: a += c*2, c = 0"]);
+        assert_eq!(loops("cpy 0 b\ninc d\ndec c\njnz c -3"), vec!["lines 1-4: b = 0, d += c, c = 0"]);
+        assert_eq!(loops("cpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5"), vec![
+            "lines 1-6: a += d*b, c = 0, d = 0 if b >= 1",
+            "lines 2-4: a += c, c = 0",
+        ]);
+        assert_eq!(loops("cpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7"), vec![
+            "lines 1-8: a += b/2, c = 2 - b%2, b = 0",
+        ]);
+        assert_eq!(loops("jnz c 2\njnz 1 4\ndec b\ndec c\njnz 1 -4"), vec!["lines 1-5: b -= c, c = 0"]);
+        assert_eq!(loops("inc a\ndec c\ndec c\njnz c -3"), vec!["lines 1-4: a += c/2, c = 0"]);
+        assert_eq!(loops("inc a\ntgl c\ndec c\njnz c -3"), Vec::<String>::new());
+        assert_eq!(loops("cpy a c\ninc a\ndec b\njnz b -2\ncpy c b\ndec d\njnz d -6"), vec!["lines 2-4: a += b, b = 0"]);
+    }
+
+    #[test]
+    fn finding_overflowing_loops() {
+        // The outer loop would add 3*(2^31-1)^2 per iteration, which doesn't fit i64
+        assert_eq!(loops("cpy 2147483647 d\ncpy 2147483647 c\ncpy 2147483647 b\ninc a\ninc a\ninc a\ndec b\njnz b -4\ndec c\njnz c -7\ndec d\njnz d -10"), vec![
+            "lines 3-10: a += c*6442450941, b = 0, c = 0",
+            "lines 4-8: a += b*3, b = 0",
+        ]);
+    }
+
+    #[test]
+    fn updating_loops() {
+        for program in &[
+            include_str!("../day23.txt"),
+            "cpy 2 a\ntgl a\ninc a\ndec c\njnz c -2\ncpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7",
+            "cpy b c\ninc a\ndec c\ncpy c -2\ndec d\njnz d -5\njnz c 2\njnz 1 4\ndec b\ndec c\njnz 1 -4",
+        ] {
+            let mut instructions = Instruction::parse(program, &Dialect::default()).unwrap();
+            let mut loops = find_loops(&instructions);
+            // Toggle every instruction, then toggle them back in another order
+            let addrs: Vec<usize> = (0..instructions.len()).chain((0..instructions.len()).rev()).collect();
+            for addr in addrs {
+                instructions[addr] = instructions[addr].toggle();
+                update_loops(&mut loops, &instructions, addr);
+                assert_eq!(loops, find_loops(&instructions));
+            }
+        }
+    }
+
+    #[test]
+    fn applying_loops() {
+        let instructions = Instruction::parse("cpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5", &Dialect::default()).unwrap();
+        let l = find_loops(&instructions)[0].clone().unwrap();
+        assert_eq!(l.apply(&[1, 6, 0, 7], Overflow::Wrap), Some(vec![43, 6, 0, 0]));
+        assert_eq!(l.apply(&[1, 0, 0, 7], Overflow::Wrap), None);
+        assert_eq!(l.apply(&[1, 6, 0, 0], Overflow::Wrap), None);
+        assert_eq!(l.apply(&[1, 1 << 16, 0, 1 << 16], Overflow::Wrap), Some(vec![1, 1 << 16, 0, 0]));
+        assert_eq!(l.apply(&[1, 1 << 16, 0, 1 << 16], Overflow::Trap), None);
+        assert_eq!(l.apply(&[1i64, 1 << 16, 0, 1 << 16], Overflow::Trap), Some(vec![(1 << 32) + 1, 1 << 16, 0, 0]));
+        let instructions = Instruction::parse("cpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7", &Dialect::default()).unwrap();
+        let l = find_loops(&instructions)[0].clone().unwrap();
+        assert_eq!(l.apply(&[0, 7, 0, 0], Overflow::Wrap), Some(vec![3, 0, 1, 0]));
+        assert_eq!(l.apply(&[0, 8, 0, 0], Overflow::Wrap), Some(vec![4, 0, 2, 0]));
+        assert_eq!(l.apply(&[0, -1, 0, 0], Overflow::Wrap), None);
+    }
+
+    #[test]
+    fn running_optimized() {
+        for &(program, regs) in &[
+            ("cpy 5 b\ncpy 6 c\ncpy c d\ninc a\ndec d\njnz d -2\ndec b\njnz b -5", [30, 0, 6, 0]),
+            ("cpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7", [0, 0, 2, 0]),
+            ("cpy 13 b\ncpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7", [6, 0, 1, 0]),
+            ("cpy 9 c\ncpy 2 b\njnz c 2\njnz 1 4\ndec b\ndec c\njnz 1 -4", [0, -7, 0, 0]),
+            ("cpy 10 c\ninc a\ndec c\ndec c\njnz c -3", [5, 0, 0, 0]),
+        ] {
+            let mut cpu = Cpu::new(program, &Dialect::default()).unwrap();
+            cpu.run();
+            assert_eq!(cpu.regs, regs.to_vec());
+        }
+    }
+
+    #[test]
+    fn counting_cost() {
+        for &(program, regs) in &[
+            ("inc a\ndec c\njnz c -2", [0, 0, 5, 0]),
+            ("cpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5", [1, 6, 0, 7]),
+            ("cpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7", [0, 7, 0, 0]),
+            ("cpy 2 c\njnz b 2\njnz 1 6\ndec b\ndec c\njnz c -4\ninc a\njnz 1 -7", [0, 8, 0, 0]),
+            ("jnz c 2\njnz 1 4\ndec b\ndec c\njnz 1 -4", [0, 9, 4, 0]),
+            ("jnz c 2\njnz 1 4\ndec b\ndec c\njnz 1 -4", [0, 9, 0, 0]),
+            ("jnz b 2\njnz 1 7\ncpy a c\ninc d\ndec c\njnz c -2\ndec b\njnz 1 -7", [3, 4, 0, 0]),
+        ] {
+            let mut cpu = Cpu::new(program, &Dialect::default()).unwrap();
+            let cost = cpu.loops[0].as_ref().unwrap().cost(&regs);
+            cpu.regs = regs.to_vec();
+            let mut steps = 0;
+            while !cpu.step_instruction() { steps += 1; }
+            assert_eq!(cost, Some(steps));
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Running programs safely. A program can be run with a limited number of
+//! steps, which results in a structured outcome instead of hanging if it
+//! never halts. Repeating machine states are detected with constant memory,
+//! so that programs stuck in a loop are detected early. Invalid operations
+//! stop the program with a fault instead of continuing at a garbage address.
+
+use std::collections::VecDeque;
+use std::fmt;
+use super::{Cpu, Instruction};
+use super::word::{OverflowError, Word};
+
+
+/// Invalid operation that stopped a program
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Fault {
+    /// Trapped arithmetic overflow
+    Overflow(OverflowError),
+    /// The `jnz` instruction at the given address jumped to a negative address
+    Jump { addr: usize, target: i128 },
+    /// The `tgl` instruction at the given address targeted a negative address
+    Toggle { addr: usize, target: i128 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::Overflow(ref err) => write!(f, "{}", err),
+            Fault::Jump { addr, target } => write!(f, "jump to negative address {} at line {}", target, addr + 1),
+            Fault::Toggle { addr, target } => write!(f, "toggle of negative address {} at line {}", target, addr + 1),
+        }
+    }
+}
+
+impl Fault {
+    /// Address of the instruction that caused the fault
+    pub fn addr(&self) -> usize {
+        match *self {
+            Fault::Overflow(ref err) => err.addr,
+            Fault::Jump { addr, .. } | Fault::Toggle { addr, .. } => addr,
+        }
+    }
+}
+
+
+/// Outcome of running a program with a limited number of steps. Running
+/// an optimized loop counts as a single step.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Outcome {
+    /// Program halted after the given number of steps
+    Halted { steps: u64 },
+    /// Program waits for input after the given number of steps
+    Waiting { steps: u64 },
+    /// Program didn't halt within the given number of steps
+    Exhausted { steps: u64 },
+    /// Program never halts, since the machine state at the given address
+    /// repeats every `period` steps
+    InfiniteLoop { ip: usize, period: u64 },
+    /// Program was stopped by a fault of the given instruction
+    Fault { fault: Fault, instruction: Instruction },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Halted { steps } => write!(f, "halted after {} steps", steps),
+            Outcome::Waiting { steps } => write!(f, "waiting for input after {} steps", steps),
+            Outcome::Exhausted { steps } => write!(f, "still running after {} steps", steps),
+            Outcome::InfiniteLoop { ip, period } => write!(f, "infinite loop at line {} repeating every {} steps", ip + 1, period),
+            Outcome::Fault { ref fault, ref instruction } => write!(f, "{} ({})", fault, instruction),
+        }
+    }
+}
+
+
+/// Machine state to compare later states with, to detect a repeating
+/// state. The program is only copied if it was modified since the previous
+/// saved state.
+struct Saved<W: Word> {
+    ip: usize,
+    regs: Vec<W>,
+    input: VecDeque<W>,
+    generation: u64,
+    /// Program at the given generation (None if it wasn't copied)
+    instructions: Option<Vec<Instruction>>,
+}
+
+impl<W: Word> Saved<W> {
+    /// Save the state of a CPU
+    fn new(cpu: &Cpu<W>) -> Saved<W> {
+        Saved { ip: cpu.ip, regs: cpu.regs.clone(), input: cpu.input.clone(), generation: cpu.generation, instructions: None }
+    }
+
+    /// Replace by the current state of a CPU
+    fn update(&mut self, cpu: &Cpu<W>) {
+        self.ip = cpu.ip;
+        self.regs.clone_from(&cpu.regs);
+        self.input.clone_from(&cpu.input);
+        if cpu.generation != self.generation {
+            self.generation = cpu.generation;
+            self.instructions = Some(cpu.instructions.clone());
+        }
+    }
+
+    /// Check if a CPU has the saved state
+    fn matches(&self, cpu: &Cpu<W>) -> bool {
+        self.ip == cpu.ip && self.regs == cpu.regs && self.input == cpu.input && (
+            cpu.generation == self.generation ||
+            self.instructions.as_ref().map(|instructions| *instructions == cpu.instructions).unwrap_or(false)
+        )
+    }
+}
+
+
+impl<W: Word> Cpu<W> {
+    /// Run program for at most the given number of steps. Repeating states
+    /// are detected with Brent's algorithm: the state is saved at steps
+    /// that are powers of 2 and compared with every following state, which
+    /// needs constant memory.
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Outcome {
+        let mut saved = Saved::new(self);
+        let (mut power, mut period) = (1, 0);
+        let mut steps = 0;
+        loop {
+            if let Some(fault) = self.fault {
+                return Outcome::Fault { fault: fault, instruction: self.instructions[fault.addr()] };
+            } else if self.is_waiting() {
+                return Outcome::Waiting { steps: steps };
+            } else if self.is_done() {
+                return Outcome::Halted { steps: steps };
+            } else if steps >= max_steps {
+                return Outcome::Exhausted { steps: steps };
+            }
+            self.step();
+            steps += 1;
+            period += 1;
+            if self.fault.is_none() && saved.matches(self) {
+                return Outcome::InfiniteLoop { ip: self.ip, period: period };
+            }
+            if period == power {
+                saved.update(self);
+                power *= 2;
+                period = 0;
+            }
+        }
+    }
+
+    /// Run program. Returns the fault if it was stopped by one.
+    pub fn try_run(&mut self) -> Result<(), Fault> {
+        self.run();
+        match self.fault {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Dialect, Value};
+    use super::super::word::Overflow;
+
+    fn run(program: &str) -> Outcome {
+        Cpu::new(program, &Dialect::default()).unwrap().run_with_limit(100)
+    }
+
+    #[test]
+    fn running_with_limit() {
+        assert_eq!(run("cpy 3 a\ndec a\njnz a -1"), Outcome::Halted { steps: 2 });
+        assert_eq!(run("inp a\nout a"), Outcome::Waiting { steps: 0 });
+        assert_eq!(run("inc a\njnz 1 -1"), Outcome::Exhausted { steps: 100 });
+        assert_eq!(run("cpy 1 a\njnz a 0"), Outcome::InfiniteLoop { ip: 1, period: 1 });
+        assert_eq!(run("cpy 2 b\ninc a\ndec a\ntgl b\njnz 1 -3\ninc c"), Outcome::InfiniteLoop { ip: 3, period: 8 });
+        assert_eq!(run("cpy 2 a\njnz a -3"), Outcome::Fault {
+            fault: Fault::Jump { addr: 1, target: -2 },
+            instruction: Instruction::Jnz(Value::Register(0), Value::Immediate(-3)),
+        });
+        assert_eq!(run("cpy -5 a\ntgl a").to_string(), "toggle of negative address -4 at line 2 (tgl a)");
+    }
+
+    #[test]
+    fn running_long_loops() {
+        // Counts down from a million without shortcuts, then loops forever
+        let mut cpu = Cpu::new("cpy 1000000 a\ndec a\njnz a -1\ninc b\ndec b\njnz 1 -2", &Dialect::default()).unwrap();
+        cpu.shortcuts = false;
+        assert_eq!(cpu.run_with_limit(1_000_000_000_000), Outcome::InfiniteLoop { ip: 4, period: 3 });
+        let mut cpu = Cpu::new("inc a\njnz 1 -1", &Dialect::default()).unwrap();
+        cpu.shortcuts = false;
+        assert_eq!(cpu.run_with_limit(5_000_000), Outcome::Exhausted { steps: 5_000_000 });
+    }
+
+    #[test]
+    fn reporting_faults() {
+        let mut cpu = Cpu::new("cpy 2147483647 a\ninc a", &Dialect::default()).unwrap();
+        cpu.overflow = Overflow::Trap;
+        assert_eq!(cpu.run_with_limit(100).to_string(), "overflow of register a at line 2 (inc a)");
+        assert_eq!(cpu.ip, 1);
+        let mut cpu = Cpu::new("jnz 1 -1", &Dialect::default()).unwrap();
+        assert_eq!(cpu.try_run(), Err(Fault::Jump { addr: 0, target: -1 }));
+        assert_eq!(cpu.ip, 0);
+        let mut cpu = Cpu::new("jnz 1 -1", &Dialect::default()).unwrap();
+        cpu.run_compiled();
+        assert_eq!(cpu.fault, Some(Fault::Jump { addr: 0, target: -1 }));
+    }
+}
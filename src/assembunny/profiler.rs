@@ -0,0 +1,139 @@
+//! Instruction-level profiler for assembunny programs. Counts how often
+//! every instruction is run, how often every taken `jnz` is stepped
+//! normally or handled by running the loop it closes as an optimized
+//! shortcut, and how many instructions these shortcuts skipped. The
+//! annotated listing shows where a program spends its time and which
+//! loops are missed by the optimizer.
+
+use super::{Cpu, Instruction};
+use super::word::Word;
+
+
+/// Execution profile of a program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Profile {
+    /// Number of times each instruction was run
+    pub counts: Vec<u64>,
+    /// Number of times each `jnz` was taken when run normally
+    pub jumps: Vec<u64>,
+    /// Number of times the loop closed by each `jnz` was run as a shortcut
+    pub shortcuts: Vec<u64>,
+    /// Number of instructions skipped by shortcuts, for each loop end
+    pub skipped: Vec<u64>,
+    /// Number of shortcuts for which the number of skipped instructions is unknown
+    pub uncounted: u64,
+}
+
+impl Profile {
+    /// Create an empty profile for a program with the given number of instructions
+    pub fn new(len: usize) -> Profile {
+        Profile { counts: vec![0; len], jumps: vec![0; len], shortcuts: vec![0; len], skipped: vec![0; len], uncounted: 0 }
+    }
+
+    /// Record that the instruction at the given address was run
+    pub fn count(&mut self, addr: usize, jumped: bool) {
+        self.counts[addr] += 1;
+        if jumped { self.jumps[addr] += 1; }
+    }
+
+    /// Record that the loop ending at the given address was run as a
+    /// shortcut, skipping the given number of instructions
+    pub fn shortcut(&mut self, end: usize, skipped: Option<u64>) {
+        self.shortcuts[end] += 1;
+        match skipped {
+            Some(n) => self.skipped[end] += n,
+            None => self.uncounted += 1,
+        }
+    }
+
+    /// Total number of instructions run
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Total number of instructions skipped by shortcuts
+    pub fn total_skipped(&self) -> u64 {
+        self.skipped.iter().sum()
+    }
+
+    /// Annotated listing of the given program, using the given register
+    /// names. Every line shows how often the instruction was run and its
+    /// share of all instructions run. Hot spots (with a share of at least
+    /// 10%) are marked with an asterisk, jumps are annotated with how often
+    /// they were taken or handled by a shortcut.
+    pub fn listing(&self, instructions: &[Instruction], names: &str) -> String {
+        let total = self.total();
+        let mut s = String::new();
+        for (addr, ins) in instructions.iter().enumerate() {
+            let share = if total > 0 { self.counts[addr] as f64 * 100.0 / total as f64 } else { 0.0 };
+            let hot = if share >= 10.0 { "*" } else { " " };
+            s.push_str(&format!("{:12} {:5.1}%{} {:4}  ", self.counts[addr], share, hot, addr));
+            if self.jumps[addr] == 0 && self.shortcuts[addr] == 0 {
+                s.push_str(&ins.show(names));
+            } else {
+                s.push_str(&format!("{:<12} ; jumped {}, shortcut {}", ins.show(names), self.jumps[addr], self.shortcuts[addr]));
+                if self.skipped[addr] > 0 {
+                    s.push_str(&format!(" (skipped {})", self.skipped[addr]));
+                }
+            }
+            s.push('\n');
+        }
+        s.push_str(&format!("{} instructions run, {} skipped by {} shortcuts",
+            total, self.total_skipped(), self.shortcuts.iter().sum::<u64>()));
+        if self.uncounted > 0 {
+            s.push_str(&format!(" ({} shortcuts skipped an unknown number of instructions)", self.uncounted));
+        }
+        s.push('\n');
+        s
+    }
+}
+
+
+impl<W: Word> Cpu<W> {
+    /// Start collecting a profile (discarding a profile collected so far)
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Box::new(Profile::new(self.instructions.len())));
+    }
+
+    /// Stop collecting and return the collected profile
+    pub fn stop_profile(&mut self) -> Option<Profile> {
+        self.profile.take().map(|profile| *profile)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Cpu, Dialect};
+
+    #[test]
+    fn profiling() {
+        let mut cpu = Cpu::new("cpy 5 b\ncpy 6 c\ncpy c d\ninc a\ndec d\njnz d -2\ndec b\njnz b -5\ncpy 3 c\nout c\ndec c\njnz c -2", &Dialect::default()).unwrap();
+        cpu.start_profile();
+        cpu.run();
+        let profile = cpu.stop_profile().unwrap();
+        assert_eq!(profile.counts, vec![1, 1, 0, 0, 0, 0, 0, 0, 1, 3, 3, 3]);
+        assert_eq!(profile.jumps, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(profile.shortcuts, vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(profile.skipped, vec![0, 0, 0, 0, 0, 0, 0, 105, 0, 0, 0, 0]);
+        assert_eq!(profile.listing(&cpu.instructions, &cpu.names),
+"           1   8.3%     0  cpy 5 b
+           1   8.3%     1  cpy 6 c
+           0   0.0%     2  cpy c d
+           0   0.0%     3  inc a
+           0   0.0%     4  dec d
+           0   0.0%     5  jnz d -2
+           0   0.0%     6  dec b
+           0   0.0%     7  jnz b -5     ; jumped 0, shortcut 1 (skipped 105)
+           1   8.3%     8  cpy 3 c
+           3  25.0%*    9  out c
+           3  25.0%*   10  dec c
+           3  25.0%*   11  jnz c -2     ; jumped 2, shortcut 0
+12 instructions run, 105 skipped by 1 shortcuts
+");
+        let mut cpu = Cpu::new("cpy 2 y\ndec y\njnz y -1", &Dialect::default().with_registers("xy")).unwrap();
+        cpu.start_profile();
+        cpu.run();
+        assert!(cpu.stop_profile().unwrap().listing(&cpu.instructions, &cpu.names).contains("     2  jnz y -1     ; jumped 0, shortcut 1 (skipped 4)\n"));
+    }
+}
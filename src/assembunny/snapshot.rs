@@ -0,0 +1,219 @@
+//! Snapshots of the machine state, to pause a program and resume it later.
+//! A snapshot is a text with one field per line, followed by the program.
+//! Instructions that have been toggled are annotated with the instruction
+//! they were toggled from, so that a CPU loaded from a snapshot can still
+//! be reset to the original program. A fault is not part of a snapshot
+//! (a faulting instruction doesn't advance, so resuming faults again).
+//!
+//! ```text
+//! ip 4
+//! regs 3 0 0 0
+//! names abcd
+//! output 1 7
+//! input
+//! overflow wrap
+//! program
+//! cpy 2 a
+//! jnz 1 a ; toggled from: cpy 1 a
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::str::{FromStr, SplitWhitespace};
+use super::{named_register, Cpu, Dialect, Instruction};
+use super::word::{Overflow, Word};
+
+
+/// Error while loading a snapshot. Line numbers start at 1.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Line contains an invalid field or instruction
+    InvalidLine(usize),
+    /// Snapshot lacks the given field
+    MissingField(&'static str),
+}
+
+
+/// Largest number of registers in snapshots
+const MAX_REGISTERS: usize = 26;
+
+/// Separator of a toggled instruction and its original instruction
+const TOGGLED_FROM: &'static str = " ; toggled from: ";
+
+
+/// Parse the values of a field
+fn values<T: FromStr>(words: SplitWhitespace, lineno: usize) -> Result<Vec<T>, Error> {
+    words.map(|word| word.parse().map_err(|_| Error::InvalidLine(lineno))).collect()
+}
+
+/// Join values of a field
+fn join<T: ToString>(values: &[T]) -> String {
+    values.iter().map(|value| format!(" {}", value.to_string())).collect()
+}
+
+
+impl<W: Word> Cpu<W> {
+    /// Snapshot of the current machine state
+    pub fn snapshot(&self) -> String {
+        let mut s = String::new();
+        let input: Vec<W> = self.input.iter().cloned().collect();
+        let overflow = match self.overflow {
+            Overflow::Wrap => "wrap",
+            Overflow::Saturate => "saturate",
+            Overflow::Trap => "trap",
+        };
+        writeln!(s, "ip {}", self.ip).unwrap();
+        writeln!(s, "regs{}", join(&self.regs)).unwrap();
+        writeln!(s, "names {}", self.names).unwrap();
+        writeln!(s, "output{}", join(&self.output)).unwrap();
+        writeln!(s, "input{}", join(&input)).unwrap();
+        writeln!(s, "overflow {}", overflow).unwrap();
+        writeln!(s, "program").unwrap();
+        for (ins, original) in self.instructions.iter().zip(&self.original) {
+            if ins == original {
+                writeln!(s, "{}", ins.show(&self.names)).unwrap();
+            } else {
+                writeln!(s, "{}{}{}", ins.show(&self.names), TOGGLED_FROM, original.show(&self.names)).unwrap();
+            }
+        }
+        s
+    }
+
+    /// Create CPU with the machine state of a snapshot
+    pub fn from_snapshot(s: &str) -> Result<Cpu<W>, Error> {
+        let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let (mut ip, mut regs, mut output, mut input, mut overflow) = (None, None, vec![], VecDeque::new(), Overflow::Wrap);
+        let mut names = None;
+        let mut program = false;
+        for (lineno, line) in &mut lines {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                None => (),
+                Some("ip") => match values(words, lineno)?.as_slice() {
+                    &[value] => ip = Some(value),
+                    _ => return Err(Error::InvalidLine(lineno)),
+                },
+                Some("regs") => {
+                    let values: Vec<W> = values(words, lineno)?;
+                    if values.len() > MAX_REGISTERS {
+                        return Err(Error::InvalidLine(lineno));
+                    }
+                    regs = Some(values);
+                },
+                Some("names") => match (words.next(), words.next()) {
+                    (name, None) => names = Some(name.unwrap_or("").to_owned()),
+                    _ => return Err(Error::InvalidLine(lineno)),
+                },
+                Some("output") => output = values(words, lineno)?,
+                Some("input") => input = values(words, lineno)?.into_iter().collect(),
+                Some("overflow") => overflow = match (words.next(), words.next()) {
+                    (Some("wrap"), None) => Overflow::Wrap,
+                    (Some("saturate"), None) => Overflow::Saturate,
+                    (Some("trap"), None) => Overflow::Trap,
+                    _ => return Err(Error::InvalidLine(lineno)),
+                },
+                Some("program") if words.next().is_none() => { program = true; break; },
+                _ => return Err(Error::InvalidLine(lineno)),
+            }
+        }
+        let ip = ip.ok_or(Error::MissingField("ip"))?;
+        let regs = regs.ok_or(Error::MissingField("regs"))?;
+        if !program {
+            return Err(Error::MissingField("program"));
+        }
+        // Registers without a name have their standard name
+        let names = names.unwrap_or_default();
+        let all_names: String = (0..regs.len() as u8).map(|x| named_register(&names, x)).collect();
+        let dialect = Dialect::default();
+        let (mut instructions, mut original) = (vec![], vec![]);
+        for (lineno, line) in lines {
+            let mut parts = line.splitn(2, TOGGLED_FROM);
+            let ins = Instruction::parse_named(parts.next().unwrap(), &dialect, &all_names).map_err(|_| Error::InvalidLine(lineno))?;
+            instructions.push(ins);
+            original.push(match parts.next() {
+                Some(s) => Instruction::parse_named(s, &dialect, &all_names).map_err(|_| Error::InvalidLine(lineno))?,
+                None => ins,
+            });
+        }
+        let mut cpu = Cpu::from(original);
+        cpu.instructions = instructions;
+        cpu.optimize();
+        cpu.ip = ip;
+        cpu.regs = regs;
+        cpu.output = output;
+        cpu.input = input;
+        cpu.overflow = overflow;
+        cpu.names = names;
+        Ok(cpu)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving() {
+        let mut cpu = Cpu::new("cpy 2 a\ntgl a\nout a\ntgl a\ncpy 1 a\ndec a\ninp b", &Dialect::default()).unwrap();
+        cpu.input.push_back(7);
+        for _ in 0..4 { cpu.step(); }
+        assert_eq!(cpu.snapshot(), "\
+ip 4
+regs 3 0 0 0
+names abcd
+output 2
+input 7
+overflow wrap
+program
+cpy 2 a
+tgl a
+out a
+inc a ; toggled from: tgl a
+cpy 1 a
+dec a
+inp b
+");
+    }
+
+    #[test]
+    fn loading() {
+        let mut cpu = Cpu::new("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a", &Dialect::day23()).unwrap();
+        for _ in 0..8 { cpu.step(); }
+        cpu.output.push(42);
+        let mut loaded = Cpu::from_snapshot(&cpu.snapshot()).unwrap();
+        assert_eq!(loaded, cpu);
+        loaded.run();
+        assert_eq!(loaded.regs[0], 3);
+        loaded.reset();
+        assert_eq!(loaded.instructions, Instruction::parse("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a", &Dialect::day23()).unwrap());
+        let cpu: Cpu<i64> = Cpu::from_snapshot("ip 1\nregs 1 2 3 4 5\noverflow trap\n\nprogram\ninc e\n").unwrap();
+        assert_eq!((cpu.ip, cpu.regs.clone(), cpu.overflow), (1, vec![1, 2, 3, 4, 5], Overflow::Trap));
+    }
+
+    #[test]
+    fn loading_registers() {
+        let mut cpu = Cpu::new("cpy 2 y\ntgl y\ninc x\ninc w", &Dialect::default().with_registers("xyzw")).unwrap();
+        for _ in 0..3 { cpu.step(); }
+        let snapshot = cpu.snapshot();
+        assert!(snapshot.contains("names xyzw\n"));
+        assert!(snapshot.ends_with("program\ncpy 2 y\ntgl y\ninc x\ndec w ; toggled from: inc w\n"));
+        let mut loaded = Cpu::from_snapshot(&snapshot).unwrap();
+        assert_eq!(loaded, cpu);
+        loaded.run();
+        assert_eq!(loaded.regs, [1, 2, 0, -1]);
+        let cpu: Cpu<i32> = Cpu::from_snapshot("ip 0\nregs 0 0 0\nnames xy\nprogram\ninc c\ninc y").unwrap();
+        assert_eq!((cpu.names.as_str(), cpu.register_name(2)), ("xy", 'c'));
+        assert_eq!(Cpu::<i32>::from_snapshot("ip 0\nregs 0 0\nnames x y\nprogram"), Err(Error::InvalidLine(3)));
+    }
+
+    #[test]
+    fn loading_invalid() {
+        assert_eq!(Cpu::<i32>::from_snapshot("ip 0\nregs 0 0\nprogram\ninc c"), Err(Error::InvalidLine(4)));
+        assert_eq!(Cpu::<i32>::from_snapshot("ip 0 1\nregs 0\nprogram"), Err(Error::InvalidLine(1)));
+        assert_eq!(Cpu::<i32>::from_snapshot("ip 0\nregs x\nprogram"), Err(Error::InvalidLine(2)));
+        assert_eq!(Cpu::<i32>::from_snapshot("ip 0\nregs 0\nfoo\nprogram"), Err(Error::InvalidLine(3)));
+        assert_eq!(Cpu::<i32>::from_snapshot("regs 0\nprogram"), Err(Error::MissingField("ip")));
+        assert_eq!(Cpu::<i32>::from_snapshot("ip 0\nregs 0"), Err(Error::MissingField("program")));
+    }
+}
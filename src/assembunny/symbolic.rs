@@ -0,0 +1,1199 @@
+//! Symbolic execution of assembunny programs. Runs a program with the
+//! initial register values as symbols and derives closed-form expressions
+//! for the final registers and output. Where the control flow depends on
+//! the symbols, execution forks into paths with conditions on the initial
+//! values. Optimizable loops are replaced by their closed-form effect, and
+//! cycles whose number of iterations depends on a symbol are solved as
+//! recurrences (which turns day 23 into a factorial).
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use super::{register_name, registers_used, Instruction, Value};
+use super::optimizer::{self, Effect, Loop, Poly, Update};
+
+
+/// Atomic (non-polynomial) part of a symbolic value
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub enum Atom {
+    /// Initial value of a register
+    Input(u8),
+    /// Value of a register at the head of a cycle (used while solving cycles)
+    Head(u8),
+    /// Factorial quotient q!/m! for a constant m
+    Fact(Sym, i64),
+    /// Quotient of a division by a constant
+    Div(Sym, i64),
+    /// Remainder of a division by a constant
+    Rem(Sym, i64),
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Atom::Input(x) => write!(f, "{}", register_name(x)),
+            Atom::Head(x) => write!(f, "{}'", register_name(x)),
+            Atom::Fact(ref q, 0) => write!(f, "{}!", operand(q)),
+            Atom::Fact(ref q, m) => write!(f, "{}!/{}!", operand(q), m),
+            Atom::Div(ref q, k) => write!(f, "{}/{}", operand(q), k),
+            Atom::Rem(ref q, k) => write!(f, "{}%{}", operand(q), k),
+        }
+    }
+}
+
+/// Show a symbolic value as operand, in parentheses unless it's a single atom
+fn operand(s: &Sym) -> String {
+    match s.0.iter().next() {
+        Some((monomial, &1)) if s.0.len() == 1 && monomial.len() == 1 => s.to_string(),
+        _ if s.0.len() <= 1 && s.as_constant().map(|c| c >= 0).unwrap_or(false) => s.to_string(),
+        _ => format!("({})", s),
+    }
+}
+
+impl Atom {
+    /// Substitute atoms (including nested ones) by the given function
+    fn substitute<F: Fn(&Atom) -> Option<Sym>>(&self, f: &F) -> Sym {
+        if let Some(s) = f(self) {
+            return s;
+        }
+        match *self {
+            Atom::Fact(ref q, m) => Sym::fact(q.substitute(f), m),
+            Atom::Div(ref q, k) => Sym::div(q.substitute(f), k),
+            Atom::Rem(ref q, k) => Sym::rem(q.substitute(f), k),
+            _ => Sym::atom(self.clone()),
+        }
+    }
+
+    /// Evaluate with the given initial register values
+    fn eval(&self, inputs: &[i32]) -> Option<i64> {
+        match *self {
+            Atom::Input(x) => Some(inputs[x as usize] as i64),
+            Atom::Head(_) => None,
+            Atom::Fact(ref q, m) => {
+                let q = q.eval(inputs)?;
+                if q < m { return None; }
+                Some((m + 1..q + 1).fold(1i64, |prod, i| prod.wrapping_mul(i)))
+            },
+            Atom::Div(ref q, k) => q.eval(inputs).map(|q| q / k),
+            Atom::Rem(ref q, k) => q.eval(inputs).map(|q| q % k),
+        }
+    }
+
+    /// Registers of cycle heads used by this atom
+    fn heads(&self, heads: &mut BTreeSet<u8>) {
+        match *self {
+            Atom::Input(_) => (),
+            Atom::Head(x) => { heads.insert(x); },
+            Atom::Fact(ref q, _) | Atom::Div(ref q, _) | Atom::Rem(ref q, _) => heads.extend(q.heads()),
+        }
+    }
+}
+
+
+/// Symbolic value. A polynomial over atoms that maps monomials (sorted list
+/// of atoms, empty for the constant term) to coefficients.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Default)]
+pub struct Sym(BTreeMap<Vec<Atom>, i64>);
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("0");
+        }
+        // Highest terms first, constant last
+        let terms = self.0.iter().rev().filter(|&(m, _)| !m.is_empty()).chain(self.0.iter().take_while(|&(m, _)| m.is_empty()));
+        for (i, (monomial, &coef)) in terms.enumerate() {
+            match (i, coef < 0) {
+                (0, true) => f.write_str("-")?,
+                (_, true) => f.write_str(" - ")?,
+                (0, false) => (),
+                (_, false) => f.write_str(" + ")?,
+            }
+            if monomial.is_empty() || coef.abs() != 1 {
+                write!(f, "{}", coef.abs())?;
+                if !monomial.is_empty() { f.write_str("*")?; }
+            }
+            for (j, atom) in monomial.iter().enumerate() {
+                if j > 0 { f.write_str("*")?; }
+                write!(f, "{}", atom)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sym {
+    /// Constant value
+    pub fn constant(c: i64) -> Sym {
+        Sym::term(vec![], c)
+    }
+
+    /// Initial value of a register
+    pub fn input(x: u8) -> Sym {
+        Sym::atom(Atom::Input(x))
+    }
+
+    /// Value of a register at the head of a cycle
+    fn head(x: u8) -> Sym {
+        Sym::atom(Atom::Head(x))
+    }
+
+    /// Value of a single atom
+    fn atom(atom: Atom) -> Sym {
+        Sym::term(vec![atom], 1)
+    }
+
+    /// Single term. Factors of the coefficient are absorbed into factorial
+    /// quotients, i.e. 3*q!/3! becomes q!/2! and 2*q!/2! becomes q!.
+    fn term(mut monomial: Vec<Atom>, mut coef: i64) -> Sym {
+        for atom in monomial.iter_mut() {
+            if let Atom::Fact(_, ref mut m) = *atom {
+                while *m > 1 && coef % *m == 0 {
+                    coef /= *m;
+                    *m -= 1;
+                }
+                if *m == 1 { *m = 0; }
+            }
+        }
+        monomial.sort();
+        let mut sym = Sym::default();
+        if coef != 0 { sym.0.insert(monomial, coef); }
+        sym
+    }
+
+    /// Factorial quotient q!/m!
+    pub fn fact(q: Sym, m: i64) -> Sym {
+        match q.as_constant() {
+            Some(q) if q >= m => Sym::constant((m + 1..q + 1).fold(1i64, |prod, i| prod.wrapping_mul(i))),
+            _ => Sym::atom(Atom::Fact(q, m)),
+        }
+    }
+
+    /// Quotient of a division by a constant
+    pub fn div(q: Sym, k: i64) -> Sym {
+        match q.as_constant() {
+            Some(q) => Sym::constant(q / k),
+            None => Sym::atom(Atom::Div(q, k)),
+        }
+    }
+
+    /// Remainder of a division by a constant
+    pub fn rem(q: Sym, k: i64) -> Sym {
+        match q.as_constant() {
+            Some(q) => Sym::constant(q % k),
+            None => Sym::atom(Atom::Rem(q, k)),
+        }
+    }
+
+    /// Symbolic value of an optimizer polynomial, given the current values
+    /// of all registers
+    fn from_poly(poly: &Poly, regs: &[Sym]) -> Sym {
+        poly.terms().fold(Sym::default(), |sum, (monomial, &coef)| {
+            let term = monomial.iter().fold(Sym::constant(coef), |term, &r| term.times(&regs[r as usize]));
+            sum.plus(&term)
+        })
+    }
+
+    /// Sum of two values
+    pub fn plus(&self, other: &Sym) -> Sym {
+        let mut sym = self.clone();
+        for (monomial, &coef) in &other.0 {
+            let sum = sym.0.get(monomial).cloned().unwrap_or(0).wrapping_add(coef);
+            if sum == 0 { sym.0.remove(monomial); } else { sym.0.insert(monomial.clone(), sum); }
+        }
+        sym
+    }
+
+    /// Difference of two values
+    pub fn minus(&self, other: &Sym) -> Sym {
+        self.plus(&other.times(&Sym::constant(-1)))
+    }
+
+    /// Product of two values
+    pub fn times(&self, other: &Sym) -> Sym {
+        let mut sym = Sym::default();
+        for (m1, &c1) in &self.0 {
+            for (m2, &c2) in &other.0 {
+                let monomial = m1.iter().chain(m2.iter()).cloned().collect();
+                sym = sym.plus(&Sym::term(monomial, c1.wrapping_mul(c2)));
+            }
+        }
+        sym
+    }
+
+    /// Returns the value if it is constant
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.0.len() {
+            0 => Some(0),
+            1 => self.0.get(&vec![]).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Coefficient of the given monomial
+    fn coefficient(&self, monomial: &[Atom]) -> i64 {
+        self.0.get(monomial).cloned().unwrap_or(0)
+    }
+
+    /// Substitute atoms (including nested ones) by the given function
+    fn substitute<F: Fn(&Atom) -> Option<Sym>>(&self, f: &F) -> Sym {
+        self.0.iter().fold(Sym::default(), |sum, (monomial, &coef)| {
+            let term = monomial.iter().fold(Sym::constant(coef), |term, atom| term.times(&atom.substitute(f)));
+            sum.plus(&term)
+        })
+    }
+
+    /// Evaluate with the given initial register values. Wraps around on
+    /// overflow.
+    pub fn eval(&self, inputs: &[i32]) -> Option<i64> {
+        let mut sum = 0i64;
+        for (monomial, &coef) in &self.0 {
+            let mut term = coef;
+            for atom in monomial {
+                term = term.wrapping_mul(atom.eval(inputs)?);
+            }
+            sum = sum.wrapping_add(term);
+        }
+        Some(sum)
+    }
+
+    /// Registers of cycle heads used by this value
+    fn heads(&self) -> BTreeSet<u8> {
+        let mut heads = BTreeSet::new();
+        for atom in self.0.keys().flat_map(|monomial| monomial.iter()) {
+            atom.heads(&mut heads);
+        }
+        heads
+    }
+
+    /// Returns the atom if the value is a polynomial in a single atom
+    fn single_atom(&self) -> Option<Atom> {
+        let atoms: BTreeSet<&Atom> = self.0.keys().flat_map(|monomial| monomial.iter()).collect();
+        match atoms.len() {
+            1 => atoms.into_iter().next().cloned(),
+            _ => None,
+        }
+    }
+
+    /// Coefficients (by degree) of a polynomial in a single atom
+    fn univariate(&self) -> Vec<i64> {
+        let mut coefs = vec![];
+        for (monomial, &coef) in &self.0 {
+            if coefs.len() <= monomial.len() { coefs.resize(monomial.len() + 1, 0); }
+            coefs[monomial.len()] = coef;
+        }
+        coefs
+    }
+
+    /// Polynomial in the given atom with the given coefficients
+    fn from_univariate(atom: &Atom, coefs: &[i64]) -> Sym {
+        coefs.iter().enumerate().fold(Sym::default(), |sum, (degree, &coef)| sum.plus(&Sym::term(vec![atom.clone(); degree], coef)))
+    }
+
+    /// Divide by a linear value `x + c`. Returns None if the division
+    /// leaves a remainder.
+    fn divide_linear(&self, divisor: &Sym) -> Option<Sym> {
+        let atom = self.single_atom()?;
+        if divisor.single_atom() != Some(atom.clone()) { return None; }
+        let d = divisor.univariate();
+        let p = self.univariate();
+        if d.len() != 2 || d[1] != 1 || p.len() < 2 { return None; }
+        // Synthetic division by x - r
+        let r = -d[0];
+        let mut quotient = vec![0; p.len() - 1];
+        let mut carry = 0;
+        for i in (1..p.len()).rev() {
+            carry = p[i] + r * carry;
+            quotient[i - 1] = carry;
+        }
+        if p[0] + r * carry != 0 { return None; }
+        Some(Sym::from_univariate(&atom, &quotient))
+    }
+
+    /// Multiply by q!/m!. Linear factors of the value that directly follow
+    /// q are absorbed into the factorial, i.e. q*(q+1) * q!/m! = (q+1)!/m!.
+    fn times_fact(&self, q: &Sym, m: i64) -> Sym {
+        let (mut value, mut q) = (self.clone(), q.clone());
+        loop {
+            let next = q.plus(&Sym::constant(1));
+            match value.divide_linear(&next) {
+                Some(quotient) => { value = quotient; q = next; },
+                None => break,
+            }
+        }
+        value.times(&Sym::fact(q, m))
+    }
+}
+
+
+/// Ceiling of a/b for positive b
+fn div_ceil(a: i64, b: i64) -> i64 {
+    -div_floor(-a, b)
+}
+
+/// Floor of a/b for positive b
+fn div_floor(a: i64, b: i64) -> i64 {
+    if a % b < 0 { a / b - 1 } else { a / b }
+}
+
+/// Evaluate a univariate polynomial. Returns None on overflow.
+fn eval_univariate(p: &[i64], v: i64) -> Option<i128> {
+    p.iter().rev().fold(Some(0i128), |acc, &c| acc?.checked_mul(v as i128)?.checked_add(c as i128))
+}
+
+/// Forward difference p(v+1) - p(v) of a univariate polynomial
+fn delta(p: &[i64]) -> Vec<i64> {
+    let mut q = vec![0i64; p.len().saturating_sub(1)];
+    for (i, &c) in p.iter().enumerate() {
+        // (v+1)^i - v^i = sum of C(i,j)*v^j for j < i
+        let mut binom = 1i64;
+        for j in 0..i {
+            q[j] = q[j].wrapping_add(c.wrapping_mul(binom));
+            binom = binom * (i - j) as i64 / (j + 1) as i64;
+        }
+    }
+    q
+}
+
+/// Check if a univariate polynomial is non-negative for all values in the
+/// given (possibly unbounded) range. Can only prove, not disprove.
+fn nonneg_on(p: &[i64], lo: Option<i64>, hi: Option<i64>) -> bool {
+    let p = match p.iter().rposition(|&c| c != 0) {
+        None => return true,
+        Some(0) => return p[0] >= 0,
+        Some(degree) => &p[..degree + 1],
+    };
+    let at = |v| eval_univariate(p, v).map(|x| x >= 0).unwrap_or(false);
+    match (lo, hi) {
+        (Some(lo), Some(hi)) if hi < lo => true,
+        (Some(lo), Some(hi)) if hi - lo <= 64 => (lo..hi + 1).all(at),
+        // Non-negative at the lower end and non-decreasing above it
+        (Some(lo), _) => at(lo) && nonneg_on(&delta(p), Some(lo), hi.map(|hi| hi - 1)),
+        // Non-negative at the upper end and non-increasing below it
+        (None, Some(hi)) => {
+            let d: Vec<i64> = delta(p).iter().map(|&c| -c).collect();
+            at(hi) && nonneg_on(&d, None, Some(hi - 1))
+        },
+        (None, None) => false,
+    }
+}
+
+
+/// Condition on symbolic values
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Constraint {
+    /// Value is non-negative
+    NonNeg(Sym),
+    /// Value is zero
+    Zero(Sym),
+    /// Value is not zero
+    NonZero(Sym),
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Show constraint as `expr op constant`, flipped if all coefficients are negative
+        let s = self.value();
+        let constant = s.coefficient(&[]);
+        let mut lhs = s.minus(&Sym::constant(constant));
+        let mut rhs = -constant;
+        let flipped = lhs.0.values().all(|&coef| coef < 0);
+        if flipped {
+            lhs = lhs.times(&Sym::constant(-1));
+            rhs = -rhs;
+        }
+        let op = match (self, flipped) {
+            (&Constraint::NonNeg(_), false) => ">=",
+            (&Constraint::NonNeg(_), true) => "<=",
+            (&Constraint::Zero(_), _) => "==",
+            (&Constraint::NonZero(_), _) => "!=",
+        };
+        write!(f, "{} {} {}", lhs, op, rhs)
+    }
+}
+
+impl Constraint {
+    /// Constrained value
+    fn value(&self) -> &Sym {
+        match *self {
+            Constraint::NonNeg(ref s) | Constraint::Zero(ref s) | Constraint::NonZero(ref s) => s,
+        }
+    }
+
+    /// Constraint that holds if and only if this one doesn't
+    fn negate(&self) -> Constraint {
+        match *self {
+            Constraint::NonNeg(ref s) => Constraint::NonNeg(Sym::constant(-1).minus(s)),
+            Constraint::Zero(ref s) => Constraint::NonZero(s.clone()),
+            Constraint::NonZero(ref s) => Constraint::Zero(s.clone()),
+        }
+    }
+
+    /// Substitute atoms (including nested ones) by the given function
+    fn substitute<F: Fn(&Atom) -> Option<Sym>>(&self, f: &F) -> Constraint {
+        match *self {
+            Constraint::NonNeg(ref s) => Constraint::NonNeg(s.substitute(f)),
+            Constraint::Zero(ref s) => Constraint::Zero(s.substitute(f)),
+            Constraint::NonZero(ref s) => Constraint::NonZero(s.substitute(f)),
+        }
+    }
+
+    /// Check the constraint for the given initial register values
+    pub fn holds(&self, inputs: &[i32]) -> bool {
+        match (self, self.value().eval(inputs)) {
+            (_, None) => false,
+            (&Constraint::NonNeg(_), Some(v)) => v >= 0,
+            (&Constraint::Zero(_), Some(v)) => v == 0,
+            (&Constraint::NonZero(_), Some(v)) => v != 0,
+        }
+    }
+}
+
+
+/// Known bounds of an atom
+#[derive(Debug, Default)]
+struct Bounds {
+    lo: Option<i64>,
+    hi: Option<i64>,
+    excluded: Vec<i64>,
+}
+
+
+/// Conditions of an execution path (all constraints must hold)
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Conditions(Vec<Constraint>);
+
+impl fmt::Display for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, constraint) in self.0.iter().enumerate() {
+            if i > 0 { f.write_str(", ")?; }
+            write!(f, "{}", constraint)?;
+        }
+        Ok(())
+    }
+}
+
+impl Conditions {
+    /// Check if there are no conditions
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Check the conditions for the given initial register values
+    pub fn hold(&self, inputs: &[i32]) -> bool {
+        self.0.iter().all(|constraint| constraint.holds(inputs))
+    }
+
+    /// Bounds of an atom, derived from its nature and from all linear
+    /// constraints on it
+    fn bounds(&self, atom: &Atom) -> Bounds {
+        let mut b = match *atom {
+            Atom::Fact(..) => Bounds { lo: Some(1), ..Bounds::default() },
+            Atom::Div(..) => Bounds { lo: Some(0), ..Bounds::default() },
+            Atom::Rem(_, k) => Bounds { lo: Some(0), hi: Some(k - 1), ..Bounds::default() },
+            _ => Bounds::default(),
+        };
+        for constraint in &self.0 {
+            let s = constraint.value();
+            if s.single_atom().as_ref() != Some(atom) { continue; }
+            let p = s.univariate();
+            if p.len() != 2 { continue; }
+            let (c, k) = (p[0], p[1]);
+            match *constraint {
+                Constraint::NonNeg(_) if k > 0 => b.lo = Some(b.lo.map_or(div_ceil(-c, k), |lo| lo.max(div_ceil(-c, k)))),
+                Constraint::NonNeg(_) => b.hi = Some(b.hi.map_or(div_floor(c, -k), |hi| hi.min(div_floor(c, -k)))),
+                Constraint::Zero(_) if c % k == 0 => {
+                    b.lo = Some(b.lo.map_or(-c / k, |lo| lo.max(-c / k)));
+                    b.hi = Some(b.hi.map_or(-c / k, |hi| hi.min(-c / k)));
+                },
+                Constraint::Zero(_) => { b.lo = Some(1); b.hi = Some(0); },
+                Constraint::NonZero(_) => if c % k == 0 { b.excluded.push(-c / k); },
+            }
+        }
+        while let Some(lo) = b.lo.filter(|lo| b.excluded.contains(lo)) { b.lo = Some(lo + 1); }
+        while let Some(hi) = b.hi.filter(|hi| b.excluded.contains(hi)) { b.hi = Some(hi - 1); }
+        b
+    }
+
+    /// Check if a value is non-negative under these conditions. Can only
+    /// prove, not disprove.
+    fn nonneg(&self, s: &Sym) -> bool {
+        if let Some(c) = s.as_constant() {
+            return c >= 0;
+        }
+        if let Some(atom) = s.single_atom() {
+            let b = self.bounds(&atom);
+            if nonneg_on(&s.univariate(), b.lo, b.hi) {
+                return true;
+            }
+        }
+        // Sum of products of non-negative atoms
+        s.0.iter().all(|(monomial, &coef)| {
+            coef >= 0 && monomial.iter().all(|atom| self.bounds(atom).lo.map(|lo| lo >= 0).unwrap_or(false))
+        })
+    }
+
+    /// Decide a constraint under these conditions. Returns None if it may
+    /// or may not hold.
+    pub fn decide(&self, constraint: &Constraint) -> Option<bool> {
+        if self.0.contains(constraint) {
+            return Some(true);
+        }
+        if self.0.contains(&constraint.negate()) {
+            return Some(false);
+        }
+        match *constraint {
+            Constraint::NonNeg(ref s) => {
+                if self.nonneg(s) { Some(true) }
+                else if self.nonneg(&Sym::constant(-1).minus(s)) { Some(false) }
+                else { None }
+            },
+            Constraint::Zero(ref s) => {
+                if let Some(c) = s.as_constant() {
+                    return Some(c == 0);
+                }
+                if self.nonneg(&s.minus(&Sym::constant(1))) || self.nonneg(&Sym::constant(-1).minus(s)) {
+                    return Some(false);
+                }
+                let atom = s.single_atom()?;
+                let p = s.univariate();
+                if p.len() != 2 { return None; }
+                if p[0] % p[1] != 0 { return Some(false); }
+                let v = -p[0] / p[1];
+                let b = self.bounds(&atom);
+                if b.lo.map_or(false, |lo| v < lo) || b.hi.map_or(false, |hi| v > hi) || b.excluded.contains(&v) {
+                    Some(false)
+                } else if b.lo == Some(v) && b.hi == Some(v) {
+                    Some(true)
+                } else {
+                    None
+                }
+            },
+            Constraint::NonZero(ref s) => self.decide(&Constraint::Zero(s.clone())).map(|zero| !zero),
+        }
+    }
+
+    /// Conditions with the given constraint added, or None if they can't
+    /// hold anymore. Constraints implied by others are dropped.
+    fn assume(&self, constraint: Constraint) -> Option<Conditions> {
+        match self.decide(&constraint) {
+            Some(true) => return Some(self.clone()),
+            Some(false) => return None,
+            None => (),
+        }
+        let mut conditions = self.clone();
+        conditions.0.push(constraint);
+        for atom in conditions.0.last().unwrap().value().0.keys().flat_map(|monomial| monomial.iter()) {
+            let b = conditions.bounds(atom);
+            if b.lo.is_some() && b.hi.is_some() && b.lo > b.hi {
+                return None;
+            }
+        }
+        let mut i = 0;
+        while i < conditions.0.len() {
+            let constraint = conditions.0.remove(i);
+            if conditions.decide(&constraint) != Some(true) {
+                conditions.0.insert(i, constraint);
+                i += 1;
+            }
+        }
+        // Bounds that fix an atom to a value are replaced by an equation
+        for (atom, value) in conditions.pinned() {
+            conditions.0.retain(|constraint| constraint.value().single_atom().as_ref() != Some(&atom));
+            conditions.0.push(Constraint::Zero(Sym::atom(atom).minus(&Sym::constant(value))));
+        }
+        Some(conditions)
+    }
+
+    /// Atoms whose value is fixed by these conditions
+    fn pinned(&self) -> Vec<(Atom, i64)> {
+        let atoms: BTreeSet<Atom> = self.0.iter().filter_map(|constraint| constraint.value().single_atom()).collect();
+        atoms.into_iter().filter_map(|atom| {
+            let b = self.bounds(&atom);
+            match (b.lo, b.hi) {
+                (Some(lo), Some(hi)) if lo == hi => Some((atom, lo)),
+                _ => None,
+            }
+        }).collect()
+    }
+}
+
+
+/// Result of symbolically executing a program on one path
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Outcome {
+    /// Program halts with the given registers and output if the conditions hold
+    Halted { conditions: Conditions, regs: Vec<Sym>, output: Vec<Sym> },
+    /// Result is unknown if the conditions hold
+    Unknown { conditions: Conditions, reason: String },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Halted { ref regs, ref output, .. } => {
+                for (x, value) in regs.iter().enumerate() {
+                    if x > 0 { f.write_str(", ")?; }
+                    write!(f, "{} = {}", register_name(x as u8), value)?;
+                }
+                if !output.is_empty() {
+                    let output: Vec<String> = output.iter().map(|value| value.to_string()).collect();
+                    write!(f, ", output = [{}]", output.join(", "))?;
+                }
+            },
+            Outcome::Unknown { ref reason, .. } => write!(f, "unknown ({})", reason)?,
+        }
+        if !self.conditions().is_empty() {
+            write!(f, " if {}", self.conditions())?;
+        }
+        Ok(())
+    }
+}
+
+impl Outcome {
+    /// Conditions for this outcome
+    pub fn conditions(&self) -> &Conditions {
+        match *self {
+            Outcome::Halted { ref conditions, .. } | Outcome::Unknown { ref conditions, .. } => conditions,
+        }
+    }
+
+    /// Final value of a register as formula with its conditions (if the
+    /// program halts on this path)
+    pub fn formula(&self, x: u8) -> Option<String> {
+        match *self {
+            Outcome::Halted { ref conditions, ref regs, .. } if conditions.is_empty() => Some(regs[x as usize].to_string()),
+            Outcome::Halted { ref conditions, ref regs, .. } => Some(format!("{} if {}", regs[x as usize], conditions)),
+            Outcome::Unknown { .. } => None,
+        }
+    }
+
+    /// Final register values for the given initial register values, or None
+    /// if the outcome doesn't apply to them
+    pub fn eval(&self, inputs: &[i32]) -> Option<Vec<i64>> {
+        match *self {
+            Outcome::Halted { ref conditions, ref regs, .. } if conditions.hold(inputs) =>
+                regs.iter().map(|value| value.eval(inputs)).collect(),
+            _ => None,
+        }
+    }
+}
+
+
+/// State of an execution path
+#[derive(Debug, Clone)]
+struct Path {
+    ip: usize,
+    regs: Vec<Sym>,
+    instructions: Vec<Instruction>,
+    loops: Vec<Option<Loop>>,
+    output: Vec<Sym>,
+    conditions: Conditions,
+    steps: usize,
+}
+
+impl Path {
+    /// Get the value of an immediate or register
+    fn value(&self, v: Value) -> Sym {
+        match v {
+            Value::Immediate(x) => Sym::constant(x as i64),
+            Value::Register(r) => self.regs[r as usize].clone(),
+        }
+    }
+
+    /// Path with the given constraint added, or None if it can't be taken.
+    /// Atoms that become fixed to a value are replaced by it.
+    fn assuming(&self, constraint: Constraint) -> Option<Path> {
+        let mut path = self.clone();
+        path.conditions = self.conditions.assume(constraint)?;
+        for (atom, value) in path.conditions.pinned() {
+            let f = |a: &Atom| if *a == atom { Some(Sym::constant(value)) } else { None };
+            for reg in path.regs.iter_mut() { *reg = reg.substitute(&f); }
+            for out in path.output.iter_mut() { *out = out.substitute(&f); }
+        }
+        Some(path)
+    }
+
+    /// Split the path into the path where the given constraint holds and the
+    /// path where it doesn't (if they can be taken)
+    fn branch(&self, constraint: Constraint) -> (Option<Path>, Option<Path>) {
+        match self.conditions.decide(&constraint) {
+            Some(true) => (Some(self.clone()), None),
+            Some(false) => (None, Some(self.clone())),
+            None => (self.assuming(constraint.clone()), self.assuming(constraint.negate())),
+        }
+    }
+}
+
+
+/// Result of a symbolic execution step
+enum Step {
+    Next(Path),
+    Halted(Path),
+    Unknown(Path, String),
+}
+
+
+/// How a cycle changes a register
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Kind {
+    /// Counter of the cycle, changes by 1 per iteration
+    Counter,
+    /// Register is left unchanged
+    Keep,
+    /// Register is set to a value that only depends on the counter
+    Set,
+    /// Register is increased by an amount that only depends on unchanged registers
+    Add,
+    /// Register is multiplied by the counter plus the given offset
+    Product(i64),
+}
+
+
+/// Effect of one iteration of a cycle, in terms of register values at the
+/// cycle head, and the conditions under which it is taken
+#[derive(Debug, Clone)]
+struct Cycle {
+    regs: Vec<Sym>,
+    conditions: Conditions,
+}
+
+impl Cycle {
+    /// Run as many iterations of the cycle as the given path takes. Returns
+    /// the path after the last iteration and, if it is not certain that the
+    /// cycle runs, the path without running it. Returns None if the cycle
+    /// can't be solved for this path.
+    fn apply(&self, path: &Path) -> Option<Vec<Path>> {
+        let n = self.regs.len() as u8;
+        let (counter, step) = (0..n).filter_map(|x| {
+            match self.regs[x as usize].minus(&Sym::head(x)).as_constant() {
+                Some(d) if d == 1 || d == -1 => Some((x, d)),
+                _ => None,
+            }
+        }).next()?;
+        let mut kinds = vec![Kind::Keep; n as usize];
+        kinds[counter as usize] = Kind::Counter;
+        for x in 0..n {
+            let value = &self.regs[x as usize];
+            if x == counter || *value == Sym::head(x) { continue; }
+            let increase = value.minus(&Sym::head(x));
+            let product = value.minus(&Sym::head(x).times(&Sym::head(counter)));
+            let offset = product.coefficient(&[Atom::Head(x)]);
+            kinds[x as usize] = if value.heads().iter().all(|&y| y == counter) {
+                Kind::Set
+            } else if increase.heads().iter().all(|&y| self.regs[y as usize] == Sym::head(y)) {
+                Kind::Add
+            } else if step == -1 && product == Sym::head(x).times(&Sym::constant(offset)) {
+                Kind::Product(offset)
+            } else {
+                return None;
+            };
+        }
+
+        // The cycle repeats while the counter is within bounds. Other
+        // constraints must hold on entry and stay true for all iterations.
+        let (mut lower, mut upper, mut excluded, mut entry) = (None, None, vec![], vec![]);
+        for constraint in &self.conditions.0 {
+            let s = constraint.value();
+            let heads = s.heads();
+            let p = s.univariate();
+            if s.single_atom() == Some(Atom::Head(counter)) && p.len() == 2 {
+                let (c, k) = (p[0], p[1]);
+                match *constraint {
+                    Constraint::NonNeg(_) if (k > 0) == (step > 0) => entry.push(constraint.clone()),
+                    Constraint::NonNeg(_) if k > 0 => lower = Some(lower.map_or(div_ceil(-c, k), |lo: i64| lo.max(div_ceil(-c, k)))),
+                    Constraint::NonNeg(_) => upper = Some(upper.map_or(div_floor(c, -k), |hi: i64| hi.min(div_floor(c, -k)))),
+                    Constraint::NonZero(_) => if c % k == 0 { excluded.push(-c / k); },
+                    Constraint::Zero(_) => return None,
+                }
+            } else if heads.iter().all(|&y| kinds[y as usize] == Kind::Keep) {
+                entry.push(constraint.clone());
+            } else if let (&Constraint::NonNeg(_), Some(x)) = (constraint, s.single_atom().and_then(|atom| match atom { Atom::Head(x) => Some(x), _ => None })) {
+                // A product stays positive if all its factors are
+                match (kinds[x as usize], s.minus(&Sym::head(x)).as_constant()) {
+                    (Kind::Product(_), Some(c)) if c <= 0 && p.len() == 2 && p[1] == 1 => entry.push(constraint.clone()),
+                    _ => return None,
+                }
+            } else {
+                return None;
+            }
+        }
+        let last = match step {
+            -1 => {
+                let mut lo = lower?;
+                while excluded.contains(&lo) { lo += 1; }
+                if excluded.iter().any(|&v| v > lo) { return None; }
+                lo
+            },
+            _ => {
+                let mut hi = upper?;
+                while excluded.contains(&hi) { hi -= 1; }
+                if excluded.iter().any(|&v| v < hi) { return None; }
+                hi
+            },
+        };
+        for &kind in &kinds {
+            if let Kind::Product(offset) = kind {
+                if last + offset < 1 { return None; }
+            }
+        }
+
+        // Run the cycle if the counter is within bounds on entry
+        let current = path.regs[counter as usize].clone();
+        let within = Constraint::NonNeg(current.minus(&Sym::constant(last)).times(&Sym::constant(-step)));
+        let (run, skip) = path.branch(within);
+        let run = run?;
+        let f = |atom: &Atom| match *atom {
+            Atom::Head(y) => Some(run.regs[y as usize].clone()),
+            _ => None,
+        };
+        if entry.iter().any(|constraint| run.conditions.decide(&constraint.substitute(&f)) != Some(true)) {
+            return None;
+        }
+        let iterations = run.regs[counter as usize].minus(&Sym::constant(last - step)).times(&Sym::constant(-step));
+        let mut result = run.clone();
+        for x in 0..n as usize {
+            let value = &self.regs[x];
+            result.regs[x] = match kinds[x] {
+                Kind::Counter => Sym::constant(last + step),
+                Kind::Keep => continue,
+                Kind::Set => value.substitute(&|atom: &Atom| if *atom == Atom::Head(counter) { Some(Sym::constant(last)) } else { None }),
+                Kind::Add => run.regs[x].plus(&iterations.times(&value.minus(&Sym::head(x as u8)).substitute(&f))),
+                Kind::Product(offset) => run.regs[x].times_fact(&run.regs[counter as usize].plus(&Sym::constant(offset)), last - 1 + offset),
+            };
+        }
+        Some(Some(result).into_iter().chain(skip).collect())
+    }
+}
+
+
+/// Symbolic executor
+pub struct Executor {
+    /// Maximum number of steps per path
+    pub max_steps: usize,
+    /// Maximum number of paths
+    pub max_paths: usize,
+    /// Cycles found at cycle heads, for every program state (by toggling)
+    cycles: HashMap<(usize, Vec<Instruction>), Vec<Cycle>>,
+    /// Optimizable loops, for every program state (by toggling)
+    loops: HashMap<Vec<Instruction>, Vec<Option<Loop>>>,
+}
+
+impl Default for Executor {
+    fn default() -> Executor {
+        Executor { max_steps: 10000, max_paths: 100, cycles: HashMap::new(), loops: HashMap::new() }
+    }
+}
+
+impl Executor {
+    /// Execute a program with the given initial register values
+    pub fn run(&mut self, instructions: &[Instruction], regs: Vec<Sym>) -> Vec<Outcome> {
+        let start = Path {
+            ip: 0,
+            regs: regs,
+            instructions: instructions.to_vec(),
+            loops: self.loops(instructions),
+            output: vec![],
+            conditions: Conditions::default(),
+            steps: 0,
+        };
+        let mut paths = vec![start];
+        let mut outcomes = vec![];
+        while let Some(path) = paths.pop() {
+            if paths.len() + outcomes.len() >= self.max_paths {
+                outcomes.push(Outcome::Unknown { conditions: path.conditions, reason: "path limit exceeded".to_string() });
+                continue;
+            }
+            for step in self.step(path, true) {
+                match step {
+                    Step::Next(path) => paths.push(path),
+                    Step::Halted(path) => outcomes.push(Outcome::Halted { conditions: path.conditions, regs: path.regs, output: path.output }),
+                    Step::Unknown(path, reason) => outcomes.push(Outcome::Unknown { conditions: path.conditions, reason: reason }),
+                }
+            }
+        }
+        outcomes
+    }
+
+    /// Execute the next instruction (or optimizable loop) of a path. Solves
+    /// cycles when jumping back, if enabled.
+    fn step(&mut self, mut path: Path, solve: bool) -> Vec<Step> {
+        let ip = path.ip;
+        if ip >= path.instructions.len() {
+            return vec![Step::Halted(path)];
+        }
+        if path.steps >= self.max_steps {
+            return vec![Step::Unknown(path, "step limit exceeded".to_string())];
+        }
+        path.steps += 1;
+        if let Some(l) = path.loops[ip].clone() {
+            if let Some(steps) = run_loop(&path, &l) {
+                return steps;
+            }
+        }
+        match path.instructions[ip] {
+            Instruction::Cpy(_, Value::Immediate(_)) => (),
+            Instruction::Cpy(v, Value::Register(y)) => path.regs[y as usize] = path.value(v),
+            Instruction::Inc(x) => path.regs[x as usize] = path.regs[x as usize].plus(&Sym::constant(1)),
+            Instruction::Dec(x) => path.regs[x as usize] = path.regs[x as usize].minus(&Sym::constant(1)),
+            Instruction::Out(v) => {
+                let value = path.value(v);
+                path.output.push(value);
+            },
+            Instruction::Tgl(x) => return self.toggle(path, x),
+            Instruction::Inp(_) => return vec![Step::Unknown(path, "program reads input".to_string())],
+            Instruction::Jnz(v1, v2) => return self.jump(path, v1, v2, solve),
+        }
+        path.ip += 1;
+        vec![Step::Next(path)]
+    }
+
+    /// Execute a `jnz` instruction
+    fn jump(&mut self, path: Path, v1: Value, v2: Value, solve: bool) -> Vec<Step> {
+        let ip = path.ip;
+        let offset = match path.value(v2).as_constant() {
+            Some(offset) => offset,
+            None => return vec![Step::Unknown(path, "jump offset depends on input".to_string())],
+        };
+        let (taken, not_taken) = path.branch(Constraint::NonZero(path.value(v1)));
+        let mut steps = vec![];
+        if let Some(mut path) = not_taken {
+            path.ip = ip + 1;
+            steps.push(Step::Next(path));
+        }
+        if let Some(mut path) = taken {
+            let target = ip as i64 + offset;
+            if target < 0 {
+                steps.push(Step::Unknown(path, "jump to negative address".to_string()));
+                return steps;
+            }
+            path.ip = target as usize;
+            if solve && offset <= 0 && path.regs.iter().any(|r| r.as_constant().is_none()) {
+                if let Some(paths) = self.solve_cycle(&path) {
+                    steps.extend(paths.into_iter().map(Step::Next));
+                    return steps;
+                }
+            }
+            steps.push(Step::Next(path));
+        }
+        steps
+    }
+
+    /// Try to run all iterations of a cycle starting at the current address
+    fn solve_cycle(&mut self, path: &Path) -> Option<Vec<Path>> {
+        let key = (path.ip, path.instructions.clone());
+        if !self.cycles.contains_key(&key) {
+            let cycles = self.find_cycles(path);
+            self.cycles.insert(key.clone(), cycles);
+        }
+        self.cycles[&key].iter().filter_map(|cycle| cycle.apply(path)).next()
+    }
+
+    /// Optimizable loops of a program. Toggles fork paths with the same
+    /// programs over and over, so loops are only found once per program.
+    fn loops(&mut self, instructions: &[Instruction]) -> Vec<Option<Loop>> {
+        if !self.loops.contains_key(instructions) {
+            self.loops.insert(instructions.to_vec(), optimizer::find_loops(instructions));
+        }
+        self.loops[instructions].clone()
+    }
+
+    /// Toggle the instruction at the given address of a path
+    fn toggle_instruction(&mut self, path: &mut Path, addr: usize) {
+        path.instructions[addr] = path.instructions[addr].toggle();
+        path.loops = self.loops(&path.instructions);
+    }
+
+    /// Execute a `tgl` instruction. Forks for every address the target may be.
+    fn toggle(&mut self, mut path: Path, x: u8) -> Vec<Step> {
+        let ip = path.ip;
+        let len = path.instructions.len() as i64;
+        let target = path.regs[x as usize].plus(&Sym::constant(ip as i64));
+        if let Some(t) = target.as_constant() {
+            if t >= 0 && t < len {
+                self.toggle_instruction(&mut path, t as usize);
+            }
+            path.ip += 1;
+            return vec![Step::Next(path)];
+        }
+        let mut candidates = vec![
+            (Constraint::NonNeg(Sym::constant(-1).minus(&target)), None),
+            (Constraint::NonNeg(target.minus(&Sym::constant(len))), None),
+        ];
+        candidates.extend((0..len).map(|t| (Constraint::Zero(target.minus(&Sym::constant(t))), Some(t as usize))));
+        candidates.into_iter().filter_map(|(constraint, t)| {
+            let mut path = path.assuming(constraint)?;
+            if let Some(t) = t { self.toggle_instruction(&mut path, t); }
+            path.ip = ip + 1;
+            Some(Step::Next(path))
+        }).collect()
+    }
+
+    /// Find all ways to get from the current address back to it, without
+    /// toggling instructions or producing output
+    fn find_cycles(&mut self, path: &Path) -> Vec<Cycle> {
+        let start = Path {
+            ip: path.ip,
+            regs: (0..path.regs.len() as u8).map(Sym::head).collect(),
+            instructions: path.instructions.clone(),
+            loops: path.loops.clone(),
+            output: vec![],
+            conditions: Conditions::default(),
+            steps: 0,
+        };
+        let mut paths = vec![start];
+        let mut cycles = vec![];
+        while let Some(p) = paths.pop() {
+            if paths.len() + cycles.len() > 16 {
+                break;
+            }
+            for step in self.step(p, false) {
+                if let Step::Next(p) = step {
+                    if p.instructions != path.instructions || !p.output.is_empty() || p.steps > 1000 {
+                        continue;
+                    }
+                    if p.ip == path.ip {
+                        cycles.push(Cycle { regs: p.regs, conditions: p.conditions });
+                    } else {
+                        paths.push(p);
+                    }
+                }
+            }
+        }
+        cycles
+    }
+}
+
+/// Run an optimizable loop. Returns None if the loop can't be optimized on
+/// this path, so that its instructions need to be run normally.
+fn run_loop(path: &Path, l: &Loop) -> Option<Vec<Step>> {
+    let mut steps = vec![];
+    match l.effect {
+        Effect::Counted { counter, step, check_first, ref updates, ref guards, .. } => {
+            let iterations = |regs: &[Sym]| match (step, regs[counter as usize].as_constant()) {
+                (1, _) | (-1, _) => Some(regs[counter as usize].times(&Sym::constant(-step))),
+                (_, Some(r)) if r % step == 0 => Some(Sym::constant(-r / step)),
+                _ => None,
+            };
+            let n = match (iterations(&path.regs), path.regs[counter as usize].as_constant()) {
+                (Some(n), _) => n,
+                (None, Some(_)) => return None,
+                (None, None) => return Some(vec![Step::Unknown(path.clone(), format!("iterations of loop at lines {}-{} depend on input", l.start + 1, l.end + 1))]),
+            };
+            let mut constraints: Vec<Constraint> = guards.iter().map(|guard| Constraint::NonNeg(Sym::from_poly(guard, &path.regs))).collect();
+            constraints.push(Constraint::NonNeg(n.minus(&Sym::constant(if check_first { 0 } else { 1 }))));
+            if constraints.iter().any(|constraint| path.conditions.decide(constraint) == Some(false)) {
+                return None;
+            }
+            // Where the loop can't be optimized, its instructions are run normally
+            let mut path = path.clone();
+            for constraint in constraints {
+                let (holds, fails) = path.branch(constraint);
+                steps.extend(fails.map(Step::Next));
+                match holds {
+                    Some(p) => path = p,
+                    None => return Some(steps),
+                }
+            }
+            // Registers are only updated if the loop runs at least once
+            let n = iterations(&path.regs).unwrap();
+            let (run, skip) = if check_first { path.branch(Constraint::NonZero(n)) } else { (Some(path), None) };
+            if let Some(mut path) = skip {
+                path.ip = l.end + 1;
+                steps.push(Step::Next(path));
+            }
+            if let Some(mut path) = run {
+                let n = iterations(&path.regs).unwrap();
+                let regs = path.regs.clone();
+                for (x, update) in updates.iter().enumerate() {
+                    match *update {
+                        Update::Keep => (),
+                        Update::Add(ref k) => path.regs[x] = regs[x].plus(&n.times(&Sym::from_poly(k, &regs))),
+                        Update::Set(ref e) => path.regs[x] = Sym::from_poly(e, &regs),
+                    }
+                }
+                path.ip = l.end + 1;
+                steps.push(Step::Next(path));
+            }
+        },
+        Effect::DivMod { quotient, dividend, remainder, divisor } => {
+            let (holds, fails) = path.branch(Constraint::NonNeg(path.regs[dividend as usize].clone()));
+            let mut path = holds?;
+            steps.extend(fails.map(Step::Next));
+            let (d, k) = (path.regs[dividend as usize].clone(), divisor);
+            path.regs[quotient as usize] = path.regs[quotient as usize].plus(&Sym::div(d.clone(), k));
+            path.regs[remainder as usize] = Sym::constant(k).minus(&Sym::rem(d, k));
+            path.regs[dividend as usize] = Sym::constant(0);
+            path.ip = l.end + 1;
+            steps.push(Step::Next(path));
+        },
+    }
+    Some(steps)
+}
+
+/// Symbolically execute a program with all initial register values as symbols
+pub fn execute(instructions: &[Instruction]) -> Vec<Outcome> {
+    let n = cmp::max(4, registers_used(instructions)) as u8;
+    let inputs = (0..n).map(Sym::input).collect();
+    Executor::default().run(instructions, inputs)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Cpu, Dialect};
+
+    #[test]
+    fn simplifying() {
+        let a = Sym::input(0);
+        assert_eq!(Sym::fact(a.clone(), 5).times(&Sym::constant(120)).to_string(), "a!");
+        assert_eq!(Sym::fact(a.clone(), 5).times(&Sym::constant(5)).to_string(), "a!/4!");
+        assert_eq!(Sym::fact(a.clone(), 3).times(&Sym::constant(3)).to_string(), "a!/2!");
+        assert_eq!(Sym::fact(a.clone(), 3).times(&Sym::constant(6)).to_string(), "a!");
+        assert_eq!(Sym::fact(Sym::constant(7), 4).to_string(), "210");
+        let product = a.times(&a.minus(&Sym::constant(1)));
+        assert_eq!(product.to_string(), "a*a - a");
+        assert_eq!(product.times_fact(&a.minus(&Sym::constant(2)), 5), Sym::fact(a.clone(), 5));
+        assert_eq!(Sym::div(a.plus(&Sym::constant(2534)), 2).to_string(), "(a + 2534)/2");
+    }
+
+    #[test]
+    fn deciding() {
+        let a = Sym::input(0);
+        let conditions = Conditions::default().assume(Constraint::NonNeg(a.minus(&Sym::constant(7)))).unwrap();
+        assert_eq!(conditions.to_string(), "a >= 7");
+        assert_eq!(conditions.decide(&Constraint::NonNeg(a.times(&a).minus(&a).minus(&Sym::constant(1)))), Some(true));
+        assert_eq!(conditions.decide(&Constraint::Zero(a.minus(&Sym::constant(3)))), Some(false));
+        assert_eq!(conditions.decide(&Constraint::NonNeg(a.minus(&Sym::constant(8)))), None);
+        let conditions = conditions.assume(Constraint::NonNeg(Sym::constant(7).minus(&a))).unwrap();
+        assert_eq!(conditions.to_string(), "a == 7");
+        assert_eq!(conditions.pinned(), vec![(Atom::Input(0), 7)]);
+        assert_eq!(conditions.assume(Constraint::NonZero(a.minus(&Sym::constant(7)))), None);
+    }
+
+    #[test]
+    fn forking() {
+        let cpu = Cpu::new("cpy c a\ninc a\njnz b 2\ndec a\nout a", &Dialect::default()).unwrap();
+        let outcomes: Vec<String> = execute(&cpu.instructions).iter().map(|outcome| outcome.to_string()).collect();
+        assert_eq!(outcomes, vec![
+            "a = c + 1, b = b, c = c, d = d, output = [c + 1] if b != 0",
+            "a = c, b = 0, c = c, d = d, output = [c] if b == 0",
+        ]);
+    }
+
+    #[test]
+    fn solving_cycles() {
+        let mut cpu = Cpu::new("cpy a b\ndec b\ncpy a d\ncpy 0 a\ncpy b c\ninc a\ndec c\njnz c -2\ndec d\njnz d -5\ndec b\njnz b -9", &Dialect::default()).unwrap();
+        let outcomes = execute(&cpu.instructions);
+        let formulas: Vec<String> = outcomes.iter().filter_map(|outcome| outcome.formula(0)).collect();
+        assert_eq!(formulas, vec!["6 if a == 3", "a! if a >= 4", "2 if a == 2"]);
+        for a in 2..10 {
+            cpu.reset();
+            cpu.regs[0] = a;
+            cpu.run();
+            let result = outcomes.iter().filter_map(|outcome| outcome.eval(&[a, 0, 0, 0])).next();
+            assert_eq!(result.map(|regs| regs[0]), Some(cpu.regs[0] as i64));
+        }
+    }
+}
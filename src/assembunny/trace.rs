@@ -0,0 +1,495 @@
+//! Execution trace recording and replay. A trace stores the initial machine
+//! state and, for every step, the change of the instruction pointer, the
+//! register deltas, toggled instructions and output. Steps are encoded into
+//! a compact byte stream (usually 2-4 bytes per step), so that traces of
+//! millions of steps can be kept in memory. A replay moves forward and
+//! backward through the recorded steps.
+
+use std::io::{self, Write};
+use super::{named_register, Cpu, Instruction};
+use super::word::Word;
+
+
+/// Step header flag: the step was run as an optimized loop
+const SHORTCUT: u8 = 0x10;
+/// Step header flag: the instruction pointer did not just advance by one
+const JUMP: u8 = 0x20;
+/// Step header flag: an instruction was toggled
+const TOGGLE: u8 = 0x40;
+/// Step header flag: a value was output
+const OUTPUT: u8 = 0x80;
+
+
+/// Append a signed number to the given buffer as a zigzag encoded varint
+fn write_varint(data: &mut Vec<u8>, n: i128) {
+    let mut n = ((n << 1) ^ (n >> 127)) as u128;
+    while n >= 0x80 {
+        data.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    data.push(n as u8);
+}
+
+/// Read a zigzag encoded varint from the given buffer at the given position
+fn read_varint(data: &[u8], pos: &mut usize) -> i128 {
+    let mut n = 0u128;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        n |= ((byte & 0x7f) as u128) << shift;
+        if byte < 0x80 { break; }
+        shift += 7;
+    }
+    (n >> 1) as i128 ^ -((n & 1) as i128)
+}
+
+/// Append a length to the given buffer as a varint in reverse byte order,
+/// so that it can be read backwards from the end
+fn write_length(data: &mut Vec<u8>, mut len: usize) {
+    let start = data.len();
+    while len >= 0x80 {
+        data.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    data.push(len as u8);
+    data[start..].reverse();
+}
+
+/// Read a length written by `write_length` backwards, from the given end
+/// position. Moves the position to the start of the length.
+fn read_length(data: &[u8], pos: &mut usize) -> usize {
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        *pos -= 1;
+        let byte = data[*pos];
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 { break; }
+        shift += 7;
+    }
+    len
+}
+
+/// Number of bytes of a length written by `write_length`
+fn length_size(len: usize) -> usize {
+    let mut size = 1;
+    while len >> (7 * size) > 0 { size += 1; }
+    size
+}
+
+
+
+/// A single decoded step
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Step {
+    /// Step was run as an optimized loop
+    shortcut: bool,
+    /// Change of the instruction pointer
+    jump: i128,
+    /// Register deltas
+    deltas: Vec<i128>,
+    /// An instruction was toggled
+    toggle: bool,
+    /// Output value
+    output: Option<i128>,
+}
+
+impl Step {
+    /// Encode step. Every step is followed by its length, so that steps
+    /// can be decoded backwards as well. Changes of the first four registers
+    /// are flagged in the header, changes of further registers (if there
+    /// are any) in a bit mask of as many bytes as needed that follows it.
+    fn encode(&self, data: &mut Vec<u8>) {
+        let start = data.len();
+        let mut header = 0;
+        let mut mask = vec![0u8; (self.deltas.len().saturating_sub(4) + 7) / 8];
+        for (x, &delta) in self.deltas.iter().enumerate() {
+            if delta != 0 && x < 4 { header |= 1 << x; }
+            if delta != 0 && x >= 4 { mask[(x - 4) / 8] |= 1 << ((x - 4) % 8); }
+        }
+        if self.shortcut { header |= SHORTCUT; }
+        if self.jump != 1 { header |= JUMP; }
+        if self.toggle { header |= TOGGLE; }
+        if self.output.is_some() { header |= OUTPUT; }
+        data.push(header);
+        data.extend_from_slice(&mask);
+        if self.jump != 1 { write_varint(data, self.jump); }
+        for &delta in self.deltas.iter().filter(|&&delta| delta != 0) {
+            write_varint(data, delta);
+        }
+        if let Some(value) = self.output { write_varint(data, value); }
+        let len = data.len() - start;
+        write_length(data, len);
+    }
+
+    /// Decode step with the given number of registers at the given
+    /// position. Returns the step and the position of the next step.
+    fn decode(data: &[u8], start: usize, len: usize) -> (Step, usize) {
+        let header = data[start];
+        let mut pos = start + 1;
+        let mask = &data[pos..pos + (len.saturating_sub(4) + 7) / 8];
+        pos += mask.len();
+        let changed = |x: usize| if x < 4 { header & (1 << x) != 0 } else { mask[(x - 4) / 8] & (1 << ((x - 4) % 8)) != 0 };
+        let jump = if header & JUMP != 0 { read_varint(data, &mut pos) } else { 1 };
+        let mut deltas = vec![0; len];
+        for (x, delta) in deltas.iter_mut().enumerate() {
+            if changed(x) { *delta = read_varint(data, &mut pos); }
+        }
+        let output = if header & OUTPUT != 0 { Some(read_varint(data, &mut pos)) } else { None };
+        let step = Step { shortcut: header & SHORTCUT != 0, jump: jump, deltas: deltas, toggle: header & TOGGLE != 0, output: output };
+        (step, pos + length_size(pos - start))
+    }
+}
+
+
+/// A recorded execution trace
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Trace<W: Word = i32> {
+    /// Instructions when recording started
+    instructions: Vec<Instruction>,
+    /// Instruction pointer when recording started
+    ip: usize,
+    /// Registers when recording started
+    regs: Vec<W>,
+    /// Output when recording started
+    output: Vec<W>,
+    /// Names of the registers
+    names: String,
+    /// Encoded steps
+    data: Vec<u8>,
+    /// Address and previous instruction of every toggle
+    toggles: Vec<(usize, Instruction)>,
+    /// Number of steps
+    steps: usize,
+}
+
+impl<'a, W: Word> From<&'a Cpu<W>> for Trace<W> {
+    fn from(cpu: &Cpu<W>) -> Trace<W> {
+        Trace {
+            instructions: cpu.instructions.clone(),
+            ip: cpu.ip,
+            regs: cpu.regs.clone(),
+            output: cpu.output.clone(),
+            names: cpu.names.clone(),
+            data: vec![],
+            toggles: vec![],
+            steps: 0,
+        }
+    }
+}
+
+impl<W: Word> Trace<W> {
+    /// Number of recorded steps
+    pub fn len(&self) -> usize {
+        self.steps
+    }
+
+    /// Size of the encoded steps in bytes
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Record a step of the given CPU, which started at the given address
+    /// with the given registers and toggled the given instruction
+    pub fn record(&mut self, cpu: &Cpu<W>, ip: usize, regs: &[W], shortcut: bool, toggle: Option<(usize, Instruction)>, output: Option<W>) {
+        let deltas = cpu.regs.iter().zip(regs).map(|(&new, &old)| new.into().wrapping_sub(old.into())).collect();
+        let step = Step {
+            shortcut: shortcut,
+            jump: cpu.ip as i128 - ip as i128,
+            deltas: deltas,
+            toggle: toggle.is_some(),
+            output: output.map(W::into),
+        };
+        step.encode(&mut self.data);
+        self.toggles.extend(toggle);
+        self.steps += 1;
+    }
+
+    /// Replay the trace, starting at the beginning
+    pub fn replay<'a>(&'a self) -> Replay<'a, W> {
+        Replay {
+            trace: self,
+            pos: 0,
+            step: 0,
+            toggle: 0,
+            instructions: self.instructions.clone(),
+            ip: self.ip,
+            regs: self.regs.clone(),
+            output: self.output.clone(),
+        }
+    }
+
+    /// Write the trace as text, one line per step with the address and
+    /// instruction that was run, followed by the resulting registers
+    pub fn write_text<O: Write>(&self, mut w: O) -> io::Result<()> {
+        let mut replay = self.replay();
+        while replay.step < self.steps {
+            let ip = replay.ip;
+            let ins = replay.instructions[ip];
+            let step = replay.forward().unwrap();
+            let regs: Vec<String> = replay.regs.iter().enumerate().map(|(x, value)| format!("{}={}", named_register(&self.names, x as u8), value)).collect();
+            write!(w, "{:8} {:4}  {:<12} {}", replay.step, ip, ins.show(&self.names), regs.join(" "))?;
+            if step.shortcut { write!(w, " ; loop to {}", replay.ip)?; }
+            if step.toggle {
+                let (addr, prev) = self.toggles[replay.toggle - 1];
+                write!(w, " ; toggled {}: {} -> {}", addr, prev.show(&self.names), replay.instructions[addr].show(&self.names))?;
+            }
+            if let Some(value) = step.output { write!(w, " ; out {}", value)?; }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Replay of a recorded trace. Holds the machine state after the current step.
+#[derive(Debug)]
+pub struct Replay<'a, W: 'a + Word = i32> {
+    trace: &'a Trace<W>,
+    /// Position of the next step in the encoded steps
+    pos: usize,
+    /// Number of the current step
+    step: usize,
+    /// Number of toggles so far
+    toggle: usize,
+    pub instructions: Vec<Instruction>,
+    pub ip: usize,
+    pub regs: Vec<W>,
+    pub output: Vec<W>,
+}
+
+impl<'a, W: Word> Replay<'a, W> {
+    /// Number of the current step (number of steps replayed so far)
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Move forward by one step. Returns the step, or None if at the end
+    fn forward(&mut self) -> Option<Step> {
+        if self.step >= self.trace.steps { return None; }
+        let (step, pos) = Step::decode(&self.trace.data, self.pos, self.regs.len());
+        self.ip = (self.ip as i128 + step.jump) as usize;
+        for (reg, &delta) in self.regs.iter_mut().zip(step.deltas.iter()) {
+            *reg = W::truncate((*reg).into().wrapping_add(delta));
+        }
+        if step.toggle {
+            let addr = self.trace.toggles[self.toggle].0;
+            self.instructions[addr] = self.instructions[addr].toggle();
+            self.toggle += 1;
+        }
+        self.output.extend(step.output.map(W::truncate));
+        self.pos = pos;
+        self.step += 1;
+        Some(step)
+    }
+
+    /// Move backward by one step. Returns the step, or None if at the beginning
+    fn backward(&mut self) -> Option<Step> {
+        if self.step == 0 { return None; }
+        let mut pos = self.pos;
+        pos -= read_length(&self.trace.data, &mut pos);
+        let (step, _) = Step::decode(&self.trace.data, pos, self.regs.len());
+        self.ip = (self.ip as i128 - step.jump) as usize;
+        for (reg, &delta) in self.regs.iter_mut().zip(step.deltas.iter()) {
+            *reg = W::truncate((*reg).into().wrapping_sub(delta));
+        }
+        if step.toggle {
+            self.toggle -= 1;
+            let (addr, prev) = self.trace.toggles[self.toggle];
+            self.instructions[addr] = prev;
+        }
+        if step.output.is_some() {
+            self.output.pop();
+        }
+        self.pos = pos;
+        self.step -= 1;
+        Some(step)
+    }
+
+    /// Step forward. Returns false if already at the end of the trace
+    pub fn step_forward(&mut self) -> bool {
+        self.forward().is_some()
+    }
+
+    /// Step backward. Returns false if already at the beginning of the trace
+    pub fn step_backward(&mut self) -> bool {
+        self.backward().is_some()
+    }
+
+    /// Move to the given step (or to the end of the trace, if it has less steps)
+    pub fn seek(&mut self, step: usize) {
+        while self.step > step && self.step_backward() { }
+        while self.step < step && self.step_forward() { }
+    }
+}
+
+
+impl<W: Word> Cpu<W> {
+    /// Start recording a trace (discarding a trace recorded so far)
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Box::new(Trace::from(&*self)));
+    }
+
+    /// Stop recording and return the recorded trace
+    pub fn stop_trace(&mut self) -> Option<Trace<W>> {
+        self.trace.take().map(|trace| *trace)
+    }
+
+    /// Run a step, given as a function that returns true if an optimized
+    /// loop was run, and record it if a trace is being recorded
+    pub(super) fn traced<F: FnOnce(&mut Cpu<W>) -> bool>(&mut self, f: F) {
+        match self.trace.take() {
+            Some(mut trace) => {
+                let (ip, regs, outputs) = (self.ip, self.regs.clone(), self.output.len());
+                let toggle = match self.instructions[ip] {
+                    Instruction::Tgl(x) => {
+                        let addr = ip as i128 + regs[x as usize].into();
+                        if addr >= 0 && addr < self.instructions.len() as i128 {
+                            Some((addr as usize, self.instructions[addr as usize]))
+                        } else {
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+                let shortcut = f(self);
+                let toggle = toggle.filter(|&(addr, ins)| self.instructions[addr] != ins);
+                let output = if self.output.len() > outputs { self.output.last().cloned() } else { None };
+                trace.record(self, ip, &regs, shortcut, toggle, output);
+                self.trace = Some(trace);
+            },
+            None => { f(self); },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Dialect, Value};
+
+    #[test]
+    fn encoding() {
+        let mut data = vec![];
+        for &n in &[0, 1, -1, 63, -64, 64, 1000000, -2147483648, 2147483647, i128::min_value(), i128::max_value()] {
+            data.clear();
+            write_varint(&mut data, n);
+            let mut pos = 0;
+            assert_eq!(read_varint(&data, &mut pos), n);
+            assert_eq!(pos, data.len());
+        }
+        let step = Step { shortcut: false, jump: -5, deltas: vec![0, 3, 0, -1], toggle: true, output: Some(7) };
+        data.clear();
+        step.encode(&mut data);
+        assert_eq!(data.len(), 6);
+        assert_eq!(Step::decode(&data, 0, 4), (step, 6));
+        let step = Step { shortcut: false, jump: 1, deltas: vec![0, 0, 0, 0, 0, -1], toggle: false, output: None };
+        data.clear();
+        step.encode(&mut data);
+        assert_eq!(data.len(), 4);
+        assert_eq!(Step::decode(&data, 0, 6), (step, 4));
+    }
+
+    #[test]
+    fn encoding_large_steps() {
+        for &len in &[0, 1, 127, 128, 300, 16383, 16384] {
+            let mut data = vec![0xff];
+            write_length(&mut data, len);
+            assert_eq!(data.len(), 1 + length_size(len));
+            let mut pos = data.len();
+            assert_eq!(read_length(&data, &mut pos), len);
+            assert_eq!(pos, 1);
+        }
+        // More than 255 bytes, and more registers than fit in an i128 mask
+        let deltas: Vec<i128> = (0..200).map(|x| if x % 3 == 0 { 0 } else { i128::max_value() - x }).collect();
+        let step = Step { shortcut: true, jump: -1000, deltas: deltas, toggle: false, output: Some(i128::min_value()) };
+        let mut data = vec![];
+        step.encode(&mut data);
+        assert!(data.len() > 2000);
+        assert_eq!(Step::decode(&data, 0, 200), (step, data.len()));
+    }
+
+    #[test]
+    fn replaying_large_steps() {
+        // A loop that adds a large counter to 150 registers in a single step
+        let mut instructions: Vec<Instruction> = (1..151).map(Instruction::Inc).collect();
+        instructions.push(Instruction::Dec(0));
+        instructions.push(Instruction::Jnz(Value::Register(0), Value::Immediate(-151)));
+        instructions.push(Instruction::Inc(150));
+        let mut cpu: Cpu<i128> = Cpu::from(instructions);
+        cpu.regs[0] = 1 << 100;
+        cpu.start_trace();
+        let mut states = vec![cpu.regs.clone()];
+        while !cpu.step() {
+            states.push(cpu.regs.clone());
+        }
+        let trace = cpu.stop_trace().unwrap();
+        assert_eq!(trace.len(), 2);
+        assert!(trace.size() > 2000);
+        let mut replay = trace.replay();
+        for regs in &states[1..] {
+            assert!(replay.step_forward());
+            assert_eq!(&replay.regs, regs);
+        }
+        for regs in states.iter().rev().skip(1) {
+            assert!(replay.step_backward());
+            assert_eq!(&replay.regs, regs);
+        }
+        assert_eq!(replay.regs[150], 0);
+    }
+
+    #[test]
+    fn replaying() {
+        let mut cpu = Cpu::new("cpy 2 a\ntgl a\ntgl a\ntgl a\ninc c\ncpy 3 b\nout b\ndec b\njnz b -2\ndec a", &Dialect::default()).unwrap();
+        cpu.start_trace();
+        let mut states = vec![(cpu.instructions.clone(), cpu.ip, cpu.regs.clone(), cpu.output.clone())];
+        while !cpu.step_instruction() {
+            states.push((cpu.instructions.clone(), cpu.ip, cpu.regs.clone(), cpu.output.clone()));
+        }
+        let trace = cpu.stop_trace().unwrap();
+        assert_eq!(trace.len(), states.len() - 1);
+        assert!(trace.size() < 3 * trace.len());
+        let mut replay = trace.replay();
+        for state in &states[1..] {
+            assert!(replay.step_forward());
+            assert_eq!(&(replay.instructions.clone(), replay.ip, replay.regs.clone(), replay.output.clone()), state);
+        }
+        assert!(!replay.step_forward());
+        for state in states.iter().rev().skip(1) {
+            assert!(replay.step_backward());
+            assert_eq!(&(replay.instructions.clone(), replay.ip, replay.regs.clone(), replay.output.clone()), state);
+        }
+        assert!(!replay.step_backward());
+        replay.seek(7);
+        assert_eq!((replay.step(), replay.ip, replay.regs), (7, 7, vec![3, 3, -1, 0]));
+    }
+
+    #[test]
+    fn writing_text() {
+        let mut cpu = Cpu::new("cpy 2 a\ntgl a\nout a\ninc d\ncpy 3 b\ninc a\ndec b\njnz b -2", &Dialect::default()).unwrap();
+        cpu.start_trace();
+        cpu.run();
+        let mut text = Vec::new();
+        cpu.stop_trace().unwrap().write_text(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+"       1    0  cpy 2 a      a=2 b=0 c=0 d=0
+       2    1  tgl a        a=2 b=0 c=0 d=0 ; toggled 3: inc d -> dec d
+       3    2  out a        a=2 b=0 c=0 d=0 ; out 2
+       4    3  dec d        a=2 b=0 c=0 d=-1
+       5    4  cpy 3 b      a=2 b=3 c=0 d=-1
+       6    5  inc a        a=5 b=0 c=0 d=-1 ; loop to 8
+");
+        let mut cpu = Cpu::new("cpy 1 y\ntgl y\ninc x", &Dialect::default().with_registers("xy")).unwrap();
+        cpu.start_trace();
+        cpu.run();
+        let mut text = Vec::new();
+        cpu.stop_trace().unwrap().write_text(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(),
+"       1    0  cpy 1 y      x=0 y=1
+       2    1  tgl y        x=0 y=1 ; toggled 2: inc x -> dec x
+       3    2  dec x        x=-1 y=1
+");
+    }
+}
@@ -0,0 +1,361 @@
+//! Transpiler that turns an assembunny program into Rust source code. The
+//! generated function takes the initial registers and returns the final
+//! registers and output. Control flow is expressed with structured loops
+//! and conditionals where possible, annotated with the effect of loops that
+//! the optimizer recognizes. Programs with jumps that don't nest properly
+//! (or jump offsets that are not constant) are translated to a dispatch
+//! loop that matches on the instruction pointer instead. Programs that
+//! modify themselves (`tgl`) or read input (`inp`) can't be translated.
+
+use std::cmp;
+use std::collections::HashSet;
+use super::{register_name, registers_used, Instruction, Value};
+use super::optimizer::{self, Loop};
+
+
+/// Error while transpiling a program. Line numbers start at 1.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Line contains an instruction that can't be transpiled
+    Unsupported(usize, Instruction),
+}
+
+
+/// Expression of a value
+fn value(v: Value) -> String {
+    match v {
+        Value::Immediate(x) => x.to_string(),
+        Value::Register(x) => register_name(x).to_string(),
+    }
+}
+
+/// Indentation for the given depth
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+
+/// Code generator for a program
+struct Generator<'a> {
+    instructions: &'a [Instruction],
+    /// Optimizable loop starting at each address
+    loops: Vec<Option<Loop>>,
+    /// Expression of all registers
+    regs: String,
+    /// Enclosing loops (first and last address), innermost last
+    enclosing: Vec<(usize, usize)>,
+    /// Start addresses of loops whose label is used
+    labels: HashSet<usize>,
+}
+
+impl<'a> Generator<'a> {
+    /// Returns the condition and target address of the `jnz` instruction at
+    /// the given address, if it has a constant offset
+    fn jump(&self, ip: usize) -> Option<(Value, i64)> {
+        match self.instructions[ip] {
+            Instruction::Jnz(Value::Immediate(0), _) => None,
+            Instruction::Jnz(v, Value::Immediate(ofs)) => Some((v, ip as i64 + ofs as i64)),
+            _ => None,
+        }
+    }
+
+    /// Statement that halts the program
+    fn halt(&self) -> String {
+        format!("return ({}, output);", self.regs)
+    }
+
+    /// Statement of a straight-line instruction
+    fn statement(&self, ins: Instruction) -> Option<String> {
+        match ins {
+            Instruction::Cpy(_, Value::Immediate(_)) => None,
+            Instruction::Cpy(v, Value::Register(y)) => Some(format!("{} = {};", register_name(y), value(v))),
+            Instruction::Inc(x) => Some(format!("{0} = {0}.wrapping_add(1);", register_name(x))),
+            Instruction::Dec(x) => Some(format!("{0} = {0}.wrapping_sub(1);", register_name(x))),
+            Instruction::Out(v) => Some(format!("output.push({}); if output.len() == max_output {{ {} }}", value(v), self.halt())),
+            _ => None,
+        }
+    }
+
+    /// Add a statement that is run if the given value is not zero
+    fn conditional(code: &mut Vec<String>, depth: usize, v: Value, statement: &str) {
+        match v {
+            Value::Immediate(_) => code.push(format!("{}{}", indent(depth), statement)),
+            Value::Register(x) => code.push(format!("{}if {} != 0 {{ {} }}", indent(depth), register_name(x), statement)),
+        }
+    }
+
+    /// Statement that jumps to the start or behind the end of an enclosing loop
+    fn jump_out(&mut self, keyword: &str, start: usize) -> String {
+        if self.enclosing.last().map(|&(s, _)| s) == Some(start) {
+            format!("{};", keyword)
+        } else {
+            self.labels.insert(start);
+            format!("{} 'l{};", keyword, start + 1)
+        }
+    }
+
+    /// Generate structured code for the instructions in the given address
+    /// range. Returns None if the control flow can't be expressed this way.
+    fn block(&mut self, from: usize, to: usize, depth: usize, code: &mut Vec<String>) -> Option<()> {
+        let mut ip = from;
+        while ip < to {
+            // A loop starts here if a later instruction jumps back here
+            let mut end = None;
+            for e in ip..self.instructions.len() {
+                if let Some((_, target)) = self.jump(e) {
+                    if target != ip as i64 {
+                        continue;
+                    } else if e < to {
+                        end = Some(e);
+                    } else if !self.enclosing.contains(&(ip, e)) {
+                        return None;
+                    }
+                }
+            }
+            if let Some(end) = end {
+                self.enclosing.push((ip, end));
+                let mut body = Vec::new();
+                self.block(ip, end, depth + 1, &mut body)?;
+                self.enclosing.pop();
+                let label = if self.labels.contains(&ip) { format!("'l{}: ", ip + 1) } else { String::new() };
+                code.push(format!("{}{}loop {{", indent(depth), label));
+                if let Some(ref l) = self.loops[ip] {
+                    if l.end == end { code.push(format!("{}// {}", indent(depth + 1), l)); }
+                }
+                code.extend(body);
+                if let Some((Value::Register(x), _)) = self.jump(end) {
+                    code.push(format!("{}if {} == 0 {{ break; }}", indent(depth + 1), register_name(x)));
+                }
+                code.push(format!("{}}}", indent(depth)));
+                ip = end + 1;
+                continue;
+            }
+            match self.instructions[ip] {
+                Instruction::Jnz(Value::Immediate(0), _) => (),
+                Instruction::Jnz(v, Value::Immediate(_)) => {
+                    let target = self.jump(ip).unwrap().1;
+                    let len = self.instructions.len() as i64;
+                    if target < 0 {
+                        return None;
+                    } else if target >= len {
+                        let halt = self.halt();
+                        Generator::conditional(code, depth, v, &halt);
+                    } else if target > ip as i64 + 1 && target <= to as i64 {
+                        // Skip the instructions up to the target if the value is not zero. If
+                        // they end with a forward jump, the instructions up to its target
+                        // are skipped otherwise.
+                        let target = target as usize;
+                        let end = match self.jump(target - 1) {
+                            Some((Value::Immediate(_), end)) if target - 1 > ip && end > target as i64 && end <= to as i64 => end as usize,
+                            _ => target,
+                        };
+                        if let Value::Register(x) = v {
+                            let mut then = Vec::new();
+                            self.block(ip + 1, if end > target { target - 1 } else { target }, depth + 1, &mut then)?;
+                            let mut otherwise = Vec::new();
+                            self.block(target, end, depth + 1, &mut otherwise)?;
+                            if then.is_empty() {
+                                code.push(format!("{}if {} != 0 {{", indent(depth), register_name(x)));
+                                code.extend(otherwise);
+                            } else {
+                                code.push(format!("{}if {} == 0 {{", indent(depth), register_name(x)));
+                                code.extend(then);
+                                if !otherwise.is_empty() {
+                                    code.push(format!("{}}} else {{", indent(depth)));
+                                    code.extend(otherwise);
+                                }
+                            }
+                            code.push(format!("{}}}", indent(depth)));
+                            ip = end;
+                        } else {
+                            ip = target;
+                        }
+                        continue;
+                    } else if let Some(&(start, _)) = self.enclosing.iter().rev().find(|&&(_, end)| end as i64 + 1 == target) {
+                        let statement = self.jump_out("break", start);
+                        Generator::conditional(code, depth, v, &statement);
+                    } else if let Some(&(start, _)) = self.enclosing.iter().rev().find(|&&(start, _)| start as i64 == target) {
+                        let statement = self.jump_out("continue", start);
+                        Generator::conditional(code, depth, v, &statement);
+                    } else if target != ip as i64 + 1 {
+                        return None;
+                    }
+                },
+                Instruction::Jnz(_, _) => return None,
+                ins => if let Some(statement) = self.statement(ins) {
+                    code.push(format!("{}{}", indent(depth), statement));
+                },
+            }
+            ip += 1;
+        }
+        Some(())
+    }
+
+    /// Generate a dispatch loop that runs one instruction per iteration
+    fn dispatch(&self, code: &mut Vec<String>) {
+        code.push(format!("{}let mut ip: i64 = 0;", indent(1)));
+        code.push(format!("{}loop {{", indent(1)));
+        code.push(format!("{}match ip {{", indent(2)));
+        for (ip, &ins) in self.instructions.iter().enumerate() {
+            let statement = match ins {
+                Instruction::Jnz(v1, v2) => {
+                    let jump = match v2 {
+                        Value::Immediate(ofs) => format!("ip += {};", ofs),
+                        Value::Register(x) => format!("ip += {} as i64;", register_name(x)),
+                    };
+                    match v1 {
+                        Value::Immediate(0) => "ip += 1;".to_owned(),
+                        Value::Immediate(_) => jump,
+                        Value::Register(x) => format!("if {} != 0 {{ {} }} else {{ ip += 1; }}", register_name(x), jump),
+                    }
+                },
+                ins => match self.statement(ins) {
+                    Some(statement) => format!("{} ip += 1;", statement),
+                    None => "ip += 1;".to_owned(),
+                },
+            };
+            code.push(format!("{}{} => {{ {} }}", indent(3), ip, statement));
+        }
+        code.push(format!("{}_ if ip < 0 => panic!(\"jump to negative address {{}}\", ip),", indent(3)));
+        code.push(format!("{}_ => {{ {} }}", indent(3), self.halt()));
+        code.push(format!("{}}}", indent(2)));
+        code.push(format!("{}}}", indent(1)));
+    }
+}
+
+
+/// Transpile a program to a Rust function with the given name. The function
+/// stops when the program halts or has output `max_output` values (0 for no
+/// limit). Registers wrap around on overflow, like the CPU does by default.
+pub fn transpile(instructions: &[Instruction], name: &str) -> Result<String, Error> {
+    for (ip, &ins) in instructions.iter().enumerate() {
+        match ins {
+            Instruction::Tgl(_) | Instruction::Inp(_) => return Err(Error::Unsupported(ip + 1, ins)),
+            _ => (),
+        }
+    }
+    let names: Vec<String> = (0..cmp::max(4, registers_used(instructions))).map(|x| register_name(x as u8).to_string()).collect();
+    let mut generator = Generator {
+        instructions: instructions,
+        loops: optimizer::find_loops(instructions),
+        regs: format!("[{}]", names.join(", ")),
+        enclosing: Vec::new(),
+        labels: HashSet::new(),
+    };
+    // Labels are known after generating a loop's body, so the generation
+    // is repeated if a label is needed
+    let mut code = Vec::new();
+    let mut structured = generator.block(0, instructions.len(), 1, &mut code).is_some();
+    if structured && !generator.labels.is_empty() {
+        code.clear();
+        structured = generator.block(0, instructions.len(), 1, &mut code).is_some();
+    }
+    if !structured {
+        code.clear();
+        generator.dispatch(&mut code);
+    }
+    let mut s = String::new();
+    s.push_str(&format!("/// Generated from an assembunny program with {} instructions\n", instructions.len()));
+    s.push_str("#[allow(unused_mut, unused_variables, unused_assignments, unreachable_code)]\n");
+    s.push_str(&format!("pub fn {}(regs: [i32; {}], max_output: usize) -> ([i32; {}], Vec<i32>) {{\n", name, names.len(), names.len()));
+    let muts: Vec<String> = names.iter().map(|name| format!("mut {}", name)).collect();
+    s.push_str(&format!("    let [{}] = regs;\n", muts.join(", ")));
+    s.push_str("    let mut output = Vec::new();\n");
+    for line in code {
+        s.push_str(&line);
+        s.push('\n');
+    }
+    if structured {
+        s.push_str(&format!("    ({}, output)\n", generator.regs));
+    }
+    s.push_str("}\n");
+    Ok(s)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Dialect;
+
+    fn transpiled(program: &str) -> String {
+        transpile(&Instruction::parse(program, &Dialect::default()).unwrap(), "run").unwrap()
+    }
+
+    #[test]
+    fn transpiling_structured() {
+        assert_eq!(transpiled("cpy 2 a\ncpy a b\ninc c\ndec b\njnz b -2\ndec a\njnz a -5\njnz c 2\ninc d\nout c"), "\
+/// Generated from an assembunny program with 10 instructions
+#[allow(unused_mut, unused_variables, unused_assignments, unreachable_code)]
+pub fn run(regs: [i32; 4], max_output: usize) -> ([i32; 4], Vec<i32>) {
+    let [mut a, mut b, mut c, mut d] = regs;
+    let mut output = Vec::new();
+    a = 2;
+    loop {
+        b = a;
+        loop {
+            // lines 3-5: c += b, b = 0
+            c = c.wrapping_add(1);
+            b = b.wrapping_sub(1);
+            if b == 0 { break; }
+        }
+        a = a.wrapping_sub(1);
+        if a == 0 { break; }
+    }
+    if c == 0 {
+        d = d.wrapping_add(1);
+    }
+    output.push(c); if output.len() == max_output { return ([a, b, c, d], output); }
+    ([a, b, c, d], output)
+}
+");
+    }
+
+    #[test]
+    fn transpiling_jumps() {
+        assert_eq!(transpiled("jnz c 2\njnz 1 4\ndec c\ninc a\njnz 1 -4\njnz d 5\nout a"), "\
+/// Generated from an assembunny program with 7 instructions
+#[allow(unused_mut, unused_variables, unused_assignments, unreachable_code)]
+pub fn run(regs: [i32; 4], max_output: usize) -> ([i32; 4], Vec<i32>) {
+    let [mut a, mut b, mut c, mut d] = regs;
+    let mut output = Vec::new();
+    loop {
+        // lines 1-5: a += c, c = 0
+        if c == 0 {
+            break;
+        }
+        c = c.wrapping_sub(1);
+        a = a.wrapping_add(1);
+    }
+    if d != 0 { return ([a, b, c, d], output); }
+    output.push(a); if output.len() == max_output { return ([a, b, c, d], output); }
+    ([a, b, c, d], output)
+}
+");
+    }
+
+    #[test]
+    fn transpiling_dispatch() {
+        assert_eq!(transpiled("cpy 2 b\njnz 1 b\ninc a\ndec b\njnz b -1"), "\
+/// Generated from an assembunny program with 5 instructions
+#[allow(unused_mut, unused_variables, unused_assignments, unreachable_code)]
+pub fn run(regs: [i32; 4], max_output: usize) -> ([i32; 4], Vec<i32>) {
+    let [mut a, mut b, mut c, mut d] = regs;
+    let mut output = Vec::new();
+    let mut ip: i64 = 0;
+    loop {
+        match ip {
+            0 => { b = 2; ip += 1; }
+            1 => { ip += b as i64; }
+            2 => { a = a.wrapping_add(1); ip += 1; }
+            3 => { b = b.wrapping_sub(1); ip += 1; }
+            4 => { if b != 0 { ip += -1; } else { ip += 1; } }
+            _ if ip < 0 => panic!(\"jump to negative address {}\", ip),
+            _ => { return ([a, b, c, d], output); }
+        }
+    }
+}
+");
+        assert_eq!(transpile(&Instruction::parse("inc a\ntgl a", &Dialect::default()).unwrap(), "run"), Err(Error::Unsupported(2, Instruction::Tgl(0))));
+    }
+}
@@ -0,0 +1,118 @@
+//! Integer types for registers and the treatment of arithmetic overflow.
+//! All arithmetic is done in `i128` and the result is then fitted into the
+//! register's word type according to the overflow policy.
+
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+
+/// Integer type of registers
+pub trait Word: Copy + Eq + Ord + Hash + Default + fmt::Debug + fmt::Display + FromStr + From<i32> + Into<i128> {
+    /// Smallest value
+    const MIN: i128;
+    /// Largest value
+    const MAX: i128;
+
+    /// Convert a value, keeping only the lowest bits if it doesn't fit
+    fn truncate(x: i128) -> Self;
+}
+
+impl Word for i32 {
+    const MIN: i128 = i32::MIN as i128;
+    const MAX: i128 = i32::MAX as i128;
+
+    fn truncate(x: i128) -> i32 {
+        x as i32
+    }
+}
+
+impl Word for i64 {
+    const MIN: i128 = i64::MIN as i128;
+    const MAX: i128 = i64::MAX as i128;
+
+    fn truncate(x: i128) -> i64 {
+        x as i64
+    }
+}
+
+impl Word for i128 {
+    const MIN: i128 = i128::MIN;
+    const MAX: i128 = i128::MAX;
+
+    fn truncate(x: i128) -> i128 {
+        x
+    }
+}
+
+
+/// Treatment of results that don't fit into a register
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Overflow {
+    /// Wrap around (two's complement)
+    Wrap,
+    /// Clamp to the smallest or largest value
+    Saturate,
+    /// Stop the CPU with an error
+    Trap,
+}
+
+impl Default for Overflow {
+    fn default() -> Overflow {
+        Overflow::Wrap
+    }
+}
+
+impl Overflow {
+    /// Fit a result into a word, given the exact result (None if it doesn't
+    /// even fit into `i128`), the wrapped and the saturated result. Returns
+    /// None if the overflow is trapped.
+    pub fn fit<W: Word>(self, exact: Option<i128>, wrapped: i128, saturated: i128) -> Option<W> {
+        match self {
+            Overflow::Wrap => Some(W::truncate(wrapped)),
+            Overflow::Saturate => Some(W::truncate(saturated.max(W::MIN).min(W::MAX))),
+            Overflow::Trap => exact.filter(|&x| x >= W::MIN && x <= W::MAX).map(W::truncate),
+        }
+    }
+
+    /// Add a value to a word. Returns None if the overflow is trapped.
+    pub fn add<W: Word>(self, x: W, y: i128) -> Option<W> {
+        let x: i128 = x.into();
+        self.fit(x.checked_add(y), x.wrapping_add(y), x.saturating_add(y))
+    }
+}
+
+
+/// Trapped overflow of a register
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct OverflowError {
+    /// Address of the instruction (or first instruction of the loop) that overflowed
+    pub addr: usize,
+    /// Register that overflowed
+    pub register: u8,
+    /// Name of the register
+    pub name: char,
+}
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "overflow of register {} at line {}", self.name, self.addr + 1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fitting() {
+        assert_eq!(Overflow::Wrap.add(i32::MAX, 1), Some(i32::MIN));
+        assert_eq!(Overflow::Saturate.add(i32::MAX, 1), Some(i32::MAX));
+        assert_eq!(Overflow::Trap.add(i32::MAX, 1), None::<i32>);
+        assert_eq!(Overflow::Trap.add(i32::MAX as i64, 1), Some(i32::MAX as i64 + 1));
+        assert_eq!(Overflow::Wrap.add(i128::MIN, -1), Some(i128::MAX));
+        assert_eq!(Overflow::Saturate.add(i128::MIN, -1), Some(i128::MIN));
+        assert_eq!(Overflow::Trap.add(i128::MIN, -1), None::<i128>);
+    }
+}
@@ -3,376 +3,246 @@
 #[macro_use]
 extern crate nom;
 
-use std::cmp;
-use std::fmt::{self, Write};
-use std::str::{self, FromStr};
-use nom::{space, digit};
-
-
-/// An immediate or register value
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Value {
-    Immediate(i32),
-    Register(u8),
+#[macro_use]
+mod parse_error;
+mod assembunny;
+mod display;
+mod image;
+mod ocr;
+
+use std::{env, fmt};
+use std::collections::VecDeque;
+use assembunny::{Cpu, Dialect};
+use assembunny::io::Sink;
+use assembunny::transpiler::transpile;
+use assembunny::word::Word;
+use display::Display;
+use image::{Format, Recorder};
+use parse_error::ParseError;
+
+
+/// Error of a line emitted by the program
+#[derive(Debug)]
+pub enum Error {
+    /// Line that can't be parsed
+    Parse(ParseError),
+    /// Line (number and text) with an operation that can't be executed
+    Execute(usize, String, display::Error),
 }
 
-impl Value {
-    /// Get value
-    fn get(&self, regs: &[i32]) -> i32 {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Value::Immediate(x) => x,
-            Value::Register(x) => regs[x as usize],
+            Error::Parse(ref err) => err.fmt(f),
+            Error::Execute(line, ref text, ref err) => write!(f, "invalid operation at line {}: {}\n{}", line, err, text),
         }
     }
 }
 
 
-/// An assembunny instruction
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Instruction {
-    Cpy(Value, Value),
-    Inc(u8),
-    Dec(u8),
-    Jnz(Value, Value),
-    Out(Value),
-}
-
-impl FromStr for Instruction {
-    type Err = nom::ErrorKind;
-
-    fn from_str(s: &str) -> Result<Instruction, nom::ErrorKind> {
-        named!(register<&str, u8>, alt!(
-            value!(0, tag!("a")) |
-            value!(1, tag!("b")) |
-            value!(2, tag!("c")) |
-            value!(3, tag!("d"))
-        ));
-        named!(value<&str, Value>, alt!(
-            do_parse!(
-                sig: opt!(tag!("-")) >>
-                x: map_res!(digit, i32::from_str) >>
-                (Value::Immediate(if sig.is_some() { -x } else { x }))
-            ) |
-            do_parse!(
-                r: register >>
-                (Value::Register(r))
-            )
-        ));
-        alt!(s,
-            do_parse!(
-                tag!("cpy") >> space >>
-                x: value >> space >>
-                y: value >>
-                (Instruction::Cpy(x, y))
-            ) |
-            do_parse!(
-                tag!("inc") >> space >>
-                x: register >>
-                (Instruction::Inc(x))
-            ) |
-            do_parse!(
-                tag!("dec") >> space >>
-                x: register >>
-                (Instruction::Dec(x))
-            ) |
-            do_parse!(
-                tag!("jnz") >> space >>
-                x: value >> space >>
-                y: value >>
-                (Instruction::Jnz(x, y))
-            ) |
-            do_parse!(
-                tag!("out") >> space >>
-                x: value >>
-                (Instruction::Out(x))
-            )
-        ).to_result()
-    }
-}
-
-impl Instruction {
-    /// Parse a multiline-text to a vector of instructions
-    fn parse(s: &str) -> Result<Vec<Instruction>, nom::ErrorKind> {
-        s.lines().map(|line| line.parse()).collect()
-    }
+/// Streaming decoder of display operations. Output values of a program are
+/// decoded as text, and every operation is executed on the display as soon
+/// as its line is complete.
+#[derive(Debug)]
+pub struct Pipeline {
+    display: Display,
+    /// Text of the current line
+    line: String,
+    /// Number of completed lines
+    lines: usize,
+    /// Error of the first line that failed
+    error: Option<Error>,
 }
 
-
-/// The assembunny CPU
-#[derive(Debug, PartialEq, Eq)]
-pub struct Cpu {
-    instructions: Vec<Instruction>,
-    ip: usize,
-    regs: [i32; 4],
-    output: String,
-}
-
-impl Cpu {
-    /// Create new CPU
-    fn new(input: &str) -> Result<Cpu, nom::ErrorKind> {
-        Instruction::parse(input).map(|instructions|
-            Cpu { instructions: instructions, ip: 0, regs: [0; 4], output: String::new() }
-        )
-    }
-
-    /// Detect addition loop. Returns a tuple with related registers.
-    /// 0: first summand, gets result, 1: second summand, gets zeroed
-    fn detect_add_loop(&self, ip: usize) -> Option<(u8, u8)> {
-        use Instruction::*;
-        use Value::*;
-        if ip >= 2 {
-            match (self.instructions[ip-2], self.instructions[ip-1], self.instructions[ip]) {
-                (Inc(tr), Dec(cr1), Jnz(Register(cr2), Immediate(-2))) if cr1==cr2 => return Some((tr, cr1)),
-                (Dec(cr1), Inc(tr), Jnz(Register(cr2), Immediate(-2))) if cr1==cr2 => return Some((tr, cr1)),
-                _ => (),
-            }
+impl<W: Word> Sink<W> for Pipeline {
+    fn write(&mut self, value: W) {
+        if self.error.is_some() {
+            return;
         }
-        None
-    }
-
-    /// Detect multiplication loop. Returns a tuple with related registers.
-    /// 0: gets increased by result, 1: first factor, 2: second factor, gets zeroed, 3: gets zeroed
-    fn detect_mul_loop(&self, ip: usize) -> Option<(u8, u8, u8, u8)> {
-        use Instruction::*;
-        use Value::*;
-        if ip >= 5 {
-            match (self.instructions[ip-5], self.detect_add_loop(ip-2), self.instructions[ip-1], self.instructions[ip]) {
-                (Cpy(Register(ar), Register(cr1)), Some((tr, cr2)), Dec(br1), Jnz(Register(br2), Immediate(-5))) if cr1==cr2 && br1==br2 => return Some((tr, ar, br1, cr1)),
-                _ => (),
-            }
+        Sink::write(&mut self.line, value);
+        if self.line.ends_with('\n') {
+            self.line.pop();
+            self.end_line();
         }
-        None
-    }
-
-    /// Step program. Returns true if done
-    fn step(&mut self) -> bool {
-        if self.ip >= self.instructions.len() {
-            true
-        } else {
-            let ip = self.ip;
-            let ins = self.instructions[ip];
-            match ins {
-                Instruction::Cpy(_, Value::Immediate(_)) => { /* ignore invalid cpy */ },
-                Instruction::Cpy(ref v, Value::Register(y)) => self.regs[y as usize] = v.get(&self.regs),
-                Instruction::Inc(x) => self.regs[x as usize] += 1,
-                Instruction::Dec(x) => self.regs[x as usize] -= 1,
-                Instruction::Jnz(ref v1, ref v2) => {
-                    if v1.get(&self.regs) != 0 {
-                        if let Some((x, y, z, zz)) = self.detect_mul_loop(ip) {
-                            // Optimized multiplication loop
-                            self.regs[x as usize] += self.regs[y as usize] * self.regs[z as usize];
-                            self.regs[z as usize] = 0;
-                            self.regs[zz as usize] = 0;
-                            self.ip += 1;
-                        } else if let Some((x, y)) = self.detect_add_loop(ip) {
-                            // Optimized addition loop
-                            self.regs[x as usize] += self.regs[y as usize];
-                            self.regs[y as usize] = 0;
-                            self.ip += 1;
-                        } else {
-                            // Unoptimized loop
-                            self.ip = (self.ip as i32 + v2.get(&self.regs)) as usize;
-                        }
-                    } else {
-                        self.ip += 1
-                    }
-                },
-                Instruction::Out(ref v) => self.output.push(v.get(&self.regs) as u8 as char),
-            }
-            match self.instructions[ip] {
-                Instruction::Jnz(_, _) => (),
-                _ => self.ip += 1,
-            }
-            false
-        }
-    }
-
-    /// Run program
-    fn run(&mut self) {
-        while !self.step() { }
-    }
-}
-
-
-/// An operation is an instruction to modify pixels on a display
-#[derive(Debug, PartialEq, Eq)]
-pub enum Operation {
-    /// Draw rectangle in top left corner
-    Rect { width: usize, height: usize },
-    /// Rotate pixels right in a row
-    RotateRow { row: usize, count: usize },
-    /// Rotate pixels down in a column
-    RotateColumn { column: usize, count: usize },
-}
-
-impl FromStr for Operation {
-    type Err = nom::ErrorKind;
-
-    fn from_str(s: &str) -> Result<Operation, nom::ErrorKind> {
-        complete!(s, alt!(
-            do_parse!(
-                tag!("rect ") >>
-                width: map_res!(nom::digit, FromStr::from_str) >>
-                tag!("x") >>
-                height: map_res!(nom::digit, FromStr::from_str) >>
-                (Operation::Rect { width: width, height: height })
-            ) |
-            do_parse!(
-                tag!("rotate row y=") >>
-                row: map_res!(nom::digit, FromStr::from_str) >>
-                tag!(" by ") >>
-                count: map_res!(nom::digit, FromStr::from_str) >>
-                (Operation::RotateRow { row: row, count: count })
-            ) |
-            do_parse!(
-                tag!("rotate column x=") >>
-                column: map_res!(nom::digit, FromStr::from_str) >>
-                tag!(" by ") >>
-                count: map_res!(nom::digit, FromStr::from_str) >>
-                (Operation::RotateColumn { column: column, count: count })
-            )
-        )).to_result()
-    }
-}
-
-impl Operation {
-    /// Parse a multiline-text to a vector of operations
-    fn parse(s: &str) -> Result<Vec<Operation>, nom::ErrorKind> {
-        s.lines().map(|line| line.parse()).collect()
     }
 }
 
-
-/// A rectangular display with monochromatic pixels
-#[derive(Debug, PartialEq, Eq)]
-pub struct Display {
-    pixels: Vec<Vec<bool>>,
-}
-
-impl fmt::Display for Display {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for y in 0..self.pixels.len() {
-            for x in 0..self.pixels[y].len() {
-                match self.pixels[y][x] {
-                    false => try!(f.write_char('.')),
-                    true => try!(f.write_char('#')),
-                }
-            }
-            try!(f.write_str("\n"));
-        }
-        Ok(())
-    }
-}
-
-impl Display {
-    /// Create a new display with the given number of pixels in width and height
-    fn new(width: usize, height: usize) -> Display {
-        Display {
-            pixels: vec![vec![false; width]; height],
-        }
-    }
-
-    /// Draw a rectangle
-    fn rect(&mut self, x: usize, y: usize, w: usize, h: usize) {
-        for dy in 0..h {
-            for dx in 0..w {
-                let yy = cmp::min(self.pixels.len() - 1, y + dy);
-                let xx = cmp::min(self.pixels[yy].len() - 1, x + dx);
-                self.pixels[yy][xx] = true;
-            }
-        }
-    }
-
-    /// Rotate a row
-    fn rotate_row(&mut self, y: usize, c: usize) {
-        let ofs = self.pixels[y].len() - c;
-        self.pixels[y] = self.pixels[y].iter().skip(ofs).chain( self.pixels[y].iter().take(ofs) ).map(|px| *px).collect();
-    }
-
-    /// Rotate a column
-    fn rotate_column(&mut self, x: usize, c: usize) {
-        let ofs = self.pixels.len() - c;
-        let pixels: Vec<bool> = self.pixels.iter().map(|row| row[x]).collect();
-        for (y, px) in pixels.iter().skip(ofs).chain( pixels.iter().take(ofs) ).map(|px| *px).enumerate() {
-            self.pixels[y][x] = px;
+impl Pipeline {
+    /// Create new pipeline that draws on the given display (which records a
+    /// frame after every operation if it is in recording mode)
+    fn new(display: Display) -> Pipeline {
+        Pipeline { display: display, line: String::new(), lines: 0, error: None }
+    }
+
+    /// Parse the current line and execute its operation
+    fn end_line(&mut self) {
+        self.lines += 1;
+        match self.line.parse() {
+            Ok(op) => if let Err(err) = self.display.run(&[op]) {
+                self.error = Some(Error::Execute(self.lines, self.line.clone(), err));
+            },
+            Err(err) => self.error = Some(Error::Parse(err.in_line(self.lines))),
         }
+        self.line.clear();
     }
 
-    /// Execute an operation
-    fn execute(&mut self, op: &Operation) {
-        match *op {
-            Operation::Rect { width: w, height: h } => self.rect(0, 0, w, h),
-            Operation::RotateRow { row: y, count: c } => self.rotate_row(y, c),
-            Operation::RotateColumn { column: x, count: c } => self.rotate_column(x, c),
+    /// Finish decoding (a last line may be missing its line break). Returns
+    /// the display.
+    fn finish(mut self) -> Result<Display, Error> {
+        if self.error.is_none() && !self.line.is_empty() {
+            self.end_line();
         }
-    }
-
-    /// Execute a list of operations
-    fn run(&mut self, ops: &[Operation]) {
-        for op in ops {
-            self.execute(op);
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.display),
         }
     }
 }
 
 
 fn main() {
-    let mut cpu = Cpu::new(include_str!("bonuschallenge.txt")).unwrap();
-    cpu.run();
-    let operations = Operation::parse(&cpu.output).unwrap();
+    let mut cpu = Cpu::new(include_str!("bonuschallenge.txt"), &Dialect::day25()).unwrap();
+    if env::args().any(|arg| arg == "--transpile") {
+        print!("{}", transpile(&cpu.instructions, "bonuschallenge").unwrap());
+        return;
+    }
+    cpu.shortcuts = !env::args().any(|arg| arg == "--reference");
+    let profile = env::args().any(|arg| arg == "--profile");
+    if profile {
+        cpu.start_profile();
+    }
     let mut display = Display::new(50, 6);
-    display.run(&operations);
+    if let Some(dir) = env::args().skip_while(|arg| arg != "--record").nth(1) {
+        let format = env::args().skip_while(|arg| arg != "--format").nth(1).map(|s| s.parse().unwrap()).unwrap_or(Format::Pgm);
+        display.start_recording(Recorder::new(dir, format).unwrap());
+    }
+    let mut pipeline = Pipeline::new(display);
+    cpu.run_io(&mut VecDeque::new(), &mut pipeline).unwrap();
+    if profile {
+        eprint!("{}", cpu.stop_profile().unwrap().listing(&cpu.instructions, &cpu.names));
+    }
+    let mut display = match pipeline.finish() {
+        Ok(display) => display,
+        Err(err) => {
+            println!("Program emitted an invalid operation: {}", err);
+            return;
+        },
+    };
+    if let Some(recorder) = display.stop_recording() {
+        println!("Recorded {} frames", recorder.frames());
+    }
+    if let Some(path) = env::args().skip_while(|arg| arg != "--image").nth(1) {
+        display.save(&path).unwrap();
+    }
     println!("{}", display);
+    match display.text() {
+        Ok(text) => println!("Text on display: {}", text.trim()),
+        Err(err) => println!("Text on display can't be read: {}", err),
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn cpu_parsing() {
-        assert_eq!("inc a".parse(), Ok(Instruction::Inc(0)));
-        assert_eq!(Instruction::parse("cpy 41 a\ninc b\ndec c\njnz d 2\njnz 1 -2"), Ok(vec![
-            Instruction::Cpy(Value::Immediate(41), Value::Register(0)),
-            Instruction::Inc(1),
-            Instruction::Dec(2),
-            Instruction::Jnz(Value::Register(3), Value::Immediate(2)),
-            Instruction::Jnz(Value::Immediate(1), Value::Immediate(-2)),
-        ]));
-    }
+    use std::fs;
+    use assembunny::assembler::assemble;
+    use assembunny::differential::{compare, Agreement};
+    use assembunny::disassembler::disassemble;
+    use assembunny::linter::lint;
 
     #[test]
     fn cpu_parsing_real() {
-        let cpu = Cpu::new(include_str!("bonuschallenge.txt")).unwrap();
+        let cpu = Cpu::new(include_str!("bonuschallenge.txt"), &Dialect::day25()).unwrap();
         assert_eq!(cpu.instructions.len(), 1236);
     }
 
     #[test]
-    fn cpu_running() {
-        let mut cpu = Cpu::new("cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a").unwrap();
-        cpu.run();
-        assert_eq!(cpu.regs[0], 42);
+    fn cpu_disassembling() {
+        let cpu = Cpu::new(include_str!("bonuschallenge.txt"), &Dialect::day25()).unwrap();
+        let listing = disassemble(&cpu.instructions, &Dialect::day25());
+        assert_eq!(assemble(&listing, &Dialect::day25()), Ok(cpu.instructions));
     }
 
     #[test]
-    fn display_parsing() {
-        assert_eq!("rect 12x34".parse(), Ok(Operation::Rect { width: 12, height: 34 }));
-        assert_eq!("rotate row y=23 by 45".parse(), Ok(Operation::RotateRow { row: 23, count: 45 }));
-        assert_eq!("rotate column x=34 by 56".parse(), Ok(Operation::RotateColumn { column: 34, count: 56 }));
+    fn cpu_comparing_interpreters() {
+        let cpu = Cpu::new(include_str!("bonuschallenge.txt"), &Dialect::day25()).unwrap();
+        assert_eq!(compare::<i32, _>(&cpu.instructions, |_| (), 10000000), Ok(Agreement::Complete(57299)));
     }
 
     #[test]
-    fn display_operations() {
+    fn streaming_operations() {
+        let dir = env::temp_dir().join(format!("aoc2016-bonuschallenge-{}", ::std::process::id()));
         let mut display = Display::new(7, 3);
-        assert_eq!(format!("{}", display), ".......\n.......\n.......\n");
-        display.execute(&Operation::Rect { width: 3, height: 2 });
-        assert_eq!(format!("{}", display), "###....\n###....\n.......\n");
-        display.execute(&Operation::RotateColumn { column: 1, count: 1 });
-        assert_eq!(format!("{}", display), "#.#....\n###....\n.#.....\n");
-        display.execute(&Operation::RotateRow { row: 0, count: 4 });
+        display.start_recording(Recorder::new(&dir, Format::Pbm).unwrap());
+        let mut pipeline = Pipeline::new(display);
+        for b in "rect 3x2\nrotate column x=1 by 1\nrotate row y=0 by 4".bytes() {
+            Sink::<i32>::write(&mut pipeline, b as i32);
+        }
+        let mut display = pipeline.finish().unwrap();
         assert_eq!(format!("{}", display), "....#.#\n###....\n.#.....\n");
-        display.execute(&Operation::RotateColumn { column: 1, count: 1 });
-        assert_eq!(format!("{}", display), ".#..#.#\n#.#....\n.#.....\n");
+        let recorder = display.stop_recording().unwrap();
+        assert_eq!(recorder.frames(), 3);
+        assert_eq!(fs::read_to_string(recorder.path(1)).unwrap(), "P1\n7 3\n1110000\n1110000\n0000000\n");
+        assert_eq!(fs::read_to_string(recorder.path(2)).unwrap(), "P1\n7 3\n1010000\n1110000\n0100000\n");
+        assert_eq!(fs::read_to_string(recorder.path(3)).unwrap(), "P1\n7 3\n0000101\n1110000\n0100000\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streaming_invalid_operation() {
+        let mut pipeline = Pipeline::new(Display::new(7, 3));
+        for b in "rect 3x2\nrotate column y=1 by 1\nrect 1x1\n".bytes() {
+            Sink::<i32>::write(&mut pipeline, b as i32);
+        }
+        assert_eq!(pipeline.finish().unwrap_err().to_string(), "invalid input at line 2, column 15:\nrotate column y=1 by 1\n              ^");
+    }
+
+    #[test]
+    fn streaming_failing_operation() {
+        for (text, message) in vec![
+            ("rect 3x2\nrotate row y=9 by 1\n", "invalid operation at line 2: no row y=9\nrotate row y=9 by 1"),
+            ("rect 8x1", "invalid operation at line 1: rectangle 8x1 at 0,0 exceeds the display\nrect 8x1"),
+        ] {
+            let mut pipeline = Pipeline::new(Display::new(7, 3));
+            for b in text.bytes() {
+                Sink::<i32>::write(&mut pipeline, b as i32);
+            }
+            assert_eq!(pipeline.finish().unwrap_err().to_string(), message);
+        }
+        let mut pipeline = Pipeline::new(Display::new(7, 3));
+        for b in "rect 3x2\nrotate row y=0 by 60\n".bytes() {
+            Sink::<i32>::write(&mut pipeline, b as i32);
+        }
+        assert_eq!(pipeline.finish().unwrap().to_string(), "....###\n###....\n.......\n");
+    }
+
+    #[test]
+    fn streaming_real() {
+        let mut cpu = Cpu::new(include_str!("bonuschallenge.txt"), &Dialect::day25()).unwrap();
+        let mut pipeline = Pipeline::new(Display::new(50, 6));
+        cpu.run_io(&mut VecDeque::new(), &mut pipeline).unwrap();
+        let display = pipeline.finish().unwrap();
+        assert_eq!(display.text().unwrap().trim(), "AoC 2017");
+        assert_eq!(format!("{}", display), "\
+......##........##........##....#....#..####......
+.....#..#......#..#......#..#..#.#..##.....#......
+.....#..#..##..#............#..#.#...#....#.......
+.....####.#..#.#...........#...#.#...#....#.......
+.....#..#.#..#.#..#.......#....#.#...#...#........
+.....#..#..##...##.......####...#...###..#........
+");
+    }
+
+    #[test]
+    fn linting() {
+        let cpu = Cpu::new(include_str!("bonuschallenge.txt"), &Dialect::day25()).unwrap();
+        let warnings: Vec<String> = lint(&cpu.instructions, true).iter().map(|w| w.to_string()).collect();
+        // Subroutines return with a jump by a register offset, which may go anywhere
+        assert_eq!(warnings, vec![
+            "line 1163: register a is read before it is written",
+            "line 1166: register b is read before it is written",
+            "line 1169: register c is read before it is written",
+        ]);
     }
 }
@@ -1,5 +1,8 @@
+mod parse_error;
+
 use std::collections::HashSet;
 use std::str::FromStr;
+use parse_error::ParseError;
 
 
 /// Turning direction of a step
@@ -10,13 +13,13 @@ pub enum Turn {
 }
 
 impl FromStr for Turn {
-    type Err = &'static str;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Turn, &'static str> {
+    fn from_str(s: &str) -> Result<Turn, ParseError> {
         match s.as_ref() {
             "L" => Ok(Turn::Left),
             "R" => Ok(Turn::Right),
-            _ => Err("Invalid turn format"),
+            _ => Err(ParseError::at(s, 0)),
         }
     }
 }
@@ -31,20 +34,25 @@ pub struct Step {
 }
 
 impl FromStr for Step {
-    type Err = &'static str;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Step, &'static str> {
+    fn from_str(s: &str) -> Result<Step, ParseError> {
+        let split = s.char_indices().nth(1).map_or(s.len(), |(i, _)| i);
         Ok(Step {
-            turn: try!(s[0..1].parse()),
-            dist: try!(s[1..].parse().map_err(|_| "Invalid step distance format")),
+            turn: try!(s[..split].parse()),
+            dist: try!(s[split..].parse().map_err(|_| ParseError::at(s, split))),
         })
     }
 }
 
 impl Step {
     /// Parse a comma-separated string of step instructions. Returns a vector of steps
-    fn parse(input: &str) -> Result<Vec<Step>, &'static str> {
-        input.split(',').map(|s| s.trim().parse()).collect()
+    fn parse(input: &str) -> Result<Vec<Step>, ParseError> {
+        let input = input.trim_right();
+        input.split(',').map(|s| {
+            let s = s.trim();
+            s.parse().map_err(|err: ParseError| err.within(input, s))
+        }).collect()
     }
 }
 
@@ -155,6 +163,9 @@ mod tests {
     fn parsing() {
         assert_eq!("R2".parse(), Ok(Step { turn: Turn::Right, dist: 2 }));
         assert_eq!(Step::parse("R2, L3"), Ok(vec![Step { turn: Turn::Right, dist: 2 }, Step { turn: Turn::Left, dist: 3 }]));
+        assert_eq!(Step::parse("R2, X3").unwrap_err(), ParseError { line: 1, column: 5, text: "R2, X3".to_owned() });
+        assert_eq!(Step::parse("R2, L3x\n").unwrap_err().column, 6);
+        assert_eq!(Step::parse("R2,").unwrap_err().column, 4);
     }
 
     #[test]
@@ -1,4 +1,7 @@
+mod parse_error;
+
 use std::iter::FromIterator;
+use parse_error::ParseError;
 
 
 /// Directional move
@@ -10,26 +13,19 @@ pub enum Move {
     Right,
 }
 
-// FIXME: Should use TryFrom (still feature-gated)
-impl From<char> for Move {
-    fn from(ch: char) -> Move {
-        match ch {
-            'U' => Move::Up,
-            'D' => Move::Down,
-            'L' => Move::Left,
-            'R' => Move::Right,
-            _ => panic!("Invalid move format"),
-        }
-    }
-}
-
 impl Move {
-    fn parse(s: &str) -> Result<Vec<Move>, &'static str> {
-        Ok(s.chars().map(|ch| Move::from(ch)).collect())
+    fn parse(s: &str) -> Result<Vec<Move>, ParseError> {
+        s.char_indices().map(|(i, ch)| match ch {
+            'U' => Ok(Move::Up),
+            'D' => Ok(Move::Down),
+            'L' => Ok(Move::Left),
+            'R' => Ok(Move::Right),
+            _ => Err(ParseError::at(s, i)),
+        }).collect()
     }
 
-    fn parse_lines(s: &str) -> Result<Vec<Vec<Move>>, &'static str> {
-        s.lines().map(|line| Move::parse(line)).collect()
+    fn parse_lines(s: &str) -> Result<Vec<Vec<Move>>, ParseError> {
+        parse_error::parse_lines(s, Move::parse)
     }
 }
 
@@ -149,6 +145,11 @@ mod tests {
         assert_eq!(kp.walk('8', &Move::parse("UUUUD").unwrap()), '5');
     }
 
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!(Move::parse_lines("ULL\nRRxDD").unwrap_err(), ParseError { line: 2, column: 3, text: "RRxDD".to_owned() });
+    }
+
     #[test]
     fn walking_n() {
         let kp = Keypad::new_3x3();
@@ -1,8 +1,7 @@
-extern crate itertools;
+mod parse_error;
 
-use std::num;
 use std::str::FromStr;
-use itertools::Itertools;
+use parse_error::ParseError;
 
 
 /// A triangle, specified by the side lengths
@@ -13,39 +12,45 @@ pub struct Triangle {
     lc: u32,
 }
 
+/// Parse a line of whitespace-separated numbers
+fn numbers(line: &str) -> Result<Vec<u32>, ParseError> {
+    line.split_whitespace().map(|s| s.parse().map_err(|_| ParseError::of(line, s))).collect()
+}
+
+/// Number at the given index (0 if missing)
+fn number(numbers: &[u32], i: usize) -> u32 {
+    numbers.get(i).cloned().unwrap_or(0)
+}
+
 impl FromStr for Triangle {
-    type Err = num::ParseIntError;
+    type Err = ParseError;
 
-    fn from_str(line: &str) -> Result<Triangle, num::ParseIntError> {
-        let mut it = line.split_whitespace().map(|s| s.parse());
+    fn from_str(line: &str) -> Result<Triangle, ParseError> {
+        let lengths = try!(numbers(line));
         Ok(Triangle {
-            la: try!(it.next().unwrap_or(Ok(0))),
-            lb: try!(it.next().unwrap_or(Ok(0))),
-            lc: try!(it.next().unwrap_or(Ok(0))),
+            la: number(&lengths, 0),
+            lb: number(&lengths, 1),
+            lc: number(&lengths, 2),
         })
     }
 }
 
 impl Triangle {
     /// Parse a text with triangle lengths (3 numbers per line)
-    fn parse(s: &str) -> Result<Vec<Triangle>, num::ParseIntError> {
-        s.lines().map(|line| line.parse()).collect()
+    fn parse(s: &str) -> Result<Vec<Triangle>, ParseError> {
+        parse_error::parse_lines(s, |line| line.parse())
     }
 
     /// Vertically parse a text with triangle lengths (3 numbers per column)
-    fn parse_vertical(s: &str) -> Result<Vec<Triangle>, num::ParseIntError> {
-        s.lines().tuples().flat_map(|(line1, line2, line3)| {
-            let mut it1 = line1.split_whitespace();
-            let mut it2 = line2.split_whitespace();
-            let mut it3 = line3.split_whitespace();
-            (0..3).map(|_|
-                format!("{} {} {}",
-                    it1.next().unwrap_or(""),
-                    it2.next().unwrap_or(""),
-                    it3.next().unwrap_or("")
-                ).parse()
-            ).collect::<Vec<_>>()
-        }).collect()
+    fn parse_vertical(s: &str) -> Result<Vec<Triangle>, ParseError> {
+        let rows = try!(parse_error::parse_lines(s, numbers));
+        Ok(rows.chunks(3).filter(|rows| rows.len() == 3).flat_map(|rows| {
+            (0..3).map(move |i| Triangle {
+                la: number(&rows[0], i),
+                lb: number(&rows[1], i),
+                lc: number(&rows[2], i),
+            })
+        }).collect())
     }
 
     /// True if the triangle is valid (i.e. any side length plus any other side length is
@@ -78,6 +83,12 @@ mod tests {
         assert_eq!(triangles[1], Triangle { la: 10, lb: 20, lc: 25 });
     }
 
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!(Triangle::parse("5 10 25\n10 2x 25").unwrap_err(), ParseError { line: 2, column: 4, text: "10 2x 25".to_owned() });
+        assert_eq!(Triangle::parse_vertical("1 2 3\n4 5 6\n7 8 -9").unwrap_err().column, 5);
+    }
+
     #[test]
     fn parsing_vertically() {
         let triangles = Triangle::parse_vertical("101 301 501\n102 302 502\n103 303 503\n201 401 601\n202 402 602\n203 403 603").unwrap();
@@ -1,5 +1,8 @@
+mod parse_error;
+
 use std::collections::HashMap;
 use std::str::FromStr;
+use parse_error::ParseError;
 
 
 /// A room, described by its encrypted name
@@ -11,29 +14,26 @@ pub struct Room {
 }
 
 impl FromStr for Room {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Room, &'static str> {
-        s.rfind('[').and_then(|ic| {
-            let checksum = s[ic+1..s.len()-1].to_owned();
-            s[..ic].rfind('-').and_then(|is| {
-                let name = s[..is].to_owned();
-                s[is+1..ic].parse().map(|sector|
-                    Room {
-                        encrypted_name: name,
-                        sector_id: sector,
-                        checksum: checksum,
-                    }
-                ).ok()
-            })
-        }).ok_or("Invalid room format")
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Room, ParseError> {
+        let ic = try!(s.rfind('[').ok_or(ParseError::at(s, s.len())));
+        if !s.ends_with(']') {
+            return Err(ParseError::at(s, s.len()));
+        }
+        let is = try!(s[..ic].rfind('-').ok_or(ParseError::at(s, 0)));
+        Ok(Room {
+            encrypted_name: s[..is].to_owned(),
+            sector_id: try!(s[is+1..ic].parse().map_err(|_| ParseError::at(s, is + 1))),
+            checksum: s[ic+1..s.len()-1].to_owned(),
+        })
     }
 }
 
 impl Room {
     /// Parse a text with room descriptions
-    fn parse(s: &str) -> Result<Vec<Room>, &'static str> {
-        s.lines().map(|line| line.parse()).collect()
+    fn parse(s: &str) -> Result<Vec<Room>, ParseError> {
+        parse_error::parse_lines(s, |line| line.parse())
     }
 
     /// Calculate checksum
@@ -95,6 +95,13 @@ mod tests {
         assert_eq!(rooms[3], Room { encrypted_name: "totally-real-room".to_owned(), sector_id: 200, checksum: "decoy".to_owned() });
     }
 
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!(Room::parse("a-b-1[abc]\na-b-c1[abc]").unwrap_err().to_string(), "invalid input at line 2, column 5:\na-b-c1[abc]\n    ^");
+        assert_eq!(Room::parse("a-b-1[abc").unwrap_err().column, 10);
+        assert_eq!(Room::parse("ab1[abc]").unwrap_err().column, 1);
+    }
+
     #[test]
     fn checking() {
         let rooms = Room::parse("aaaaa-bbb-z-y-x-123[abxyz]\na-b-c-d-e-f-g-h-987[abcde]\nnot-a-real-room-404[oarel]\ntotally-real-room-200[decoy]").unwrap();
@@ -1,10 +1,14 @@
 #[macro_use]
 extern crate nom;
 
+#[macro_use]
+mod parse_error;
+
 use std::cmp;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::str::FromStr;
+use parse_error::ParseError;
 
 
 /// Target of a bot can be either another bot or an output box
@@ -33,9 +37,9 @@ pub enum Instruction {
 }
 
 impl FromStr for Instruction {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Instruction, nom::ErrorKind> {
+    fn from_str(s: &str) -> Result<Instruction, ParseError> {
 
         named!(target<&str, Target>, alt!(
             // bot <b>
@@ -52,7 +56,7 @@ impl FromStr for Instruction {
             )
         ));
 
-        complete!(s, alt!(
+        parse_line!(s, alt!(
             // value <v> goes to bot <b>
             do_parse!(
                 tag!("value ") >>
@@ -71,14 +75,14 @@ impl FromStr for Instruction {
                 th: target >>
                 (Instruction::BotToTarget(b, tl, th))
             )
-        )).to_result()
+        ))
     }
 }
 
 impl Instruction {
     /// Parse a multiline-text to a vector of instructions
-    fn parse(s: &str) -> Result<Vec<Instruction>, nom::ErrorKind> {
-        s.lines().map(|line| line.parse()).collect()
+    fn parse(s: &str) -> Result<Vec<Instruction>, ParseError> {
+        parse_error::parse_lines(s, |line| line.parse())
     }
 }
 
@@ -107,9 +111,9 @@ pub struct Factory {
 }
 
 impl FromStr for Factory {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Factory, nom::ErrorKind> {
+    fn from_str(s: &str) -> Result<Factory, ParseError> {
         Instruction::parse(s).map(|instructions|
             Factory::from(instructions)
         )
@@ -236,6 +240,12 @@ mod tests {
         assert_eq!("bot 1 gives low to output 1 and high to bot 0".parse(), Ok(Instruction::BotToTarget(1, Target::Output(1), Target::Bot(0))));
     }
 
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!("bot 1 gives low to robot 1 and high to bot 0".parse::<Instruction>().unwrap_err().column, 20);
+        assert_eq!("value 5 goes to bot".parse::<Instruction>().unwrap_err().column, 20);
+    }
+
     #[test]
     fn finding_values() {
         let factory: Factory = "value 5 goes to bot 2\nbot 2 gives low to bot 1 and high to bot 0\nvalue 3 goes to bot 1\nbot 1 gives low to output 1 and high to bot 0\nbot 0 gives low to output 2 and high to output 0\nvalue 2 goes to bot 2".parse().unwrap();
@@ -1,22 +1,20 @@
+#[macro_use]
+extern crate nom;
+
+#[macro_use]
+mod parse_error;
+
 use std::collections::BTreeSet;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 use std::{env, fmt};
+use nom::{alpha, digit};
+use parse_error::ParseError;
 
 pub use self::Thing::*;
 
 
-macro_rules! set {
-    () => ( ::std::collections::BTreeSet::new() );
-    ($($x:expr),*) => ({
-        let mut set = ::std::collections::BTreeSet::new();
-        $(set.insert($x);)*
-        set
-    });
-    ($($x:expr,)*) => ( set![$($x),*] )
-}
-
-
 /// Things that can be moved around
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum Thing {
@@ -34,6 +32,93 @@ impl fmt::Display for Thing {
 }
 
 
+/// Ordinal numbers of floors in words
+const ORDINALS: &'static [&'static str] = &[
+    "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth", "tenth",
+    "eleventh", "twelfth", "thirteenth", "fourteenth", "fifteenth", "sixteenth", "seventeenth", "eighteenth", "nineteenth", "twentieth",
+];
+
+// Ordinal number of a floor (starting at 0), in words (first) or digits (21st)
+named!(ordinal<&str, usize>, alt!(
+    map_opt!(alpha, |word| ORDINALS.iter().position(|&o| o == word)) |
+    map_opt!(
+        terminated!(map_res!(digit, FromStr::from_str), alt!(tag!("st") | tag!("nd") | tag!("rd") | tag!("th"))),
+        |n: usize| n.checked_sub(1)
+    )
+));
+
+// a <element> generator, a <element>-compatible microchip (element and
+// whether it's a generator)
+named!(thing<&str, (&str, bool)>, do_parse!(
+    alt!(tag!("an ") | tag!("a ")) >>
+    element: alpha >>
+    generator: alt!(value!(true, tag!(" generator")) | value!(false, tag!("-compatible microchip"))) >>
+    (element, generator)
+));
+
+// The <ordinal> floor contains <things>.
+named!(floor<&str, (usize, Vec<(&str, bool)>)>, do_parse!(
+    tag!("The ") >>
+    n: ordinal >>
+    tag!(" floor contains ") >>
+    things: alt!(
+        value!(vec![], tag!("nothing relevant")) |
+        do_parse!(
+            first: thing >>
+            rest: many0!(preceded!(alt!(tag!(", and ") | tag!(", ") | tag!(" and ")), thing)) >>
+            (Some(first).into_iter().chain(rest).collect())
+        )
+    ) >>
+    tag!(".") >>
+    (n, things)
+));
+
+/// Parse a sentence telling the things on a floor. Returns the floor number
+/// (starting at 0) and the things (element and whether it's a generator).
+fn parse_floor(line: &str) -> Result<(usize, Vec<(&str, bool)>), ParseError> {
+    match floor(line) {
+        nom::IResult::Done("", value) => Ok(value),
+        // Parse again with a parser that returns no elements (which can't outlive
+        // the given parser function), to locate the error
+        _ => Err(parse_line!(line, map!(floor, |_| ())).unwrap_err()),
+    }
+}
+
+
+/// Error of parsing the arrangement of things
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A sentence that can't be understood
+    Parse(ParseError),
+    /// There are no floors
+    NoFloors,
+    /// A thing that is mentioned more than once
+    Duplicate(Thing),
+    /// A generator without a microchip of the same element, or a microchip
+    /// without a generator of the same element
+    Unpaired(Thing),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref err) => err.fmt(f),
+            Error::NoFloors => write!(f, "no floors"),
+            Error::Duplicate(Generator(e)) => write!(f, "{} generator is mentioned more than once", e),
+            Error::Duplicate(Microchip(e)) => write!(f, "{}-compatible microchip is mentioned more than once", e),
+            Error::Unpaired(Generator(e)) => write!(f, "{} generator has no {}-compatible microchip", e, e),
+            Error::Unpaired(Microchip(e)) => write!(f, "{}-compatible microchip has no {} generator", e, e),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+
 /// Current arrangement of things
 #[derive(PartialEq, Eq, Clone)]
 pub struct State {
@@ -93,6 +178,39 @@ impl State {
         State { floors: floors, elevator: 0 }
     }
 
+    /// Parse sentences telling the things on each floor, from the first
+    /// floor upwards. Every generator needs a microchip of the same element
+    /// and vice versa.
+    fn parse(s: &'static str) -> Result<State, Error> {
+        let mut floors = Vec::new();
+        let mut seen = BTreeSet::new();
+        for (i, line) in s.lines().enumerate() {
+            let (n, things) = parse_floor(line).map_err(|err| err.in_line(i + 1))?;
+            if n != i {
+                return Err(ParseError::at(line, "The ".len()).in_line(i + 1).into());
+            }
+            let mut floor = BTreeSet::new();
+            for (element, generator) in things {
+                let thing = if generator { Generator(element) } else { Microchip(element) };
+                if !seen.insert(thing.clone()) {
+                    return Err(Error::Duplicate(thing));
+                }
+                floor.insert(thing);
+            }
+            floors.push(floor);
+        }
+        if floors.is_empty() {
+            return Err(Error::NoFloors);
+        }
+        for thing in &seen {
+            let pair = match *thing { Generator(e) => Microchip(e), Microchip(e) => Generator(e) };
+            if !seen.contains(&pair) {
+                return Err(Error::Unpaired(thing.clone()));
+            }
+        }
+        Ok(State::new(floors))
+    }
+
     /// Check if the current arrangement is valid (i.e. no microchip
     /// above the first floor is exposed to a genreator without being
     /// protected)
@@ -186,25 +304,14 @@ impl State {
 
 
 fn main() {
-    let mut state = State::new(vec![
-        // The first floor contains a polonium generator, a thulium generator, a thulium-compatible microchip,
-        // a promethium generator, a ruthenium generator, a ruthenium-compatible microchip,
-        // a cobalt generator, and a cobalt-compatible microchip.
-        set![Generator("Po"), Generator("Tm"), Microchip("Tm"), Generator("Pm"), Generator("Ru"), Microchip("Ru"), Generator("Co"), Microchip("Co")],
-        // The second floor contains a polonium-compatible microchip and a promethium-compatible microchip.
-        set![Microchip("Po"), Microchip("Pm")],
-        // The third floor contains nothing relevant.
-        set![],
-        // The fourth floor contains nothing relevant.
-        set![],
-    ]);
+    let mut state = State::parse(include_str!("day11.txt")).unwrap();
     println!("Minimum number of steps: {}", state.min_steps());
     // Don't run the tedious part two on CI
     if !env::var("CI").is_ok() {
-        state.floors[0].insert(Generator("El"));
-        state.floors[0].insert(Microchip("El"));
-        state.floors[0].insert(Generator("Di"));
-        state.floors[0].insert(Microchip("Di"));
+        state.floors[0].insert(Generator("elerium"));
+        state.floors[0].insert(Microchip("elerium"));
+        state.floors[0].insert(Generator("dilithium"));
+        state.floors[0].insert(Microchip("dilithium"));
         println!("Minimum number of steps with extra parts: {}", state.min_steps());
     }
 }
@@ -214,6 +321,16 @@ fn main() {
 mod tests {
     use super::*;
 
+    macro_rules! set {
+        () => ( ::std::collections::BTreeSet::new() );
+        ($($x:expr),*) => ({
+            let mut set = ::std::collections::BTreeSet::new();
+            $(set.insert($x);)*
+            set
+        });
+        ($($x:expr,)*) => ( set![$($x),*] )
+    }
+
     #[test]
     fn solving() {
         let state = State::new(vec![set![Microchip("H"), Microchip("L")], set![Generator("H")], set![Generator("L")], set![]]);
@@ -221,4 +338,53 @@ mod tests {
         assert!(!state.is_done());
         assert_eq!(state.min_steps(), 11);
     }
+
+    #[test]
+    fn parsing() {
+        let state = State::parse("\
+The first floor contains a hydrogen-compatible microchip and a lithium-compatible microchip.
+The second floor contains a hydrogen generator.
+The third floor contains a lithium generator.
+The fourth floor contains nothing relevant.").unwrap();
+        assert_eq!(state, State::new(vec![
+            set![Microchip("hydrogen"), Microchip("lithium")], set![Generator("hydrogen")], set![Generator("lithium")], set![],
+        ]));
+        assert_eq!(state.min_steps(), 11);
+        let state = State::parse("\
+The 1st floor contains an elerium generator, a dilithium generator, and an elerium-compatible microchip.
+The 2nd floor contains a dilithium-compatible microchip.").unwrap();
+        assert_eq!(state, State::new(vec![
+            set![Generator("elerium"), Generator("dilithium"), Microchip("elerium")], set![Microchip("dilithium")],
+        ]));
+    }
+
+    #[test]
+    fn parsing_real() {
+        let state = State::parse(include_str!("day11.txt")).unwrap();
+        assert_eq!(state.floors.len(), 4);
+        assert_eq!(state.floors[0].len(), 8);
+        assert_eq!(state.floors[1], set![Microchip("polonium"), Microchip("promethium")]);
+        assert!(state.is_valid());
+    }
+
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!(State::parse("The first floor contains a hydrogen generator.\nThe second floor contains a hydrogen-compatible chip.").unwrap_err(),
+            Error::Parse(ParseError { line: 2, column: 49, text: "The second floor contains a hydrogen-compatible chip.".to_owned() }));
+        assert_eq!(State::parse("The first floor contains nothing relevant.\nThe third floor contains nothing relevant.").unwrap_err().to_string(),
+            "invalid input at line 2, column 5:\nThe third floor contains nothing relevant.\n    ^");
+        assert_eq!(State::parse("The first floor contains a hydrogen generator").unwrap_err().to_string(),
+            "invalid input at line 1, column 46:\nThe first floor contains a hydrogen generator\n                                             ^");
+        assert_eq!(State::parse("").unwrap_err(), Error::NoFloors);
+    }
+
+    #[test]
+    fn checking_pairs() {
+        assert_eq!(State::parse("The first floor contains a hydrogen generator.\nThe second floor contains a lithium-compatible microchip.").unwrap_err(),
+            Error::Unpaired(Generator("hydrogen")));
+        assert_eq!(State::parse("The first floor contains a hydrogen-compatible microchip.").unwrap_err().to_string(),
+            "hydrogen-compatible microchip has no hydrogen generator");
+        assert_eq!(State::parse("The first floor contains a lithium generator.\nThe second floor contains a lithium generator.").unwrap_err(),
+            Error::Duplicate(Generator("lithium")));
+    }
 }
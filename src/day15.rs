@@ -1,8 +1,12 @@
 #[macro_use]
 extern crate nom;
 
+#[macro_use]
+mod parse_error;
+
 use std::str::FromStr;
 use nom::digit;
+use parse_error::ParseError;
 
 
 /// A rotating disc
@@ -14,10 +18,10 @@ pub struct Disc {
 }
 
 impl FromStr for Disc {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Disc, nom::ErrorKind> {
-        do_parse!(s,
+    fn from_str(s: &str) -> Result<Disc, ParseError> {
+        parse_line!(s, do_parse!(
             tag!("Disc #") >>
             num: map_res!(digit, FromStr::from_str) >>
             tag!(" has ") >>
@@ -26,14 +30,14 @@ impl FromStr for Disc {
             offset: map_res!(digit, FromStr::from_str) >>
             tag!(".") >>
             (Disc { num: num, positions: positions, offset: offset })
-        ).to_result()
+        ))
     }
 }
 
 impl Disc {
     /// Parse a multiline-text to a vector of discs
-    fn parse(s: &str) -> Result<Vec<Disc>, nom::ErrorKind> {
-        s.lines().map(|line| line.parse()).collect()
+    fn parse(s: &str) -> Result<Vec<Disc>, ParseError> {
+        parse_error::parse_lines(s, |line| line.parse())
     }
 
     /// Determine if the sphere can pass at the given time
@@ -71,6 +75,12 @@ mod tests {
         assert_eq!("Disc #2 has 2 positions; at time=0, it is at position 1.".parse(), Ok(Disc { num: 2, positions: 2, offset: 1 }));
     }
 
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!(Disc::parse("Disc #1 has 5 positions; at time=0, it is at position 4.\nDisc #2 has two positions; at time=0, it is at position 1.").unwrap_err(),
+            ParseError { line: 2, column: 13, text: "Disc #2 has two positions; at time=0, it is at position 1.".to_owned() });
+    }
+
     #[test]
     fn name() {
         let discs = Disc::parse("Disc #1 has 5 positions; at time=0, it is at position 4.\nDisc #2 has 2 positions; at time=0, it is at position 1.").unwrap();
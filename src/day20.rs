@@ -1,12 +1,17 @@
-use std::num;
+mod parse_error;
+
+use parse_error::ParseError;
 
 
 /// Parse multiline-text of ranges into a vector of tuples
-pub fn parse(s: &str) -> Result<Vec<(u32, u32)>, num::ParseIntError> {
-    s.lines().map(|line| {
-        let mut nums = line.split('-').map(|s| s.parse::<u32>().unwrap());
-        Ok((nums.next().unwrap(), nums.next().unwrap()))
-    }).collect()
+pub fn parse(s: &str) -> Result<Vec<(u32, u32)>, ParseError> {
+    parse_error::parse_lines(s, |line| {
+        let mut nums = line.splitn(2, '-').map(|s| s.parse::<u32>().map_err(|_| ParseError::of(line, s)));
+        match (nums.next(), nums.next()) {
+            (Some(from), Some(to)) => Ok((try!(from), try!(to))),
+            _ => Err(ParseError::at(line, line.len())),
+        }
+    })
 }
 
 /// Find lowest number not covered by a list of ranges
@@ -45,6 +50,13 @@ fn main() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parsing() {
+        assert_eq!(parse("5-8\n0-2"), Ok(vec![(5, 8), (0, 2)]));
+        assert_eq!(parse("5-8\n0-x").unwrap_err(), ParseError { line: 2, column: 3, text: "0-x".to_owned() });
+        assert_eq!(parse("5-8\n0").unwrap_err(), ParseError { line: 2, column: 2, text: "0".to_owned() });
+    }
+
     #[test]
     fn finding_lowest() {
         let ranges = parse("5-8\n0-2\n4-7").unwrap();
@@ -1,7 +1,11 @@
 #[macro_use]
 extern crate nom;
 
+#[macro_use]
+mod parse_error;
+
 use std::str::FromStr;
+use parse_error::ParseError;
 
 
 /// One step of the weird password scrambling
@@ -18,10 +22,10 @@ pub enum Instruction {
 }
 
 impl FromStr for Instruction {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Instruction, nom::ErrorKind> {
-        complete!(s, alt!(
+    fn from_str(s: &str) -> Result<Instruction, ParseError> {
+        parse_line!(s, alt!(
             do_parse!(
                 tag!("swap position ") >>
                 x: map_res!(nom::digit, FromStr::from_str) >>
@@ -71,14 +75,14 @@ impl FromStr for Instruction {
                 y: map_res!(nom::digit, FromStr::from_str) >>
                 (Instruction::Move(x, y))
             )
-        )).to_result()
+        ))
     }
 }
 
 impl Instruction {
     /// Parse a multiline-text to a vector of instructions
-    fn parse(s: &str) -> Result<Vec<Instruction>, nom::ErrorKind> {
-        s.lines().map(|line| line.parse()).collect()
+    fn parse(s: &str) -> Result<Vec<Instruction>, ParseError> {
+        parse_error::parse_lines(s, |line| line.parse())
     }
 
     /// Apply instruction to a given string
@@ -160,7 +164,7 @@ pub struct Scrambler {
 
 impl Scrambler {
     /// Create new scrambler using the given instructions
-    fn new(s: &str) -> Result<Scrambler, nom::ErrorKind> {
+    fn new(s: &str) -> Result<Scrambler, ParseError> {
         Instruction::parse(s).map(|instructions|
             Scrambler { instructions: instructions }
         )
@@ -207,6 +211,12 @@ mod tests {
         assert_eq!("rotate based on position of letter d".parse(), Ok(Instruction::RotateAtLetter('d')));
     }
 
+    #[test]
+    fn parsing_invalid() {
+        assert_eq!("rotate left 2 step".parse::<Instruction>().unwrap_err().column, 19);
+        assert_eq!("swap position 4 with letter 0".parse::<Instruction>().unwrap_err().column, 22);
+    }
+
     #[test]
     fn instructions() {
         let mut s = "abcde".to_owned();
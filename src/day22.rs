@@ -1,9 +1,13 @@
 #[macro_use]
 extern crate nom;
 
+#[macro_use]
+mod parse_error;
+
 use std::collections::{HashMap, VecDeque};
 use std::str::FromStr;
 use nom::{space, digit};
+use parse_error::ParseError;
 
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -16,10 +20,10 @@ pub struct Node {
 }
 
 impl FromStr for Node {
-    type Err = nom::ErrorKind;
+    type Err = ParseError;
 
-    fn from_str(s: &str) -> Result<Node, nom::ErrorKind> {
-        complete!(s, do_parse!(
+    fn from_str(s: &str) -> Result<Node, ParseError> {
+        parse_line!(s, do_parse!(
             tag!("/dev/grid/node-x") >>
             x: map_res!(digit, FromStr::from_str) >>
             tag!("-y") >>
@@ -37,14 +41,16 @@ impl FromStr for Node {
             digit >>
             tag!("%") >>
             (Node { x: x, y: y, size: size, used: used, avail: avail })
-        )).to_result()
+        ))
     }
 }
 
 impl Node {
     /// Parse a multiline-text to a vector of nodes
-    fn parse(s: &str) -> Result<Vec<Node>, nom::ErrorKind> {
-        s.lines().skip(2).map(|line| line.parse()).collect()
+    fn parse(s: &str) -> Result<Vec<Node>, ParseError> {
+        s.lines().enumerate().skip(2).map(|(i, line)| {
+            line.parse().map_err(|err: ParseError| err.in_line(i + 1))
+        }).collect()
     }
 }
 
@@ -57,12 +63,15 @@ pub struct Cluster {
 
 impl Cluster {
     /// Create new cluster using the given node descriptions
-    fn new(s: &str) -> Result<Cluster, nom::ErrorKind> {
+    fn new(s: &str) -> Result<Cluster, ParseError> {
         let mut nodes = vec![];
-        for node in try!(Node::parse(s)) {
+        for (i, (node, line)) in try!(Node::parse(s)).into_iter().zip(s.lines().skip(2)).enumerate() {
             if node.x == nodes.len() { nodes.push(vec![]); }
-            if node.x >= nodes.len() { panic!("Invalid node order"); }
-            if node.y == nodes[node.x].len() { nodes[node.x].push(node); } else { panic!("Invalid node order"); }
+            if node.x >= nodes.len() || node.y != nodes[node.x].len() {
+                // Nodes need to be ordered by position
+                return Err(ParseError::at(line, 0).in_line(i + 3));
+            }
+            nodes[node.x].push(node);
         }
         Ok(Cluster { nodes: nodes })
     }
@@ -203,6 +212,14 @@ Filesystem            Size  Used  Avail  Use%\r
         assert_eq!(cluster.height(), 3);
     }
 
+    #[test]
+    fn parsing_invalid() {
+        let err = Cluster::new(&TEST_DATA.replace("x0-y2   32T", "x0-y2   32G")).unwrap_err();
+        assert_eq!((err.line, err.column), (5, 26));
+        let err = Cluster::new(&TEST_DATA.replace("x1-y1", "x1-y3")).unwrap_err();
+        assert_eq!(err.to_string(), "invalid input at line 7, column 1:\n/dev/grid/node-x1-y3    8T    0T     8T    0%\n^");
+    }
+
     #[test]
     fn moving_top_data() {
         let cluster = Cluster::new(TEST_DATA).unwrap();
@@ -1,242 +1,57 @@
 #[macro_use]
 extern crate nom;
 
-use std::str::FromStr;
-use nom::{space, digit};
-
-
-/// An immediate or register value
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Value {
-    Immediate(i32),
-    Register(u8),
-}
-
-impl Value {
-    /// Get value
-    fn get(&self, regs: &[i32]) -> i32 {
-        match *self {
-            Value::Immediate(x) => x,
-            Value::Register(x) => regs[x as usize],
-        }
-    }
-}
-
-
-/// An assembunny instruction
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Instruction {
-    Cpy(Value, Value),
-    Inc(u8),
-    Dec(u8),
-    Jnz(Value, Value),
-    Tgl(u8),
-}
-
-impl FromStr for Instruction {
-    type Err = nom::ErrorKind;
-
-    fn from_str(s: &str) -> Result<Instruction, nom::ErrorKind> {
-        named!(register<&str, u8>, alt!(
-            value!(0, tag!("a")) |
-            value!(1, tag!("b")) |
-            value!(2, tag!("c")) |
-            value!(3, tag!("d"))
-        ));
-        named!(value<&str, Value>, alt!(
-            do_parse!(
-                sig: opt!(tag!("-")) >>
-                x: map_res!(digit, i32::from_str) >>
-                (Value::Immediate(if sig.is_some() { -x } else { x }))
-            ) |
-            do_parse!(
-                r: register >>
-                (Value::Register(r))
-            )
-        ));
-        alt!(s,
-            do_parse!(
-                tag!("cpy") >> space >>
-                x: value >> space >>
-                y: value >>
-                (Instruction::Cpy(x, y))
-            ) |
-            do_parse!(
-                tag!("inc") >> space >>
-                x: register >>
-                (Instruction::Inc(x))
-            ) |
-            do_parse!(
-                tag!("dec") >> space >>
-                x: register >>
-                (Instruction::Dec(x))
-            ) |
-            do_parse!(
-                tag!("jnz") >> space >>
-                x: value >> space >>
-                y: value >>
-                (Instruction::Jnz(x, y))
-            ) |
-            do_parse!(
-                tag!("tgl") >> space >>
-                x: register >>
-                (Instruction::Tgl(x))
-            )
-        ).to_result()
-    }
-}
-
-impl Instruction {
-    /// Parse a multiline-text to a vector of instructions
-    fn parse(s: &str) -> Result<Vec<Instruction>, nom::ErrorKind> {
-        s.lines().map(|line| line.parse()).collect()
-    }
-
-    /// Returns the toggled instruction (result of being affected by a tgl instruction)
-    fn toggle(&self) -> Instruction {
-        match *self {
-            Instruction::Cpy(v1, v2) => Instruction::Jnz(v1, v2),
-            Instruction::Inc(x) => Instruction::Dec(x),
-            Instruction::Dec(x) => Instruction::Inc(x),
-            Instruction::Jnz(v1, v2) => Instruction::Cpy(v1, v2),
-            Instruction::Tgl(x) => Instruction::Inc(x),
-        }
-    }
-}
+#[macro_use]
+mod parse_error;
+mod assembunny;
 
+use std::env;
+use assembunny::{Cpu, Dialect};
+use assembunny::symbolic;
+use assembunny::word::Overflow;
 
-/// The assembunny CPU
-#[derive(Debug, PartialEq, Eq)]
-pub struct Cpu {
-    instructions: Vec<Instruction>,
-    ip: usize,
-    regs: [i32; 4],
-}
-
-impl Cpu {
-    /// Create new CPU
-    fn new(input: &str) -> Result<Cpu, nom::ErrorKind> {
-        Instruction::parse(input).map(|instructions|
-            Cpu { instructions: instructions, ip: 0, regs: [0; 4] }
-        )
-    }
 
-    /// Detect addition loop. Returns a tuple with related registers.
-    /// 0: first summand, gets result, 1: second summand, gets zeroed
-    fn detect_add_loop(&self, ip: usize) -> Option<(u8, u8)> {
-        use Instruction::*;
-        use Value::*;
-        if ip >= 2 {
-            match (self.instructions[ip-2], self.instructions[ip-1], self.instructions[ip]) {
-                (Inc(tr), Dec(cr1), Jnz(Register(cr2), Immediate(-2))) if cr1==cr2 => return Some((tr, cr1)),
-                (Dec(cr1), Inc(tr), Jnz(Register(cr2), Immediate(-2))) if cr1==cr2 => return Some((tr, cr1)),
-                _ => (),
-            }
-        }
-        None
-    }
+fn main() {
+    let reference = env::args().any(|arg| arg == "--reference");
+    let mut cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+    cpu.shortcuts = !reference;
+    cpu.regs[0] = 7;
+    cpu.run_compiled();
+    println!("Value to send to the safe (7 eggs): {}", cpu.regs[0]);
 
-    /// Detect multiplication loop. Returns a tuple with related registers.
-    /// 0: gets increased by result, 1: first factor, 2: second factor, gets zeroed, 3: gets zeroed
-    fn detect_mul_loop(&self, ip: usize) -> Option<(u8, u8, u8, u8)> {
-        use Instruction::*;
-        use Value::*;
-        if ip >= 5 {
-            match (self.instructions[ip-5], self.detect_add_loop(ip-2), self.instructions[ip-1], self.instructions[ip]) {
-                (Cpy(Register(ar), Register(cr1)), Some((tr, cr2)), Dec(br1), Jnz(Register(br2), Immediate(-5))) if cr1==cr2 && br1==br2 => return Some((tr, ar, br1, cr1)),
-                _ => (),
-            }
-        }
-        None
+    let mut cpu: Cpu<i64> = Cpu::with_overflow(include_str!("day23.txt"), &Dialect::day23(), Overflow::Trap).unwrap();
+    cpu.shortcuts = !reference;
+    cpu.regs[0] = 12;
+    cpu.run_compiled();
+    match cpu.fault {
+        None => println!("Value to send to the safe (12 eggs): {}", cpu.regs[0]),
+        Some(err) => println!("Computing value to send to the safe (12 eggs) failed: {}", err),
     }
 
-    /// Step program. Returns true if done
-    fn step(&mut self) -> bool {
-        if self.ip >= self.instructions.len() {
-            true
-        } else {
-            let ip = self.ip;
-            let ins = self.instructions[ip];
-            match ins {
-                Instruction::Cpy(_, Value::Immediate(_)) => { /* ignore invalid cpy */ },
-                Instruction::Cpy(ref v, Value::Register(y)) => self.regs[y as usize] = v.get(&self.regs),
-                Instruction::Inc(x) => self.regs[x as usize] += 1,
-                Instruction::Dec(x) => self.regs[x as usize] -= 1,
-                Instruction::Jnz(ref v1, ref v2) => {
-                    if v1.get(&self.regs) != 0 {
-                        if let Some((x, y, z, zz)) = self.detect_mul_loop(ip) {
-                            // Optimized multiplication loop
-                            self.regs[x as usize] += self.regs[y as usize] * self.regs[z as usize];
-                            self.regs[z as usize] = 0;
-                            self.regs[zz as usize] = 0;
-                            self.ip += 1;
-                        } else if let Some((x, y)) = self.detect_add_loop(ip) {
-                            // Optimized addition loop
-                            self.regs[x as usize] += self.regs[y as usize];
-                            self.regs[y as usize] = 0;
-                            self.ip += 1;
-                        } else {
-                            // Unoptimized loop
-                            self.ip = (self.ip as i32 + v2.get(&self.regs)) as usize;
-                        }
-                    } else {
-                        self.ip += 1
-                    }
-                },
-                Instruction::Tgl(x) => {
-                    let addr = self.ip + self.regs[x as usize] as usize;
-                    if addr < self.instructions.len() {
-                        self.instructions[addr] = self.instructions[addr].toggle();
-                    }
-                },
-            }
-            match self.instructions[ip] {
-                Instruction::Jnz(_, _) => (),
-                _ => self.ip += 1,
+    if env::args().any(|arg| arg == "--symbolic") {
+        let cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        for outcome in symbolic::execute(&cpu.instructions) {
+            match outcome.formula(0) {
+                Some(formula) => println!("Value to send to the safe: {}", formula),
+                None => println!("Value to send to the safe: {}", outcome),
             }
-            false
         }
     }
-
-    /// Run program
-    fn run(&mut self) {
-        while !self.step() { }
-    }
-}
-
-
-fn main() {
-    let mut cpu = Cpu::new(include_str!("day23.txt")).unwrap();
-    cpu.regs[0] = 7;
-    cpu.run();
-    println!("Value to send to the safe (7 eggs): {}", cpu.regs[0]);
-
-    let mut cpu = Cpu::new(include_str!("day23.txt")).unwrap();
-    cpu.regs[0] = 12;
-    cpu.run();
-    println!("Value to send to the safe (12 eggs): {}", cpu.regs[0]);
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn parsing() {
-        assert_eq!("inc a".parse(), Ok(Instruction::Inc(0)));
-        assert_eq!(Instruction::parse("cpy 41 a\ninc b\ndec c\njnz d 2\njnz 1 -2"), Ok(vec![
-            Instruction::Cpy(Value::Immediate(41), Value::Register(0)),
-            Instruction::Inc(1),
-            Instruction::Dec(2),
-            Instruction::Jnz(Value::Register(3), Value::Immediate(2)),
-            Instruction::Jnz(Value::Immediate(1), Value::Immediate(-2)),
-        ]));
-    }
+    use assembunny::{Instruction, Value};
+    use assembunny::assembler::assemble;
+    use assembunny::differential::{compare, Agreement};
+    use assembunny::disassembler::disassemble_toggled;
+    use assembunny::linter::lint;
 
     #[test]
     fn parsing_new() {
-        assert_eq!(Instruction::parse("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a"), Ok(vec![
+        assert_eq!(Instruction::parse("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a", &Dialect::day23()), Ok(vec![
             Instruction::Cpy(Value::Immediate(2), Value::Register(0)),
             Instruction::Tgl(0),
             Instruction::Tgl(0),
@@ -248,16 +63,98 @@ mod tests {
     }
 
     #[test]
-    fn running() {
-        let mut cpu = Cpu::new("cpy 41 a\ninc a\ninc a\ndec a\njnz a 2\ndec a").unwrap();
+    fn running_new() {
+        let mut cpu = Cpu::new("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a", &Dialect::day23()).unwrap();
         cpu.run();
-        assert_eq!(cpu.regs[0], 42);
+        assert_eq!(cpu.regs[0], 3);
     }
 
     #[test]
-    fn running_new() {
-        let mut cpu = Cpu::new("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a").unwrap();
+    fn optimizing() {
+        let cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        let loops: Vec<String> = cpu.optimized_loops().iter().map(|l| l.to_string()).collect();
+        assert_eq!(loops, vec![
+            "lines 5-10: a += d*b, c = 0, d = 0 if b >= 1",
+            "lines 6-8: a += c, c = 0",
+            "lines 14-16: c += d, d = 0",
+            "lines 22-24: a -= d, d = 0",
+        ]);
+    }
+
+    #[test]
+    fn running_compiled() {
+        let mut cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        cpu.regs[0] = 7;
+        cpu.run_compiled();
+        assert_eq!(cpu.regs[0], 11340);
+    }
+
+    #[test]
+    fn comparing_interpreters() {
+        let cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        assert_eq!(compare::<i32, _>(&cpu.instructions, |cpu| cpu.regs[0] = 7, 1000000), Ok(Agreement::Complete(56053)));
+    }
+
+    #[test]
+    fn assembling_annotated() {
+        let program = assemble(include_str!("day23.asm"), &Dialect::day23()).unwrap();
+        assert_eq!(program, Instruction::parse(include_str!("day23.txt"), &Dialect::day23()).unwrap());
+        let mut cpu = Cpu::from(program);
+        cpu.regs[0] = 7;
         cpu.run();
-        assert_eq!(cpu.regs[0], 3);
+        assert_eq!(cpu.regs[0], 11340);
+    }
+
+    #[test]
+    fn disassembling_toggled() {
+        let mut cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        let original = cpu.instructions.clone();
+        cpu.regs[0] = 7;
+        cpu.run();
+        let listing = disassemble_toggled(&cpu.instructions, &original, &Dialect::day23());
+        assert!(listing.contains("19      cpy 1 c                     ; toggled from: jnz 1 c\n"));
+        assert_eq!(listing.matches("toggled from").count(), 4);
+    }
+
+    #[test]
+    fn resuming_snapshot() {
+        let mut cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        cpu.regs[0] = 7;
+        cpu.run_with_limit(50);
+        assert!(!cpu.is_done());
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.matches("toggled from").count(), 4);
+        let mut cpu: Cpu = Cpu::from_snapshot(&snapshot).unwrap();
+        cpu.run();
+        assert_eq!(cpu.regs[0], 11340);
+        cpu.reset();
+        cpu.regs[0] = 7;
+        cpu.run();
+        assert_eq!(cpu.regs[0], 11340);
+    }
+
+    #[test]
+    fn solving_symbolically() {
+        let cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        let outcomes = symbolic::execute(&cpu.instructions);
+        let formulas: Vec<String> = outcomes.iter().filter_map(|outcome| outcome.formula(0)).collect();
+        assert_eq!(formulas, vec!["7020 if a == 6", "11340 if a == 7", "a! + 6300 if a >= 8"]);
+        for &(eggs, value) in &[(7, 11340), (12, 479007900)] {
+            let result = outcomes.iter().filter_map(|outcome| outcome.eval(&[eggs, 0, 0, 0])).next();
+            assert_eq!(result.map(|regs| regs[0]), Some(value));
+        }
+    }
+
+    #[test]
+    fn linting() {
+        let cpu = Cpu::new(include_str!("day23.txt"), &Dialect::day23()).unwrap();
+        let warnings: Vec<String> = lint(&cpu.instructions, false).iter().map(|w| w.to_string()).collect();
+        // The target of the toggle is unknown, so every instruction may be toggled
+        assert_eq!(warnings, vec![
+            "line 1: register a is read before it is written",
+            "line 2: register b is read before it is written",
+            "line 7: register c is read before it is written",
+            "line 9: register d is read before it is written",
+        ]);
     }
 }
@@ -0,0 +1,808 @@
+//! Display of monochromatic pixels, shared by the puzzles that draw on it
+//! (day 8 and the bonus challenge), and the operations that modify it
+
+use std::{cmp, io};
+use std::fmt::{self, Write};
+use std::str::{self, FromStr};
+use nom;
+use image::{self, Recorder};
+use ocr;
+use parse_error::{self, ParseError};
+
+
+/// An operation is an instruction to modify pixels on a display
+#[derive(Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Draw rectangle in top left corner
+    Rect { width: usize, height: usize },
+    /// Draw rectangle at the given position
+    RectAt { x: usize, y: usize, width: usize, height: usize },
+    /// Turn off pixels of a rectangle
+    Clear { x: usize, y: usize, width: usize, height: usize },
+    /// Toggle pixels of a rectangle
+    Invert { x: usize, y: usize, width: usize, height: usize },
+    /// Rotate pixels right in a row (left if the count is negative)
+    RotateRow { row: usize, count: isize },
+    /// Rotate pixels down in a column (up if the count is negative)
+    RotateColumn { column: usize, count: isize },
+    /// Mirror the display left to right
+    FlipHorizontal,
+    /// Mirror the display top to bottom
+    FlipVertical,
+    /// Draw text with the built-in font at the given position
+    Text { x: usize, y: usize, text: String },
+}
+
+/// Parse a number
+fn number(input: &str) -> nom::IResult<&str, usize> {
+    map_res!(input, nom::digit, FromStr::from_str)
+}
+
+/// Parse a number that may be negative
+fn signed(input: &str) -> nom::IResult<&str, isize> {
+    do_parse!(input,
+        sig: opt!(tag!("-")) >>
+        x: map_res!(nom::digit, isize::from_str) >>
+        (if sig.is_some() { -x } else { x })
+    )
+}
+
+/// Parse an optional position (` at X,Y`)
+fn position(input: &str) -> nom::IResult<&str, Option<(usize, usize)>> {
+    opt!(input, complete!(do_parse!(
+        tag!(" at ") >>
+        x: number >>
+        tag!(",") >>
+        y: number >>
+        ((x, y))
+    )))
+}
+
+/// Parse the size and position of a rectangle (`AxB at X,Y`, in the top
+/// left corner if the position is missing)
+fn area(input: &str) -> nom::IResult<&str, (usize, usize, usize, usize)> {
+    do_parse!(input,
+        width: number >>
+        tag!("x") >>
+        height: number >>
+        pos: position >>
+        ({ let (x, y) = pos.unwrap_or((0, 0)); (x, y, width, height) })
+    )
+}
+
+impl FromStr for Operation {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Operation, ParseError> {
+        let characters = ocr::characters();
+        parse_line!(s, alt!(
+            do_parse!(
+                tag!("rect ") >>
+                width: number >>
+                tag!("x") >>
+                height: number >>
+                pos: position >>
+                (match pos {
+                    Some((x, y)) => Operation::RectAt { x: x, y: y, width: width, height: height },
+                    None => Operation::Rect { width: width, height: height },
+                })
+            ) |
+            do_parse!(
+                tag!("clear ") >>
+                a: area >>
+                (Operation::Clear { x: a.0, y: a.1, width: a.2, height: a.3 })
+            ) |
+            do_parse!(
+                tag!("invert ") >>
+                a: area >>
+                (Operation::Invert { x: a.0, y: a.1, width: a.2, height: a.3 })
+            ) |
+            do_parse!(
+                tag!("rotate row y=") >>
+                row: number >>
+                dir: alt!(value!(1, tag!(" by ")) | value!(1, tag!(" right by ")) | value!(-1, tag!(" left by "))) >>
+                count: signed >>
+                (Operation::RotateRow { row: row, count: dir * count })
+            ) |
+            do_parse!(
+                tag!("rotate column x=") >>
+                column: number >>
+                dir: alt!(value!(1, tag!(" by ")) | value!(1, tag!(" down by ")) | value!(-1, tag!(" up by "))) >>
+                count: signed >>
+                (Operation::RotateColumn { column: column, count: dir * count })
+            ) |
+            value!(Operation::FlipHorizontal, tag!("flip horizontal")) |
+            value!(Operation::FlipVertical, tag!("flip vertical")) |
+            do_parse!(
+                tag!("text \"") >>
+                text: is_a!(&characters[..]) >>
+                tag!("\"") >>
+                pos: position >>
+                ({ let (x, y) = pos.unwrap_or((0, 0)); Operation::Text { x: x, y: y, text: text.to_owned() } })
+            )
+        ))
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operation::Rect { width, height } => write!(f, "rect {}x{}", width, height),
+            Operation::RectAt { x, y, width, height } => write!(f, "rect {}x{} at {},{}", width, height, x, y),
+            Operation::Clear { x, y, width, height } => write!(f, "clear {}x{} at {},{}", width, height, x, y),
+            Operation::Invert { x, y, width, height } => write!(f, "invert {}x{} at {},{}", width, height, x, y),
+            Operation::RotateRow { row, count } => write!(f, "rotate row y={} by {}", row, count),
+            Operation::RotateColumn { column, count } => write!(f, "rotate column x={} by {}", column, count),
+            Operation::FlipHorizontal => write!(f, "flip horizontal"),
+            Operation::FlipVertical => write!(f, "flip vertical"),
+            Operation::Text { x, y, ref text } => write!(f, "text \"{}\" at {},{}", text, x, y),
+        }
+    }
+}
+
+impl Operation {
+    /// Parse a multiline-text to a vector of operations
+    #[allow(dead_code)]
+    pub fn parse(s: &str) -> Result<Vec<Operation>, ParseError> {
+        parse_error::parse_lines(s, |line| line.parse())
+    }
+}
+
+
+/// Error of executing an operation
+#[derive(Debug)]
+pub enum Error {
+    /// Rectangle (x, y, width, height) that doesn't fit on the display
+    OutOfBounds(usize, usize, usize, usize),
+    /// Row that doesn't exist
+    NoRow(usize),
+    /// Column that doesn't exist
+    NoColumn(usize),
+    /// Character that has no glyph in the font
+    UnknownCharacter(char),
+    /// Recording a frame failed
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::OutOfBounds(x, y, w, h) => write!(f, "rectangle {}x{} at {},{} exceeds the display", w, h, x, y),
+            Error::NoRow(y) => write!(f, "no row y={}", y),
+            Error::NoColumn(x) => write!(f, "no column x={}", x),
+            Error::UnknownCharacter(ch) => write!(f, "no glyph for character {:?}", ch),
+            Error::Io(ref err) => write!(f, "recording frame failed: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+
+/// Number of pixels in a word
+const WORD_BITS: usize = 64;
+
+/// A rectangular display with monochromatic pixels. Pixels are packed into
+/// words, every row starting at a new word. The pixel in column x is bit
+/// x % 64 of word x / 64 of its row, and bits right of the last column are
+/// always 0.
+#[derive(Debug)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    /// Number of words per row
+    stride: usize,
+    /// Pixels of all rows
+    words: Vec<u64>,
+    /// Copy of the pixels of a row or column that is being rotated
+    scratch: Vec<u64>,
+    /// Recorder of frames in recording mode
+    recorder: Option<Recorder>,
+}
+
+impl PartialEq for Display {
+    fn eq(&self, other: &Display) -> bool {
+        self.width == other.width && self.height == other.height && self.words == other.words
+    }
+}
+
+impl Eq for Display {}
+
+// A copy doesn't record frames
+impl Clone for Display {
+    fn clone(&self) -> Display {
+        Display { width: self.width, height: self.height, stride: self.stride, words: self.words.clone(), scratch: Vec::new(), recorder: None }
+    }
+}
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                match self.get(x, y) {
+                    false => try!(f.write_char('.')),
+                    true => try!(f.write_char('#')),
+                }
+            }
+            try!(f.write_str("\n"));
+        }
+        Ok(())
+    }
+}
+
+impl Display {
+    /// Create a new display with the given number of pixels in width and height
+    pub fn new(width: usize, height: usize) -> Display {
+        let stride = (width + WORD_BITS - 1) / WORD_BITS;
+        Display {
+            width: width,
+            height: height,
+            stride: stride,
+            words: vec![0; stride * height],
+            scratch: Vec::new(),
+            recorder: None,
+        }
+    }
+
+    /// Create a new display showing the given rows of pixels
+    #[allow(dead_code)]
+    pub fn from_pixels(pixels: &[Vec<bool>]) -> Display {
+        let mut display = Display::new(pixels.first().map(|row| row.len()).unwrap_or(0), pixels.len());
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, &px) in row.iter().enumerate() {
+                display.set(x, y, px);
+            }
+        }
+        display
+    }
+
+    /// Rows of pixels
+    pub fn pixels(&self) -> Vec<Vec<bool>> {
+        (0..self.height).map(|y| (0..self.width).map(|x| self.get(x, y)).collect()).collect()
+    }
+
+    /// Get a pixel
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.words[y * self.stride + x / WORD_BITS] >> (x % WORD_BITS) & 1 != 0
+    }
+
+    /// Set a pixel
+    pub fn set(&mut self, x: usize, y: usize, px: bool) {
+        let bit = 1 << (x % WORD_BITS);
+        let word = &mut self.words[y * self.stride + x / WORD_BITS];
+        if px { *word |= bit } else { *word &= !bit }
+    }
+
+    /// Count lit pixels (voltage check)
+    #[allow(dead_code)]
+    pub fn count_lit(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Recognize the text shown on the display
+    pub fn text(&self) -> Result<String, ocr::Error> {
+        ocr::recognize(&self.pixels())
+    }
+
+    /// Save the pixels as an image file of the format given by its extension
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        image::save(&self.pixels(), path)
+    }
+
+    /// Start recording mode, in which a frame is recorded after every
+    /// operation that is run
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Stop recording mode
+    pub fn stop_recording(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    /// Check that a rectangle fits on the display
+    fn check_bounds(&self, x: usize, y: usize, w: usize, h: usize) -> Result<(), Error> {
+        if x.checked_add(w).map(|right| right > self.width).unwrap_or(true) ||
+           y.checked_add(h).map(|bottom| bottom > self.height).unwrap_or(true) {
+            Err(Error::OutOfBounds(x, y, w, h))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Change the pixels of a rectangle with the given function. The
+    /// function is applied to whole words, by selecting the bits of every
+    /// word that are turned on by the function.
+    fn fill<F: Fn(bool) -> bool>(&mut self, x: usize, y: usize, w: usize, h: usize, f: F) -> Result<(), Error> {
+        self.check_bounds(x, y, w, h)?;
+        let (off, on) = (f(false), f(true));
+        for row in self.words.chunks_mut(cmp::max(self.stride, 1)).skip(y).take(h) {
+            for i in x / WORD_BITS..(x + w + WORD_BITS - 1) / WORD_BITS {
+                let (start, end) = (cmp::max(x, i * WORD_BITS) - i * WORD_BITS, cmp::min(x + w, (i + 1) * WORD_BITS) - i * WORD_BITS);
+                let mask = mask(end) & !mask(start);
+                let word = row[i];
+                let value = (if on { word } else { 0 }) | (if off { !word } else { 0 });
+                row[i] = word & !mask | value & mask;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rotate a row (wraps around if the count exceeds the width). Every
+    /// word of the rotated row is read from a copy of the row, starting at
+    /// the bit that moves to its first column.
+    fn rotate_row(&mut self, y: usize, c: isize) -> Result<(), Error> {
+        if y >= self.height {
+            return Err(Error::NoRow(y));
+        }
+        let shift = wrap(c, self.width);
+        if shift > 0 {
+            let row = &mut self.words[y * self.stride..(y + 1) * self.stride];
+            self.scratch.clear();
+            self.scratch.extend_from_slice(row);
+            for (i, word) in row.iter_mut().enumerate() {
+                *word = read_bits(&self.scratch, self.width, (i * WORD_BITS + self.width - shift) % self.width);
+            }
+            row[self.stride - 1] &= mask(self.width - (self.stride - 1) * WORD_BITS);
+        }
+        Ok(())
+    }
+
+    /// Rotate a column (wraps around if the count exceeds the height). The
+    /// column is copied to packed bits first.
+    fn rotate_column(&mut self, x: usize, c: isize) -> Result<(), Error> {
+        if x >= self.width {
+            return Err(Error::NoColumn(x));
+        }
+        let shift = wrap(c, self.height);
+        if shift > 0 {
+            let (i, bit) = (x / WORD_BITS, x % WORD_BITS);
+            self.scratch.clear();
+            self.scratch.resize((self.height + WORD_BITS - 1) / WORD_BITS, 0);
+            for y in 0..self.height {
+                self.scratch[y / WORD_BITS] |= (self.words[y * self.stride + i] >> bit & 1) << (y % WORD_BITS);
+            }
+            for y in 0..self.height {
+                let src = (y + self.height - shift) % self.height;
+                let px = self.scratch[src / WORD_BITS] >> (src % WORD_BITS) & 1;
+                let word = &mut self.words[y * self.stride + i];
+                *word = *word & !(1 << bit) | px << bit;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mirror the display left to right
+    fn flip_horizontal(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width / 2 {
+                let (left, right) = (self.get(x, y), self.get(self.width - 1 - x, y));
+                self.set(x, y, right);
+                self.set(self.width - 1 - x, y, left);
+            }
+        }
+    }
+
+    /// Mirror the display top to bottom
+    fn flip_vertical(&mut self) {
+        for y in 0..self.height / 2 {
+            for i in 0..self.stride {
+                self.words.swap(y * self.stride + i, (self.height - 1 - y) * self.stride + i);
+            }
+        }
+    }
+
+    /// Draw text with the built-in font
+    fn draw_text(&mut self, x: usize, y: usize, text: &str) -> Result<(), Error> {
+        let glyphs = ocr::render(text).map_err(Error::UnknownCharacter)?;
+        self.check_bounds(x, y, glyphs.first().map(|row| row.len()).unwrap_or(0), glyphs.len())?;
+        for (dy, row) in glyphs.iter().enumerate() {
+            for (dx, _) in row.iter().enumerate().filter(|&(_, &lit)| lit) {
+                self.set(x + dx, y + dy, true);
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute an operation
+    pub fn execute(&mut self, op: &Operation) -> Result<(), Error> {
+        match *op {
+            Operation::Rect { width: w, height: h } => self.fill(0, 0, w, h, |_| true),
+            Operation::RectAt { x, y, width: w, height: h } => self.fill(x, y, w, h, |_| true),
+            Operation::Clear { x, y, width: w, height: h } => self.fill(x, y, w, h, |_| false),
+            Operation::Invert { x, y, width: w, height: h } => self.fill(x, y, w, h, |px| !px),
+            Operation::RotateRow { row: y, count: c } => self.rotate_row(y, c),
+            Operation::RotateColumn { column: x, count: c } => self.rotate_column(x, c),
+            Operation::FlipHorizontal => {
+                self.flip_horizontal();
+                Ok(())
+            },
+            Operation::FlipVertical => {
+                self.flip_vertical();
+                Ok(())
+            },
+            Operation::Text { x, y, ref text } => self.draw_text(x, y, text),
+        }
+    }
+
+    /// Synthesize operations that draw the pixels of this display on a
+    /// blank display. Every row is drawn in horizontal runs, rightmost run
+    /// first. A round draws runs of the same width at the left edge of the
+    /// display (one rect per run of rows) and rotates every row to the next
+    /// pixels it has to draw, so the left edge is blank again for the next
+    /// round. A row that is done is rotated to its place, unless it would be
+    /// in the way of wider runs that are left (then it's rotated to its place
+    /// at the end). Every round uses the width that draws most pixels per
+    /// operation. The first column is drawn last. The operations are proven
+    /// by running them, None is returned if they don't draw this display.
+    #[allow(dead_code)]
+    pub fn synthesize(&self) -> Option<Vec<Operation>> {
+        self.synthesize_runs(self.width)
+    }
+
+    /// Synthesize operations like `synthesize`, drawing runs of at most the
+    /// given width
+    fn synthesize_runs(&self, max_width: usize) -> Option<Vec<Operation>> {
+        let (width, height) = (self.width, self.height);
+        // Pixels drawn in rounds (all lit pixels except the first column)
+        let target: Vec<Vec<bool>> = (0..height).map(|y| (0..width).map(|x| x > 0 && self.get(x, y)).collect()).collect();
+        let mut drawn = vec![vec![false; width]; height];
+        // Column of every row that is currently at the left edge
+        let mut shifts: Vec<usize> = (0..height).map(|y| next_run(&target[y], &drawn[y]).unwrap_or(0)).collect();
+        let mut ops = vec![];
+        loop {
+            // Undrawn lit pixels and blank pixels of every row at the left edge
+            let columns = |y: usize| { let shift = shifts[y]; (0..width).map(move |i| (shift + i) % width) };
+            let todo: Vec<usize> = (0..height).map(|y| columns(y).take_while(|&x| target[y][x] && !drawn[y][x]).count()).collect();
+            let blank: Vec<usize> = (0..height).map(|y| columns(y).take_while(|&x| !drawn[y][x]).count()).collect();
+            let mut best: Option<(usize, Vec<bool>, Vec<Operation>)> = None;
+            for w in (1..cmp::min(todo.iter().cloned().max().unwrap_or(0), max_width) + 1).take_while(|&w| blank.iter().all(|&b| b >= w)) {
+                let rows: Vec<bool> = todo.iter().map(|&n| n >= w).collect();
+                let round = draw_left_columns(&rows, w);
+                let count = rows.iter().filter(|&&px| px).count();
+                // Prefer more pixels per operation (and wider runs if equal)
+                let better = match best {
+                    Some((bw, ref brows, ref bround)) => {
+                        let bcount = brows.iter().filter(|&&px| px).count();
+                        w * count * (bround.len() + bcount) >= bw * bcount * (round.len() + count)
+                    },
+                    None => true,
+                };
+                if better {
+                    best = Some((w, rows, round));
+                }
+            }
+            let (w, rows, round) = match best {
+                Some(best) => best,
+                None => break,
+            };
+            ops.extend(round);
+            for y in (0..height).filter(|&y| rows[y]) {
+                for i in 0..w {
+                    drawn[y][(shifts[y] + i) % width] = true;
+                }
+            }
+            // Widest run that is left to draw
+            let widest = (0..height).flat_map(|y| (0..width).map(move |x| (y, x)))
+                .map(|(y, x)| (x..width).take_while(|&x| target[y][x] && !drawn[y][x]).count()).max().map_or(0, |n| cmp::min(n, max_width));
+            for y in (0..height).filter(|&y| rows[y]) {
+                let next = next_run(&target[y], &drawn[y]).unwrap_or_else(|| out_of_the_way(&drawn[y], widest));
+                ops.push(Operation::RotateRow { row: y, count: ((shifts[y] + width - next) % width) as isize });
+                shifts[y] = next;
+            }
+        }
+        for (y, &shift) in shifts.iter().enumerate().filter(|&(_, &shift)| shift > 0) {
+            ops.push(Operation::RotateRow { row: y, count: shift as isize });
+        }
+        let rows: Vec<bool> = (0..height).map(|y| width > 0 && self.get(0, y)).collect();
+        ops.extend(draw_left_columns(&rows, 1));
+        let mut display = Display::new(width, height);
+        match display.run(&ops) {
+            Ok(()) if display == *self => Some(ops),
+            _ => None,
+        }
+    }
+
+    /// Execute a list of operations. Stops at the first operation that fails.
+    pub fn run(&mut self, ops: &[Operation]) -> Result<(), Error> {
+        for op in ops {
+            self.execute(op)?;
+            if self.recorder.is_some() {
+                let pixels = self.pixels();
+                self.recorder.as_mut().unwrap().record(&pixels)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Mask of the lowest n bits of a word
+fn mask(n: usize) -> u64 {
+    if n >= WORD_BITS { !0 } else { (1 << n) - 1 }
+}
+
+/// Read a word of bits from packed pixels, starting at the given bit. Bits
+/// after the given width wrap around to the start.
+fn read_bits(words: &[u64], width: usize, start: usize) -> u64 {
+    let (mut value, mut filled, mut pos) = (0, 0, start);
+    while filled < WORD_BITS {
+        let n = cmp::min(WORD_BITS - filled, width - pos);
+        let (i, bit) = (pos / WORD_BITS, pos % WORD_BITS);
+        let mut bits = words[i] >> bit;
+        if bit > 0 && i + 1 < words.len() {
+            bits |= words[i + 1] << (WORD_BITS - bit);
+        }
+        value |= (bits & mask(n)) << filled;
+        filled += n;
+        pos = (pos + n) % width;
+    }
+    value
+}
+
+
+/// Rotation count within a length of the given size (0 if the size is 0)
+fn wrap(count: isize, size: usize) -> usize {
+    if size == 0 {
+        0
+    } else {
+        (count % size as isize + size as isize) as usize % size
+    }
+}
+
+
+/// Start of the next pixels to draw in a row: the leftmost undrawn pixel of
+/// the rightmost run that isn't drawn completely
+fn next_run(target: &[bool], drawn: &[bool]) -> Option<usize> {
+    let todo = |x: usize| target[x] && !drawn[x];
+    let end = (0..target.len()).rev().find(|&x| todo(x))?;
+    Some((0..end + 1).rev().take_while(|&x| todo(x)).last().unwrap())
+}
+
+/// Column of a row that is done to keep at the left edge, so that runs of
+/// the given width can be drawn: the first column if the gap (cyclic run of
+/// undrawn pixels) starting there is wide enough, else the widest gap.
+fn out_of_the_way(drawn: &[bool], width: usize) -> usize {
+    let gap = |x: usize| (0..drawn.len()).take_while(|&i| !drawn[(x + i) % drawn.len()]).count();
+    if gap(0) >= width {
+        0
+    } else {
+        (1..drawn.len()).fold(0, |best, x| if gap(x) > gap(best) { x } else { best })
+    }
+}
+
+/// Operations that draw the given lit rows in the given number of columns at
+/// the left edge of a display, which have to be blank. Every run of lit rows
+/// is drawn by a rect at the top, bottom run first, and moved down to its
+/// place by rotating the columns before the run above is drawn.
+fn draw_left_columns(rows: &[bool], width: usize) -> Vec<Operation> {
+    let mut runs = vec![];
+    let mut y = 0;
+    while y < rows.len() {
+        let len = rows[y..].iter().take_while(|&&px| px).count();
+        if len > 0 {
+            runs.push((y, len));
+        }
+        y += cmp::max(len, 1);
+    }
+    let mut ops = vec![];
+    for (i, &(start, len)) in runs.iter().enumerate().rev() {
+        ops.push(Operation::Rect { width: width, height: len });
+        let prev = if i > 0 { runs[i - 1].0 } else { 0 };
+        if start > prev {
+            ops.extend((0..width).map(|x| Operation::RotateColumn { column: x, count: (start - prev) as isize }));
+        }
+    }
+    ops
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+    use image::Format;
+
+    #[test]
+    fn parsing() {
+        assert_eq!("rect 12x34".parse(), Ok(Operation::Rect { width: 12, height: 34 }));
+        assert_eq!("rotate row y=23 by 45".parse(), Ok(Operation::RotateRow { row: 23, count: 45 }));
+        assert_eq!("rotate column x=34 by 56".parse(), Ok(Operation::RotateColumn { column: 34, count: 56 }));
+    }
+
+    #[test]
+    fn parsing_invalid() {
+        let err = "rect 1y2".parse::<Operation>().unwrap_err();
+        assert_eq!(err.to_string(), "invalid input at line 1, column 7:\nrect 1y2\n      ^");
+        assert_eq!(Operation::parse("rect 1x1\nrotate row x=1 by 2").unwrap_err(), ParseError { line: 2, column: 12, text: "rotate row x=1 by 2".to_owned() });
+    }
+
+    #[test]
+    fn display_operations() {
+        let mut display = Display::new(7, 3);
+        assert_eq!(format!("{}", display), ".......\n.......\n.......\n");
+        assert_eq!(display.count_lit(), 0);
+        display.execute(&Operation::Rect { width: 3, height: 2 }).unwrap();
+        assert_eq!(format!("{}", display), "###....\n###....\n.......\n");
+        assert_eq!(display.count_lit(), 6);
+        display.execute(&Operation::RotateColumn { column: 1, count: 1 }).unwrap();
+        assert_eq!(format!("{}", display), "#.#....\n###....\n.#.....\n");
+        assert_eq!(display.count_lit(), 6);
+        display.execute(&Operation::RotateRow { row: 0, count: 4 }).unwrap();
+        assert_eq!(format!("{}", display), "....#.#\n###....\n.#.....\n");
+        assert_eq!(display.count_lit(), 6);
+        display.execute(&Operation::RotateColumn { column: 1, count: 1 }).unwrap();
+        assert_eq!(format!("{}", display), ".#..#.#\n#.#....\n.#.....\n");
+        assert_eq!(display.count_lit(), 6);
+    }
+
+    #[test]
+    fn parsing_extended() {
+        assert_eq!("rect 3x2 at 4,1".parse(), Ok(Operation::RectAt { x: 4, y: 1, width: 3, height: 2 }));
+        assert_eq!("clear 3x2".parse(), Ok(Operation::Clear { x: 0, y: 0, width: 3, height: 2 }));
+        assert_eq!("invert 1x1 at 5,5".parse(), Ok(Operation::Invert { x: 5, y: 5, width: 1, height: 1 }));
+        assert_eq!("rotate row y=1 by -3".parse(), Ok(Operation::RotateRow { row: 1, count: -3 }));
+        assert_eq!("rotate row y=1 left by 3".parse(), Ok(Operation::RotateRow { row: 1, count: -3 }));
+        assert_eq!("rotate row y=1 right by 3".parse(), Ok(Operation::RotateRow { row: 1, count: 3 }));
+        assert_eq!("rotate column x=2 up by 1".parse(), Ok(Operation::RotateColumn { column: 2, count: -1 }));
+        assert_eq!("rotate column x=2 down by -1".parse(), Ok(Operation::RotateColumn { column: 2, count: -1 }));
+        assert_eq!("flip horizontal".parse(), Ok(Operation::FlipHorizontal));
+        assert_eq!("flip vertical".parse(), Ok(Operation::FlipVertical));
+        assert_eq!("text \"HI\" at 5,0".parse(), Ok(Operation::Text { x: 5, y: 0, text: "HI".to_owned() }));
+        assert_eq!("text \"HI\"".parse(), Ok(Operation::Text { x: 0, y: 0, text: "HI".to_owned() }));
+        assert_eq!("text \"Hx\"".parse::<Operation>().unwrap_err().column, 8);
+        assert_eq!("rect 3x2 at 4".parse::<Operation>().unwrap_err().column, 9);
+        for line in &["rect 3x2 at 4,1", "clear 3x2 at 0,0", "rotate row y=1 by -3", "flip vertical", "text \"HI\" at 5,0"] {
+            assert_eq!(line.parse::<Operation>().unwrap().to_string(), *line);
+        }
+    }
+
+    #[test]
+    fn display_operations_extended() {
+        let mut display = Display::new(7, 3);
+        display.run(&Operation::parse("rect 3x2 at 1,1\ninvert 2x3 at 2,0\nclear 1x1 at 1,1").unwrap()).unwrap();
+        assert_eq!(format!("{}", display), "..##...\n.......\n.#.....\n");
+        display.run(&Operation::parse("rotate row y=0 left by 2\nrotate column x=1 up by 4").unwrap()).unwrap();
+        assert_eq!(format!("{}", display), "#......\n.#.....\n.#.....\n");
+        display.run(&Operation::parse("rotate row y=1 by 11\nflip horizontal\nflip vertical").unwrap()).unwrap();
+        assert_eq!(format!("{}", display), ".....#.\n.#.....\n......#\n");
+        let mut display = Display::new(10, 6);
+        display.execute(&"text \"LO\"".parse().unwrap()).unwrap();
+        assert_eq!(display.text(), Ok("LO".to_owned()));
+    }
+
+    #[test]
+    fn display_errors() {
+        let mut display = Display::new(7, 3);
+        assert_eq!(display.execute(&Operation::Rect { width: 8, height: 1 }).unwrap_err().to_string(), "rectangle 8x1 at 0,0 exceeds the display");
+        assert_eq!(display.execute(&Operation::Clear { x: 5, y: 2, width: 3, height: 1 }).unwrap_err().to_string(), "rectangle 3x1 at 5,2 exceeds the display");
+        assert_eq!(display.execute(&Operation::RectAt { x: usize::max_value(), y: 0, width: 2, height: 1 }).unwrap_err().to_string(),
+            format!("rectangle 2x1 at {},0 exceeds the display", usize::max_value()));
+        assert_eq!(display.execute(&Operation::RotateRow { row: 3, count: 1 }).unwrap_err().to_string(), "no row y=3");
+        assert_eq!(display.execute(&Operation::RotateColumn { column: 7, count: 1 }).unwrap_err().to_string(), "no column x=7");
+        assert_eq!(display.execute(&Operation::Text { x: 0, y: 0, text: "L".to_owned() }).unwrap_err().to_string(), "rectangle 5x6 at 0,0 exceeds the display");
+        assert_eq!(display.execute(&Operation::Text { x: 0, y: 0, text: "?".to_owned() }).unwrap_err().to_string(), "no glyph for character '?'");
+        assert_eq!(display.count_lit(), 0);
+    }
+
+    #[test]
+    fn large_display() {
+        // Compare with rows of unpacked pixels
+        let (width, height) = (150, 70);
+        let mut display = Display::new(width, height);
+        let mut pixels = vec![vec![false; width]; height];
+        let mut seed = 1u32;
+        let mut random = |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as usize % n
+        };
+        for _ in 0..2000 {
+            let (x, y) = (random(width), random(height));
+            let (w, h) = (random(width - x) + 1, random(height - y) + 1);
+            let count = random(2 * width) as isize - width as isize;
+            let op = match random(6) {
+                0 => Operation::RectAt { x: x, y: y, width: w, height: h },
+                1 => Operation::Clear { x: x, y: y, width: w, height: h },
+                2 => Operation::Invert { x: x, y: y, width: w, height: h },
+                3 => Operation::RotateRow { row: y, count: count },
+                4 => Operation::RotateColumn { column: x, count: count },
+                _ => Operation::FlipHorizontal,
+            };
+            display.execute(&op).unwrap();
+            match op {
+                Operation::RectAt { .. } | Operation::Clear { .. } | Operation::Invert { .. } => {
+                    for row in &mut pixels[y..y + h] {
+                        for px in &mut row[x..x + w] {
+                            *px = match op { Operation::RectAt { .. } => true, Operation::Clear { .. } => false, _ => !*px };
+                        }
+                    }
+                },
+                Operation::RotateRow { .. } => {
+                    let ofs = width - wrap(count, width);
+                    pixels[y] = pixels[y][ofs..].iter().chain(pixels[y][..ofs].iter()).cloned().collect();
+                },
+                Operation::RotateColumn { .. } => {
+                    let ofs = height - wrap(count, height);
+                    let column: Vec<bool> = pixels.iter().map(|row| row[x]).collect();
+                    for (row, &px) in pixels.iter_mut().zip(column[ofs..].iter().chain(column[..ofs].iter())) {
+                        row[x] = px;
+                    }
+                },
+                _ => for row in &mut pixels { row.reverse() },
+            }
+        }
+        assert_eq!(display.pixels(), pixels);
+        assert_eq!(display.count_lit(), pixels.iter().map(|row| row.iter().filter(|&&px| px).count()).sum::<usize>());
+        assert_eq!(display, Display::from_pixels(&pixels));
+    }
+
+    #[test]
+    fn recording() {
+        let dir = env::temp_dir().join(format!("aoc2016-day08-{}", ::std::process::id()));
+        let mut display = Display::new(7, 3);
+        display.start_recording(Recorder::new(&dir, Format::Pbm).unwrap());
+        display.run(&Operation::parse("rect 3x2\nrotate column x=1 by 1\nrotate row y=0 by 4").unwrap()).unwrap();
+        let recorder = display.stop_recording().unwrap();
+        assert_eq!(recorder.frames(), 3);
+        assert_eq!(fs::read_to_string(recorder.path(2)).unwrap(), "P1\n7 3\n1010000\n1110000\n0100000\n");
+        display.run(&[Operation::Rect { width: 1, height: 1 }]).unwrap();
+        assert!(!recorder.path(4).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drawing_left_columns() {
+        let mut display = Display::new(2, 6);
+        let ops = draw_left_columns(&[true, false, true, true, false, true], 1);
+        assert_eq!(ops, vec![
+            Operation::Rect { width: 1, height: 1 },
+            Operation::RotateColumn { column: 0, count: 3 },
+            Operation::Rect { width: 1, height: 2 },
+            Operation::RotateColumn { column: 0, count: 2 },
+            Operation::Rect { width: 1, height: 1 },
+        ]);
+        display.run(&ops).unwrap();
+        assert_eq!(format!("{}", display), "#.\n..\n#.\n#.\n..\n#.\n");
+        let mut display = Display::new(3, 3);
+        display.run(&draw_left_columns(&[false, true, true], 2)).unwrap();
+        assert_eq!(format!("{}", display), "...\n##.\n##.\n");
+    }
+
+    #[test]
+    fn synthesizing() {
+        let mut display = Display::new(7, 3);
+        display.run(&Operation::parse("rect 3x2\nrotate column x=1 by 1\nrotate row y=0 by 4\nrotate column x=1 by 1").unwrap()).unwrap();
+        let ops = display.synthesize().unwrap();
+        let mut copy = Display::new(7, 3);
+        copy.run(&ops).unwrap();
+        assert_eq!(copy, display);
+        assert!(Display::new(4, 2).synthesize().unwrap().is_empty());
+        // Pseudo-random patterns, sparse and dense ones
+        let mut seed = 1u32;
+        for n in 0..50 {
+            let mut display = Display::new(9, 5);
+            for y in 0..5 {
+                for x in 0..9 {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    display.set(x, y, (seed >> 30 == 0) != (n % 2 == 1));
+                }
+            }
+            assert!(display.synthesize().is_some());
+        }
+    }
+
+    #[test]
+    fn synthesizing_runs() {
+        let mut display = Display::new(9, 3);
+        display.run(&Operation::parse("rect 7x1\nrotate row y=0 by 1").unwrap()).unwrap();
+        assert_eq!(display.synthesize().unwrap(), Operation::parse("rect 7x1\nrotate row y=0 by 1").unwrap());
+        // Text takes fewer operations than drawing one pixel per row and round
+        let display = Display::from_pixels(&ocr::render("HELLO").unwrap());
+        assert_eq!(display.synthesize_runs(1).map(|ops| ops.len()), Some(88));
+        assert_eq!(display.synthesize().map(|ops| ops.len()), Some(76));
+    }
+}
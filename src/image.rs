@@ -0,0 +1,218 @@
+//! Export of display pixels as images. Lit pixels are drawn black on white,
+//! as a netpbm bitmap (PBM), a netpbm graymap (PGM) or a scalable vector
+//! graphic (SVG). A recorder saves a numbered sequence of frames, which can
+//! be turned into a video, e.g. `ffmpeg -i frame-%04d.pgm video.mp4`.
+
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+
+/// Size of a pixel in SVG images
+const SVG_SCALE: usize = 10;
+
+/// Maximum line length of plain netpbm images
+const PBM_LINE_LENGTH: usize = 70;
+
+
+/// Image file format
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    /// Plain netpbm bitmap
+    Pbm,
+    /// Binary netpbm graymap
+    Pgm,
+    /// Scalable vector graphic
+    Svg,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "pbm" => Ok(Format::Pbm),
+            "pgm" => Ok(Format::Pgm),
+            "svg" => Ok(Format::Svg),
+            _ => Err(format!("unknown image format: {}", s)),
+        }
+    }
+}
+
+impl Format {
+    /// Format of an image file, given by its extension
+    pub fn of(path: &Path) -> Option<Format> {
+        path.extension().and_then(|ext| ext.to_str()).and_then(|ext| ext.parse().ok())
+    }
+
+    /// File name extension
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Format::Pbm => "pbm",
+            Format::Pgm => "pgm",
+            Format::Svg => "svg",
+        }
+    }
+
+    /// Write pixels as an image of this format
+    pub fn write<W: Write>(&self, pixels: &[Vec<bool>], out: &mut W) -> io::Result<()> {
+        match *self {
+            Format::Pbm => write_pbm(pixels, out),
+            Format::Pgm => write_pgm(pixels, out),
+            Format::Svg => write_svg(pixels, out),
+        }
+    }
+
+    /// Save pixels as an image file of this format
+    pub fn save<P: AsRef<Path>>(&self, pixels: &[Vec<bool>], path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(pixels, &mut out)?;
+        out.flush()
+    }
+}
+
+
+/// Save pixels as an image file of the format given by its extension
+pub fn save<P: AsRef<Path>>(pixels: &[Vec<bool>], path: P) -> io::Result<()> {
+    match Format::of(path.as_ref()) {
+        Some(format) => format.save(pixels, path),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown image format: {}", path.as_ref().display()))),
+    }
+}
+
+
+/// Width of the given pixels
+fn width(pixels: &[Vec<bool>]) -> usize {
+    pixels.first().map(|row| row.len()).unwrap_or(0)
+}
+
+/// Write pixels as plain netpbm bitmap. Rows wider than the maximum line
+/// length of the format are wrapped.
+pub fn write_pbm<W: Write>(pixels: &[Vec<bool>], out: &mut W) -> io::Result<()> {
+    writeln!(out, "P1\n{} {}", width(pixels), pixels.len())?;
+    for row in pixels {
+        for chunk in row.chunks(PBM_LINE_LENGTH) {
+            let line: String = chunk.iter().map(|&px| if px { '1' } else { '0' }).collect();
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write pixels as binary netpbm graymap
+pub fn write_pgm<W: Write>(pixels: &[Vec<bool>], out: &mut W) -> io::Result<()> {
+    writeln!(out, "P5\n{} {}\n255", width(pixels), pixels.len())?;
+    for row in pixels {
+        let bytes: Vec<u8> = row.iter().map(|&px| if px { 0 } else { 255 }).collect();
+        out.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+/// Write pixels as scalable vector graphic
+pub fn write_svg<W: Write>(pixels: &[Vec<bool>], out: &mut W) -> io::Result<()> {
+    let (width, height) = (width(pixels), pixels.len());
+    writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" width=\"{}\" height=\"{}\">",
+        width, height, width * SVG_SCALE, height * SVG_SCALE)?;
+    writeln!(out, "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>", width, height)?;
+    for (y, row) in pixels.iter().enumerate() {
+        for (x, _) in row.iter().enumerate().filter(|&(_, &px)| px) {
+            writeln!(out, "<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\" fill=\"black\"/>", x, y)?;
+        }
+    }
+    writeln!(out, "</svg>")
+}
+
+
+/// Recorder of frames. Every frame is saved as a numbered image file
+/// (`frame-0001`, `frame-0002` and so on) in a directory.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Recorder {
+    dir: PathBuf,
+    format: Format,
+    frames: usize,
+}
+
+impl Recorder {
+    /// Create new recorder that saves frames of the given format in the
+    /// given directory (which is created if needed)
+    pub fn new<P: AsRef<Path>>(dir: P, format: Format) -> io::Result<Recorder> {
+        ::std::fs::create_dir_all(&dir)?;
+        Ok(Recorder { dir: dir.as_ref().to_owned(), format: format, frames: 0 })
+    }
+
+    /// Number of recorded frames
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Path of the frame file with the given number
+    pub fn path(&self, frame: usize) -> PathBuf {
+        self.dir.join(format!("frame-{:04}.{}", frame, self.format.extension()))
+    }
+
+    /// Save pixels as next frame
+    pub fn record(&mut self, pixels: &[Vec<bool>]) -> io::Result<()> {
+        let path = self.path(self.frames + 1);
+        self.format.save(pixels, path)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn pixels() -> Vec<Vec<bool>> {
+        vec![vec![true, false, true], vec![false, true, false]]
+    }
+
+    #[test]
+    fn writing() {
+        let mut out = Vec::new();
+        write_pbm(&pixels(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "P1\n3 2\n101\n010\n");
+        let mut out = Vec::new();
+        write_pbm(&[(0..150).map(|x| x % 3 == 0).collect()], &mut out).unwrap();
+        let pbm = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = pbm.lines().collect();
+        assert_eq!(lines.iter().map(|line| line.len()).collect::<Vec<_>>(), vec![2, 5, 70, 70, 10]);
+        assert_eq!(lines[2..].concat(), "100".repeat(50));
+        let mut out = Vec::new();
+        write_pgm(&pixels(), &mut out).unwrap();
+        assert_eq!(out, b"P5\n3 2\n255\n\x00\xff\x00\xff\x00\xff");
+        let mut out = Vec::new();
+        write_svg(&pixels(), &mut out).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 3 2\" width=\"30\" height=\"20\">\n"));
+        assert_eq!(svg.matches("fill=\"black\"").count(), 3);
+        assert!(svg.contains("<rect x=\"1\" y=\"1\" width=\"1\" height=\"1\" fill=\"black\"/>\n"));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn choosing_format() {
+        assert_eq!("svg".parse(), Ok(Format::Svg));
+        assert!("png".parse::<Format>().is_err());
+        assert_eq!(Format::of(Path::new("display.pgm")), Some(Format::Pgm));
+        assert_eq!(Format::of(Path::new("display")), None);
+    }
+
+    #[test]
+    fn recording() {
+        let dir = env::temp_dir().join(format!("aoc2016-frames-{}", ::std::process::id()));
+        let mut recorder = Recorder::new(&dir, Format::Pbm).unwrap();
+        recorder.record(&pixels()).unwrap();
+        recorder.record(&pixels()[..1]).unwrap();
+        assert_eq!(recorder.frames(), 2);
+        assert_eq!(fs::read_to_string(dir.join("frame-0001.pbm")).unwrap(), "P1\n3 2\n101\n010\n");
+        assert_eq!(fs::read_to_string(recorder.path(2)).unwrap(), "P1\n3 1\n101\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
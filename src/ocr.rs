@@ -0,0 +1,157 @@
+//! Optical character recognition for displays of 6 pixel rows. The display
+//! is split into letter cells of 5 columns (4 columns of the letter and a
+//! blank one), and every cell is looked up in a font table of the capitals
+//! known from puzzle answers (plus the glyphs of the bonus challenge). The
+//! same font can be used to render text.
+
+
+use std::fmt;
+
+
+/// Height of a letter in pixels
+const HEIGHT: usize = 6;
+
+/// Width of a letter cell in pixels
+const WIDTH: usize = 5;
+
+/// Known glyphs, by rows of their letter cell
+const FONT: &'static [(char, [&'static str; HEIGHT])] = &[
+    ('A', [".##..", "#..#.", "#..#.", "####.", "#..#.", "#..#."]),
+    ('B', ["###..", "#..#.", "###..", "#..#.", "#..#.", "###.."]),
+    ('C', [".##..", "#..#.", "#....", "#....", "#..#.", ".##.."]),
+    ('E', ["####.", "#....", "###..", "#....", "#....", "####."]),
+    ('F', ["####.", "#....", "###..", "#....", "#....", "#...."]),
+    ('G', [".##..", "#..#.", "#....", "#.##.", "#..#.", ".###."]),
+    ('H', ["#..#.", "#..#.", "####.", "#..#.", "#..#.", "#..#."]),
+    ('I', [".###.", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('J', ["..##.", "...#.", "...#.", "...#.", "#..#.", ".##.."]),
+    ('K', ["#..#.", "#.#..", "##...", "#.#..", "#.#..", "#..#."]),
+    ('L', ["#....", "#....", "#....", "#....", "#....", "####."]),
+    ('O', [".##..", "#..#.", "#..#.", "#..#.", "#..#.", ".##.."]),
+    ('P', ["###..", "#..#.", "#..#.", "###..", "#....", "#...."]),
+    ('R', ["###..", "#..#.", "#..#.", "###..", "#.#..", "#..#."]),
+    ('S', [".###.", "#....", "#....", ".##..", "...#.", "###.."]),
+    ('U', ["#..#.", "#..#.", "#..#.", "#..#.", "#..#.", ".##.."]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#.."]),
+    ('Z', ["####.", "...#.", "..#..", ".#...", "#....", "####."]),
+    ('o', [".....", ".....", ".##..", "#..#.", "#..#.", ".##.."]),
+    ('0', ["..#..", ".#.#.", ".#.#.", ".#.#.", ".#.#.", "..#.."]),
+    ('1', ["..#..", ".##..", "..#..", "..#..", "..#..", ".###."]),
+    ('2', [".##..", "#..#.", "...#.", "..#..", ".#...", "####."]),
+    ('7', ["####.", "...#.", "..#..", "..#..", ".#...", ".#..."]),
+    (' ', [".....", ".....", ".....", ".....", ".....", "....."]),
+];
+
+
+/// Error of recognizing text
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The display doesn't have the height of letters
+    Height(usize),
+    /// The display width isn't a multiple of the letter cell width
+    Width(usize),
+    /// The letter cell with the given number (starting at 0) doesn't match
+    /// any known glyph. The glyph is given by its rows.
+    UnknownGlyph(usize, Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Height(height) => write!(f, "display height {} is not the letter height {}", height, HEIGHT),
+            Error::Width(width) => write!(f, "display width {} is not a multiple of the letter width {}", width, WIDTH),
+            Error::UnknownGlyph(cell, ref rows) => {
+                write!(f, "unknown glyph at columns {}-{}:", cell * WIDTH + 1, cell * WIDTH + WIDTH)?;
+                for row in rows {
+                    write!(f, "\n{}", row)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+
+/// Recognize the text shown by rows of pixels. Blank letter cells are
+/// recognized as spaces.
+pub fn recognize(pixels: &[Vec<bool>]) -> Result<String, Error> {
+    if pixels.len() != HEIGHT {
+        return Err(Error::Height(pixels.len()));
+    }
+    let width = pixels[0].len();
+    if width % WIDTH != 0 {
+        return Err(Error::Width(width));
+    }
+    (0..width / WIDTH).map(|cell| {
+        let rows: Vec<String> = pixels.iter().map(|row|
+            row[cell * WIDTH..(cell + 1) * WIDTH].iter().map(|&px| if px { '#' } else { '.' }).collect()
+        ).collect();
+        FONT.iter()
+            .find(|&&(_, ref glyph)| glyph.iter().zip(rows.iter()).all(|(g, row)| g == row))
+            .map(|&(ch, _)| ch)
+            .ok_or(Error::UnknownGlyph(cell, rows))
+    }).collect()
+}
+
+
+/// Characters that have a glyph in the font
+pub fn characters() -> String {
+    FONT.iter().map(|&(ch, _)| ch).collect()
+}
+
+/// Render text with the font. Returns the first character that has no
+/// glyph as error.
+pub fn render(text: &str) -> Result<Vec<Vec<bool>>, char> {
+    let glyphs = text.chars().map(|ch|
+        FONT.iter().find(|&&(c, _)| c == ch).map(|&(_, ref glyph)| glyph).ok_or(ch)
+    ).collect::<Result<Vec<_>, char>>()?;
+    Ok((0..HEIGHT).map(|y|
+        glyphs.iter().flat_map(|glyph| glyph[y].chars().map(|px| px == '#')).collect()
+    ).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(s: &str) -> Vec<Vec<bool>> {
+        s.lines().map(|line| line.chars().map(|ch| ch == '#').collect()).collect()
+    }
+
+    #[test]
+    fn recognizing() {
+        let display = pixels("\
+.##..####.#....
+#..#.#....#....
+#....###..#....
+#....#....#....
+#..#.#....#....
+.##..#....####.");
+        assert_eq!(recognize(&display), Ok("CFL".to_owned()));
+    }
+
+    #[test]
+    fn rendering() {
+        let display = render("AoC 2017").unwrap();
+        assert_eq!(display.len(), 6);
+        assert_eq!(display[0].len(), 40);
+        assert_eq!(recognize(&display), Ok("AoC 2017".to_owned()));
+        assert_eq!(render("Ax"), Err('x'));
+    }
+
+    #[test]
+    fn recognizing_unknown() {
+        let display = pixels("\
+.##..#...#
+#..#.##..#
+#....#.#.#
+#....#..##
+#..#.#...#
+.##..#...#");
+        let err = recognize(&display).unwrap_err();
+        assert_eq!(err.to_string(), "unknown glyph at columns 6-10:\n#...#\n##..#\n#.#.#\n#..##\n#...#\n#...#");
+        assert_eq!(recognize(&display[..5]), Err(Error::Height(5)));
+        assert_eq!(recognize(&pixels("#\n#\n#\n#\n#\n#")), Err(Error::Width(1)));
+    }
+}
@@ -0,0 +1,139 @@
+//! Errors of parsing puzzle input. An error tells the line and column where
+//! the input is invalid, and is shown with the text of the line and a caret
+//! under the bad spot. Parsers built with nom find the column by parsing
+//! prefixes of the line: the bad spot is at the end of the longest prefix
+//! that the parser accepts or needs more input for.
+
+use std::fmt;
+
+
+/// Error in a line of input. Line and column numbers start at 1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    /// Text of the line
+    pub text: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invalid input at line {}, column {}:", self.line, self.column)?;
+        writeln!(f, "{}", self.text)?;
+        write!(f, "{:>1$}", "^", self.column)
+    }
+}
+
+impl ParseError {
+    /// Error at the given byte offset of a single line of text
+    pub fn at(text: &str, offset: usize) -> ParseError {
+        ParseError { line: 1, column: text[..offset].chars().count() + 1, text: text.to_owned() }
+    }
+
+    /// Error at the start of a part of a single line of text. The part
+    /// must be a slice of the text.
+    #[allow(dead_code)]
+    pub fn of(text: &str, part: &str) -> ParseError {
+        ParseError::at(text, offset(text, part))
+    }
+
+    /// Same error in the given line of a multiline-text
+    pub fn in_line(self, line: usize) -> ParseError {
+        ParseError { line: line, ..self }
+    }
+
+    /// Same error in a line of text that the erroneous part is a slice of
+    #[allow(dead_code)]
+    pub fn within(self, text: &str, part: &str) -> ParseError {
+        ParseError { column: text[..offset(text, part)].chars().count() + self.column, text: text.to_owned(), ..self }
+    }
+}
+
+
+/// Byte offset of a part of a text (a slice of it)
+#[allow(dead_code)]
+fn offset(text: &str, part: &str) -> usize {
+    part.as_ptr() as usize - text.as_ptr() as usize
+}
+
+
+/// Parse every line of a multiline-text
+#[allow(dead_code)]
+pub fn parse_lines<T, F>(s: &str, mut f: F) -> Result<Vec<T>, ParseError> where F: FnMut(&str) -> Result<T, ParseError> {
+    s.lines().enumerate().map(|(i, line)| f(line).map_err(|err| err.in_line(i + 1))).collect()
+}
+
+
+/// Result of parsing a line or a prefix of it
+#[allow(dead_code)]
+pub enum Progress<T> {
+    /// Parsed a value, leaving the given number of bytes unparsed
+    Done(T, usize),
+    /// Valid so far, but more input is needed
+    Incomplete,
+    /// Invalid input
+    Invalid,
+}
+
+/// Parse a line with a parser that has to consume all of it
+#[allow(dead_code)]
+pub fn parse_prefixes<T, F>(line: &str, parser: F) -> Result<T, ParseError> where F: Fn(&str) -> Progress<T> {
+    let offset = match parser(line) {
+        Progress::Done(value, 0) => return Ok(value),
+        Progress::Done(_, rest) => line.len() - rest,
+        Progress::Incomplete => line.len(),
+        Progress::Invalid => (0..line.len()).rev()
+            .filter(|&i| line.is_char_boundary(i))
+            .find(|&i| match parser(&line[..i]) { Progress::Invalid => false, _ => true })
+            .unwrap_or(0),
+    };
+    Err(ParseError::at(line, offset))
+}
+
+/// Parse a line with a nom parser that has to consume all of it. Like
+/// `complete!(line, parser).to_result()`, but with an error position.
+#[allow(unused_macros)]
+macro_rules! parse_line {
+    ($line:expr, $submac:ident!( $($args:tt)* )) => (
+        ::parse_error::parse_prefixes($line, |s: &str| match $submac!(s, $($args)*) {
+            ::nom::IResult::Done(rest, value) => ::parse_error::Progress::Done(value, rest.len()),
+            ::nom::IResult::Incomplete(_) => ::parse_error::Progress::Incomplete,
+            ::nom::IResult::Error(_) => ::parse_error::Progress::Invalid,
+        })
+    );
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn showing() {
+        let err = ParseError { line: 3, column: 5, text: "R2, X3".to_owned() };
+        assert_eq!(err.to_string(), "invalid input at line 3, column 5:\nR2, X3\n    ^");
+    }
+
+    #[test]
+    fn locating() {
+        let line = "R2, X3";
+        assert_eq!(ParseError::of(line, &line[4..]), ParseError { line: 1, column: 5, text: line.to_owned() });
+        assert_eq!(ParseError::at("X3", 0).within(line, &line[4..]), ParseError::of(line, &line[4..]));
+        let lines: Result<Vec<u32>, ParseError> = parse_lines("1\n2\nx", |line| line.parse().map_err(|_| ParseError::at(line, 0)));
+        assert_eq!(lines, Err(ParseError { line: 3, column: 1, text: "x".to_owned() }));
+    }
+
+    #[test]
+    fn parsing_prefixes() {
+        // Parser for "ab" repeated
+        let parser = |s: &str| match s.trim_left_matches("ab") {
+            "" => Progress::Done((), 0),
+            "a" => Progress::Incomplete,
+            _ => Progress::Invalid,
+        };
+        assert!(parse_prefixes("abab", parser).is_ok());
+        assert_eq!(parse_prefixes("ababb", parser).unwrap_err().column, 5);
+        assert_eq!(parse_prefixes("abx", parser).unwrap_err().column, 3);
+        assert_eq!(parse_prefixes("aba", parser).unwrap_err().column, 4);
+    }
+}